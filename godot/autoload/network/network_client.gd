@@ -18,24 +18,36 @@ var player: PlayerController:
 	
 var debug
 var remote_ids: Array[int]
+# each remote player's state SequenceRingBuffer, for peer_stats()
+var _buffers: Dictionary[int, SequenceRingBuffer]
 var _disconnected_message: String = ""
 
 func _ready() -> void:
-	NetworkTransport.on_client_packet.connect(on_client_packet)
+	NetworkTransport.on_client_id_assignment.connect(manage_ids)
+	NetworkTransport.on_client_player_state.connect(handle_player_state.emit)
+	NetworkTransport.on_client_chat.connect(handle_chat.emit)
+	NetworkTransport.on_client_player_disconnected.connect(on_player_disconnected)
 	NetworkTransport.on_disconnect_from_server.connect(on_disconnect_from_server)
 
 
-func on_client_packet(data) -> void:
-	if data is IdAssignmentPacket:
-		manage_ids(data)
-	elif data is PlayerStatePacket:
-		handle_player_state.emit(data)
-	elif data is ChatPacket:
-		handle_chat.emit(data)
-	elif data is PlayerDisconnectedPacket:
-		handle_player_disconnected.emit(data.player_id)
-	else:
-		push_error("Packet unknown type unhandled!")
+func on_player_disconnected(packet: PlayerDisconnectedPacket) -> void:
+	handle_player_disconnected.emit(packet.player_id)
+
+
+func track_buffer(player_id: int, buffer: SequenceRingBuffer) -> void:
+	_buffers[player_id] = buffer
+
+
+func untrack_buffer(player_id: int) -> void:
+	_buffers.erase(player_id)
+
+
+## State buffer diagnostics by player id, see SequenceRingBuffer.stats().
+func peer_stats() -> Dictionary:
+	var stats := {}
+	for player_id in _buffers:
+		stats[player_id] = _buffers[player_id].stats()
+	return stats
 
 
 func manage_ids(packet: IdAssignmentPacket) -> void:
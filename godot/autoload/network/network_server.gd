@@ -4,11 +4,14 @@ signal handle_player_input(peer_id: int, input: PlayerInputPacket)
 signal handle_chat(peer_id: int, chat: ChatPacket)
 
 var peer_ids: Array[int]
+# each peer's input JitterBuffer, for peer_stats()
+var _buffers: Dictionary[int, JitterBuffer]
 
 func _ready() -> void:
 	NetworkTransport.on_peer_connect.connect(on_peer_connected)
 	NetworkTransport.on_peer_disconnect.connect(on_peer_disconnected)
-	NetworkTransport.on_server_packet.connect(on_server_packet)
+	NetworkTransport.on_server_player_input.connect(handle_player_input.emit)
+	NetworkTransport.on_server_chat.connect(handle_chat.emit)
 	handle_chat.connect(on_chat)
 
 func on_peer_connected(peer_id: int) -> void:
@@ -26,13 +29,20 @@ func on_peer_disconnected(peer_id: int) -> void:
 	# Create IDUnassignment to broadcast to all still connected peers
 
 
-func on_server_packet(peer_id: int, packet) -> void:
-	if packet is PlayerInputPacket:
-		handle_player_input.emit(peer_id, packet)
-	elif packet is ChatPacket:
-		handle_chat.emit(peer_id, packet)
-	else:
-		push_error("Unknown packet type unhandled!")
+func track_buffer(peer_id: int, buffer: JitterBuffer) -> void:
+	_buffers[peer_id] = buffer
+
+
+func untrack_buffer(peer_id: int) -> void:
+	_buffers.erase(peer_id)
+
+
+## Input buffer diagnostics by peer id, see JitterBuffer.stats().
+func peer_stats() -> Dictionary:
+	var stats := {}
+	for peer_id in _buffers:
+		stats[peer_id] = _buffers[peer_id].stats()
+	return stats
 
 
 func on_chat(peer_id: int, packet: ChatPacket):
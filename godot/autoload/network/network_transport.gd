@@ -225,19 +225,11 @@ var is_dedicated_server: bool:
 # Called when the node enters the scene tree for the first time.
 func _ready() -> void:
 	if is_dedicated_server:
-		start_server_default()
-		set_fake_ping_lag_send(fake_ping_lag_send)
-		set_fake_ping_lag_recv(fake_ping_lag_recv)
-		set_fake_loss_send(fake_loss_send)
-		set_fake_loss_recv(fake_loss_recv)
-		set_fake_jitter_send(fake_jitter_send)
-		set_fake_jitter_recv(fake_jitter_recv)
-		set_fake_dup_send(fake_dup_send)
-		set_fake_dup_recv(fake_dup_recv)
-		set_fake_dup_ms_max(fake_dup_ms_max)
-		set_fake_reorder_send(fake_reorder_send)
-		set_fake_reorder_recv(fake_reorder_recv)
-		set_fake_reorder_ms(fake_reorder_ms)
+		# Reads --config=<path>, BR_SERVER_* env vars and --key=value overrides;
+		# link simulation settings come from the config instead of the exports.
+		if start_server_from_config("") != NetworkDriver.ERR_OK:
+			push_error("Failed to start the server from its config, see log for details")
+			get_tree().quit(1)
 
 
 func _notification(what):
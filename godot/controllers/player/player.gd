@@ -5,6 +5,8 @@ signal reconcile_network_debug(delta_pos: Vector3, delta_vel: Vector3, unacked_i
 
 const TILT_LOWER_LIMIT: float = deg_to_rad(-90.0)
 const TILT_UPPER_LIMIT: float = deg_to_rad(90.0)
+# frames the server keeps to replay when a late input replaces a made up one
+const SERVER_REWIND_FRAMES := 32
 
 
 @export_group("Movement Tunables")
@@ -87,11 +89,17 @@ var _unacked_inputs := SequenceRingBuffer.new()
 func _enter_tree() -> void:
 	NetworkServer.handle_player_input.connect(server_handle_player_input)
 	NetworkClient.handle_player_state.connect(client_handle_player_state)
+	if NetworkTransport.is_server:
+		NetworkServer.track_buffer(_owner_id, _server_input_queue)
+	elif not is_authority:
+		NetworkClient.track_buffer(_owner_id, _player_state_buffer)
 
 
 func _exit_tree() -> void:
 	NetworkServer.handle_player_input.disconnect(server_handle_player_input)
 	NetworkClient.handle_player_state.disconnect(client_handle_player_state)
+	NetworkServer.untrack_buffer(_owner_id)
+	NetworkClient.untrack_buffer(_owner_id)
 
 
 func _ready():
@@ -104,6 +112,9 @@ func _ready():
 	global_position = Constants.MAP_SPAWN
 	game_position = global_position
 	game_body.global_position = game_position
+	# keep the player moving on the server while an input is late, the real
+	# input is replayed in its place once it arrives
+	_server_input_queue.set_gap_fill(JitterBuffer.GAP_FILL_REPEAT_DECAY)
 	
 	if is_authority:
 		camera.make_current()
@@ -146,16 +157,39 @@ func _physics_process(delta: float) -> void:
 
 
 var _prev_server_input: PlayerInputPacket = null
+# newest input actually received and simulated; made up frames are never acked
+var _server_acked_sequence_id: int = -1
+# state before the oldest made up frame still waiting on its real input, and
+# every frame simulated since, to replay once real inputs replace made up ones
+var _server_rewind_state: PlayerStatePacket = null
+var _server_rewind_prev_input: PlayerInputPacket = null
+var _server_rewind_frames: Array[TimestampedPacket] = []
+# gains send_rate every tick, a state goes out whenever it reaches the tick rate
+var _server_send_credit := 0
 func _server_physics_step(delta: float) -> void:
+	var tick_rate := Engine.physics_ticks_per_second
+	_server_send_credit = mini(_server_send_credit + NetworkTransport.send_rate, tick_rate)
 	var frames := _server_input_queue.consume()
+	_server_reconcile_late_frames()
 	
 	if frames.is_empty():
 		#push_warning("No input frames to consume")
 		return
 
+	var previous_ack := _server_acked_sequence_id
 	for frame in frames:
 		assert(frame.packet is PlayerInputPacket, "Packet is not a PlayerInputPacket")
 		assert(frame.delta > 0.0, "Delta is not positive")
+		if not frame.synthetic:
+			_server_acked_sequence_id = frame.sequence_id
+		if frame.synthetic and _server_rewind_state == null:
+			_server_rewind_state = _server_player_state()
+			_server_rewind_prev_input = _prev_server_input
+		if _server_rewind_state != null:
+			_server_rewind_frames.append(frame)
+			# too far back to replay, the client corrects from the next state instead
+			if _server_rewind_frames.size() > SERVER_REWIND_FRAMES:
+				_server_clear_rewind()
 		input.prev_input_packet = _prev_server_input
 		_prev_server_input = frame.packet
 		input.input_packet = frame.packet
@@ -172,10 +206,23 @@ func _server_physics_step(delta: float) -> void:
 		global_position = game_position
 		velocity = game_velocity
 
+	# states are keyed by their ack, so a tick of only made up frames has
+	# nothing new to send; the next real input's state includes it
+	if _server_acked_sequence_id == previous_ack:
+		return
+	# held back to the configured send rate, the next state acks anything skipped
+	if _server_send_credit < tick_rate:
+		return
+	_server_send_credit -= tick_rate
+
 	# if server broadcast player state
+	NetworkTransport.broadcast_packet(_server_player_state().to_payload())
+
+
+func _server_player_state() -> PlayerStatePacket:
 	var player_state := PlayerStatePacket.new()
 	player_state.player_id = _owner_id
-	player_state.last_input_sequence_id = frames[-1].packet.sequence_id
+	player_state.last_input_sequence_id = _server_acked_sequence_id
 	player_state.timestamp_us = Time.get_ticks_usec()
 	player_state.position = game_position
 	player_state.look_abs = input.input_packet.look_abs
@@ -185,7 +232,55 @@ func _server_physics_step(delta: float) -> void:
 	player_state.prone_progress = %MovementStateMachine.prone_progress
 	player_state.peek_state = %PeekStateMachine.get_logic_state_id()
 	player_state.peek_progress = %PeekStateMachine.peek_progress
-	NetworkTransport.broadcast_packet(player_state.to_payload())
+	return player_state
+
+
+# puts real inputs that arrived late in place of the frames made up for them,
+# then rewinds and replays everything simulated since the oldest made up frame
+func _server_reconcile_late_frames() -> void:
+	var late_frames := _server_input_queue.take_late_frames()
+	if _server_rewind_state == null:
+		return
+
+	var replaced := false
+	for late_frame in late_frames:
+		for i in range(_server_rewind_frames.size()):
+			var frame := _server_rewind_frames[i]
+			if frame.synthetic and frame.sequence_id == late_frame.sequence_id:
+				_server_rewind_frames[i] = late_frame
+				replaced = true
+				break
+	if not replaced:
+		return
+
+	var rewind_state := _server_rewind_state
+	game_transform.origin = rewind_state.position
+	game_velocity = rewind_state.velocity
+	context = Enums.IntegrationContext.GAME
+	%MovementStateMachine.set_logic_state_by_id(rewind_state.movement_state)
+	%PeekStateMachine.set_logic_state_by_id(rewind_state.peek_state)
+	_prev_server_input = _server_rewind_prev_input
+	for frame in _server_rewind_frames:
+		input.prev_input_packet = _prev_server_input
+		_prev_server_input = frame.packet
+		input.input_packet = frame.packet
+		game_transform.basis = Basis.from_euler(Vector3(0, input.input_packet.look_abs.y, 0))
+		%MovementStateMachine.run_logic(frame.delta)
+		%PeekStateMachine.run_logic(frame.delta)
+	context = Enums.IntegrationContext.VISUAL
+	%MovementStateMachine.sync_visual()
+	%PeekStateMachine.sync_visual()
+	global_position = game_position
+	velocity = game_velocity
+
+	if _server_rewind_frames.all(func(frame: TimestampedPacket) -> bool: return not frame.synthetic):
+		_server_clear_rewind()
+
+
+func _server_clear_rewind() -> void:
+	_server_rewind_state = null
+	_server_rewind_prev_input = null
+	_server_rewind_frames.clear()
 
 
 var _prev_client_input: PlayerInputPacket = null
@@ -506,7 +601,7 @@ func server_handle_player_input(peer_id: int, input_packet: PlayerInputPacket) -
 	if peer_id != _owner_id:
 		return
 
-	_server_input_queue.enqueue(input_packet.sequence_id, input_packet.timestamp_us, input_packet)
+	_server_input_queue.enqueue(input_packet.sequence_id, input_packet.timestamp_us, input_packet.received_us, input_packet)
 
 
 func client_handle_player_state(player_state: PlayerStatePacket) -> void:
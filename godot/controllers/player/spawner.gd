@@ -24,7 +24,8 @@ func spawn_player(id: int) -> void:
 
 
 func despawn_player(id: int) -> void:
-	if NetworkTransport.is_server:
+	# destroy_server also despawns everyone, with no one left to tell
+	if NetworkTransport.is_server and NetworkTransport.is_connected:
 		var disconnect_packet := PlayerDisconnectedPacket.new()
 		disconnect_packet.player_id = id
 		NetworkTransport.broadcast_packet(disconnect_packet.to_payload())
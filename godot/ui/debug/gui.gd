@@ -31,3 +31,10 @@ func _on_fps_controller_reconcile_network_debug(delta_pos: Vector3, delta_vel: V
 	$NetworkDebug/DeltaVel.color = color_vel
 
 	$NetworkDebug/InputBuffer.text = "Inputs Size: %d\nInputs Oldest: %d\nInputs Newest: %d\nInputs Buffer Delay: %d" % [unacked_inputs.size(), unacked_inputs.oldest_sequence_id(), unacked_inputs.newest_sequence_id(), unacked_inputs.buffer_delay_us()]
+
+	var stats := unacked_inputs.stats()
+	$NetworkDebug/InputBuffer.text += "\nInputs Late/Dup/Window: %d/%d/%d\nInputs Skipped: %d\nInputs Overflows: %d" % [stats.late_arrivals, stats.duplicates, stats.out_of_window, stats.skipped, stats.overflows]
+	var peer_stats := NetworkClient.peer_stats()
+	for player_id in peer_stats:
+		var state_stats: Dictionary = peer_stats[player_id]
+		$NetworkDebug/InputBuffer.text += "\nStates #%d Underruns: %d Late: %d Skipped: %d Occupancy: %.1f (peak %d)" % [player_id, state_stats.underruns, state_stats.late_arrivals, state_stats.skipped, state_stats.average_occupancy, state_stats.peak_occupancy]
@@ -74,7 +74,9 @@ func _on_connect_button_pressed() -> void:
 	var port = int(%PortEdit.text) if !%PortEdit.text.is_empty() else 45876
 
 	if !NetworkTransport.is_connected:
-		NetworkTransport.start_client(ip_address, port)
+		if NetworkTransport.start_client(ip_address, port) != NetworkDriver.ERR_OK:
+			push_error("Failed to start client")
+			return
 		NetworkClient.username = %UsernameEdit.text
 		print("Client started")
 	else:
@@ -0,0 +1,209 @@
+use crate::pattern::InputScript;
+use br_core::packet::{Packet, PacketData, PeerId, PlayerInputPacketWire};
+use gns::sys::{
+    ESteamNetworkingConnectionState, k_nSteamNetworkingSend_Reliable, k_nSteamNetworkingSend_Unreliable,
+};
+use gns::{GnsGlobal, GnsSocket, IsClient};
+use std::{
+    collections::VecDeque,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const MAX_MESSAGES_PER_POLL: usize = 256;
+const MAX_EVENTS_PER_POLL: usize = 16;
+// inputs older than this are no longer useful for ack round trips
+const MAX_TRACKED_INPUTS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BotState {
+    Connecting,
+    Connected,
+    Spawned,
+    Disconnected(u32),
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct BotStats {
+    pub(crate) inputs_sent: u64,
+    pub(crate) states_received: u64,
+    pub(crate) decode_errors: u64,
+    pub(crate) ping_ms: u32,
+    /// Time from sending an input until a `PlayerState` acknowledging it arrives.
+    pub(crate) ack_rtt_ms: Option<f64>,
+    /// States received since the last report, for the rate column.
+    pub(crate) window_states: u64,
+}
+
+pub(crate) struct BotClient {
+    pub(crate) index: usize,
+    pub(crate) state: BotState,
+    pub(crate) peer_id: Option<PeerId>,
+    pub(crate) stats: BotStats,
+    socket: Option<GnsSocket<IsClient>>,
+    script: InputScript,
+    next_sequence_id: u16,
+    sent_inputs: VecDeque<(u16, Instant)>,
+}
+
+impl BotClient {
+    pub(crate) fn connect(
+        index: usize,
+        global: &Arc<GnsGlobal>,
+        address: IpAddr,
+        port: u16,
+        script: InputScript,
+    ) -> Result<Self, String> {
+        let socket = GnsSocket::new(global.clone())
+            .connect(address, port)
+            .map_err(|_| format!("bot {index}: failed to connect to {address}:{port}"))?;
+
+        Ok(Self {
+            index,
+            state: BotState::Connecting,
+            peer_id: None,
+            stats: BotStats::default(),
+            socket: Some(socket),
+            script,
+            next_sequence_id: 0,
+            sent_inputs: VecDeque::with_capacity(MAX_TRACKED_INPUTS),
+        })
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    pub(crate) fn poll(&mut self) {
+        let Some(socket) = self.socket.as_ref() else {
+            return;
+        };
+
+        let mut new_state = None;
+        socket.poll_event::<MAX_EVENTS_PER_POLL>(|event| match event.info().state() {
+            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connected => {
+                new_state = Some(BotState::Connected);
+            }
+            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_ClosedByPeer
+            | ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_ProblemDetectedLocally => {
+                new_state = Some(BotState::Disconnected(event.info().end_reason()));
+            }
+            _ => {}
+        });
+
+        let mut packets = Vec::new();
+        let mut decode_errors = 0;
+        loop {
+            let processed = socket
+                .poll_messages::<MAX_MESSAGES_PER_POLL>(|message| match Packet::decode(message.payload()) {
+                    Ok(packet) => packets.push(packet),
+                    Err(_) => decode_errors += 1,
+                })
+                .unwrap_or(0);
+            if processed < MAX_MESSAGES_PER_POLL {
+                break;
+            }
+        }
+
+        if let Ok((status, _)) = socket.get_connection_real_time_status(socket.connection(), 0) {
+            self.stats.ping_ms = status.ping();
+        }
+
+        self.stats.decode_errors += decode_errors;
+        for packet in packets {
+            self.handle_packet(packet);
+        }
+
+        match new_state {
+            Some(BotState::Connected) if self.state == BotState::Connecting => {
+                self.state = BotState::Connected;
+            }
+            Some(BotState::Disconnected(reason)) => {
+                self.state = BotState::Disconnected(reason);
+                self.socket = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_packet(&mut self, packet: Packet) {
+        match packet {
+            // the first assignment after connecting is ours, later ones announce other peers
+            Packet::IdAssignment(assignment) if self.peer_id.is_none() => {
+                self.peer_id = Some(assignment.id);
+                self.state = BotState::Spawned;
+            }
+            Packet::PlayerState(state) => {
+                self.stats.states_received += 1;
+                self.stats.window_states += 1;
+                if Some(state.player_id) == self.peer_id {
+                    self.record_ack(state.last_input_sequence_id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn record_ack(&mut self, sequence_id: u16) {
+        while let Some(&(sent_sequence_id, sent_at)) = self.sent_inputs.front() {
+            if sent_sequence_id == sequence_id {
+                let rtt_ms = sent_at.elapsed().as_secs_f64() * 1000.0;
+                // smooth like TCP's SRTT so the report is readable
+                self.stats.ack_rtt_ms = Some(match self.stats.ack_rtt_ms {
+                    Some(previous) => previous * 0.875 + rtt_ms * 0.125,
+                    None => rtt_ms,
+                });
+                break;
+            }
+            if br_core::math::sequence::seq_is_newer(sent_sequence_id, sequence_id) {
+                break;
+            }
+            self.sent_inputs.pop_front();
+        }
+    }
+
+    /// Sends the next scripted input. Does nothing until the server assigned an id.
+    pub(crate) fn send_input(&mut self, global: &GnsGlobal, timestamp: Duration) {
+        if self.state != BotState::Spawned {
+            return;
+        }
+        let Some(socket) = self.socket.as_ref() else {
+            return;
+        };
+
+        let input = self.script.next();
+        let sequence_id = self.next_sequence_id;
+        self.next_sequence_id = self.next_sequence_id.wrapping_add(1);
+
+        let packet = Packet::PlayerInput(PlayerInputPacketWire {
+            sequence_id,
+            timestamp_us: timestamp.as_micros() as u32,
+            move_forward_backward: input.move_forward_backward,
+            move_left_right: input.move_left_right,
+            look_abs: input.look_abs,
+            jump: input.jump,
+            crouch: input.crouch,
+            sprint: input.sprint,
+            prone: input.prone,
+            peek_left_right: input.peek_left_right,
+        });
+
+        let flags = if PlayerInputPacketWire::IS_RELIABLE {
+            k_nSteamNetworkingSend_Reliable
+        } else {
+            k_nSteamNetworkingSend_Unreliable
+        };
+        socket.send_messages(vec![global.utils().allocate_message(
+            socket.connection(),
+            flags,
+            packet.encode().as_slice(),
+        )]);
+
+        if self.sent_inputs.len() == MAX_TRACKED_INPUTS {
+            self.sent_inputs.pop_front();
+        }
+        self.sent_inputs.push_back((sequence_id, Instant::now()));
+        self.stats.inputs_sent += 1;
+    }
+}
@@ -0,0 +1,201 @@
+//! Headless load generator: opens many GNS client connections to a server and
+//! drives each one with scripted input, printing per-client stats.
+//!
+//!     br-bots --server 127.0.0.1:45876 --clients 100 --pattern random --duration 60
+
+mod bot;
+mod pattern;
+
+use bot::{BotClient, BotState};
+use gns::GnsGlobal;
+use pattern::{InputScript, MovementPattern};
+use std::{
+    net::SocketAddr,
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "usage: br-bots [--server <ip:port>] [--clients <n>] [--tick-rate <hz>] \
+[--pattern idle|circle|strafe|random] [--duration <s>] [--report-interval <s>] \
+[--connect-interval-ms <ms>] [--seed <n>]";
+
+struct Args {
+    server: SocketAddr,
+    clients: usize,
+    tick_rate: u32,
+    pattern: MovementPattern,
+    duration: Option<Duration>,
+    report_interval: Duration,
+    connect_interval: Duration,
+    seed: u64,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args {
+            server: SocketAddr::from(([127, 0, 0, 1], 45876)),
+            clients: 10,
+            tick_rate: 60,
+            pattern: MovementPattern::Random,
+            duration: None,
+            report_interval: Duration::from_secs(5),
+            connect_interval: Duration::from_millis(20),
+            seed: 1,
+        };
+
+        let mut args = args.peekable();
+        while let Some(flag) = args.next() {
+            let (flag, inline_value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (flag, None),
+            };
+            if flag == "--help" || flag == "-h" {
+                return Err(USAGE.to_string());
+            }
+            let value = inline_value
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {flag}"))?;
+            let invalid = |e: &dyn std::fmt::Display| format!("invalid value '{value}' for {flag}: {e}");
+
+            match flag.as_str() {
+                "--server" => parsed.server = value.parse().map_err(|e| invalid(&e))?,
+                "--clients" => parsed.clients = value.parse().map_err(|e| invalid(&e))?,
+                "--tick-rate" => parsed.tick_rate = value.parse().map_err(|e| invalid(&e))?,
+                "--pattern" => parsed.pattern = value.parse().map_err(|e| invalid(&e))?,
+                "--duration" => {
+                    parsed.duration = Some(Duration::from_secs_f64(value.parse().map_err(|e| invalid(&e))?))
+                }
+                "--report-interval" => {
+                    parsed.report_interval = Duration::from_secs_f64(value.parse().map_err(|e| invalid(&e))?)
+                }
+                "--connect-interval-ms" => {
+                    parsed.connect_interval = Duration::from_millis(value.parse().map_err(|e| invalid(&e))?)
+                }
+                "--seed" => parsed.seed = value.parse().map_err(|e| invalid(&e))?,
+                _ => return Err(format!("unknown flag {flag}\n{USAGE}")),
+            }
+        }
+
+        if parsed.clients == 0 || parsed.tick_rate == 0 {
+            return Err("--clients and --tick-rate must be positive".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+fn state_label(state: BotState) -> String {
+    match state {
+        BotState::Connecting => "connecting".to_string(),
+        BotState::Connected => "connected".to_string(),
+        BotState::Spawned => "spawned".to_string(),
+        BotState::Disconnected(reason) => format!("closed({reason})"),
+    }
+}
+
+fn report(bots: &mut [BotClient], window: Duration) {
+    let window_s = window.as_secs_f64().max(f64::EPSILON);
+    println!(
+        "{:>4} {:>5} {:>12} {:>7} {:>9} {:>9} {:>9} {:>7}",
+        "bot", "peer", "state", "ping", "ack_rtt", "states/s", "inputs", "decerr"
+    );
+    for bot in bots.iter_mut() {
+        println!(
+            "{:>4} {:>5} {:>12} {:>5}ms {:>9} {:>9.1} {:>9} {:>7}",
+            bot.index,
+            bot.peer_id.map(|id| id.to_string()).unwrap_or_else(|| "-".to_string()),
+            state_label(bot.state),
+            bot.stats.ping_ms,
+            bot.stats.ack_rtt_ms.map(|rtt| format!("{rtt:.1}ms")).unwrap_or_else(|| "-".to_string()),
+            bot.stats.window_states as f64 / window_s,
+            bot.stats.inputs_sent,
+            bot.stats.decode_errors,
+        );
+        bot.stats.window_states = 0;
+    }
+
+    let spawned = bots.iter().filter(|bot| bot.state == BotState::Spawned).count();
+    let decode_errors: u64 = bots.iter().map(|bot| bot.stats.decode_errors).sum();
+    println!("-- {spawned}/{} spawned, {decode_errors} decode errors\n", bots.len());
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let global = match GnsGlobal::get() {
+        Ok(global) => global,
+        Err(e) => {
+            eprintln!("ERROR: Failed to initialize GameNetworkingSockets: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let start = Instant::now();
+    let tick = Duration::from_secs_f64(1.0 / args.tick_rate as f64);
+    let mut bots: Vec<BotClient> = Vec::with_capacity(args.clients);
+    let mut next_connect = start;
+    let mut next_tick = start;
+    let mut last_report = start;
+
+    println!(
+        "Connecting {} bots to {} ({:?} pattern, {} Hz)",
+        args.clients, args.server, args.pattern, args.tick_rate
+    );
+
+    loop {
+        let now = Instant::now();
+
+        // stagger connects so the server isn't hit with every handshake at once
+        if bots.len() < args.clients && now >= next_connect {
+            let index = bots.len();
+            let script = InputScript::new(args.pattern, args.tick_rate, args.seed.wrapping_add(index as u64));
+            match BotClient::connect(index, &global, args.server.ip(), args.server.port(), script) {
+                Ok(bot) => bots.push(bot),
+                Err(e) => {
+                    eprintln!("ERROR: {e}");
+                    return ExitCode::FAILURE;
+                }
+            }
+            next_connect = now + args.connect_interval;
+        }
+
+        global.poll_callbacks();
+        for bot in bots.iter_mut() {
+            bot.poll();
+        }
+
+        if now >= next_tick {
+            let timestamp = now - start;
+            for bot in bots.iter_mut() {
+                bot.send_input(&global, timestamp);
+            }
+            next_tick += tick;
+            // don't try to catch up on ticks missed while descheduled
+            if next_tick < now {
+                next_tick = now + tick;
+            }
+        }
+
+        if now - last_report >= args.report_interval {
+            report(&mut bots, now - last_report);
+            last_report = now;
+        }
+
+        let finished = args.duration.is_some_and(|duration| now - start >= duration);
+        let all_closed = bots.len() == args.clients && bots.iter().all(|bot| !bot.is_active());
+        if finished || all_closed {
+            report(&mut bots, now - last_report);
+            break;
+        }
+
+        thread::sleep(next_tick.saturating_duration_since(Instant::now()).min(Duration::from_millis(1)));
+    }
+
+    ExitCode::SUCCESS
+}
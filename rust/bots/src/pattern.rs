@@ -0,0 +1,99 @@
+use br_core::math::rng::Rng;
+use std::{f32::consts::TAU, str::FromStr};
+
+/// How a bot drives its `PlayerInputPacket`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MovementPattern {
+    /// No movement, only keeps the input stream alive.
+    Idle,
+    /// Walks forward while turning at a constant rate.
+    Circle,
+    /// Alternates left and right strafing every second.
+    Strafe,
+    /// Picks a new random direction, look and action set every so often.
+    Random,
+}
+
+impl FromStr for MovementPattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "idle" => Ok(MovementPattern::Idle),
+            "circle" => Ok(MovementPattern::Circle),
+            "strafe" => Ok(MovementPattern::Strafe),
+            "random" => Ok(MovementPattern::Random),
+            _ => Err(format!("unknown pattern '{value}' (idle, circle, strafe, random)")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BotInput {
+    pub(crate) move_forward_backward: i8,
+    pub(crate) move_left_right: i8,
+    pub(crate) look_abs: [f32; 2],
+    pub(crate) jump: bool,
+    pub(crate) crouch: bool,
+    pub(crate) sprint: bool,
+    pub(crate) prone: bool,
+    pub(crate) peek_left_right: i8,
+}
+
+/// Produces one input per physics tick for a given pattern.
+pub(crate) struct InputScript {
+    pattern: MovementPattern,
+    tick_rate: u32,
+    tick: u64,
+    rng: Rng,
+    current: BotInput,
+}
+
+impl InputScript {
+    pub(crate) fn new(pattern: MovementPattern, tick_rate: u32, seed: u64) -> Self {
+        Self {
+            pattern,
+            tick_rate,
+            tick: 0,
+            rng: Rng::new(seed),
+            current: BotInput::default(),
+        }
+    }
+
+    fn random_axis(&mut self) -> i8 {
+        self.rng.range_u64(0, 3) as i8 - 1
+    }
+
+    pub(crate) fn next(&mut self) -> BotInput {
+        let seconds = self.tick as f32 / self.tick_rate as f32;
+        self.tick += 1;
+
+        match self.pattern {
+            MovementPattern::Idle => {}
+            MovementPattern::Circle => {
+                self.current.move_forward_backward = -1;
+                self.current.look_abs[1] = (seconds * 0.5 * TAU) % TAU;
+            }
+            MovementPattern::Strafe => {
+                self.current.move_left_right = if (seconds as u64).is_multiple_of(2) { -1 } else { 1 };
+            }
+            MovementPattern::Random => {
+                // re-roll roughly twice a second
+                if self.rng.chance(2.0 / self.tick_rate as f64) {
+                    self.current.move_forward_backward = self.random_axis();
+                    self.current.move_left_right = self.random_axis();
+                    self.current.look_abs = [
+                        (self.rng.next_f64() as f32 - 0.5) * 0.5,
+                        self.rng.next_f64() as f32 * TAU,
+                    ];
+                    self.current.sprint = self.rng.chance(0.3);
+                    self.current.crouch = self.rng.chance(0.1);
+                    self.current.peek_left_right = if self.rng.chance(0.2) { self.random_axis() } else { 0 };
+                }
+                self.current.jump = self.rng.chance(0.5 / self.tick_rate as f64);
+            }
+        }
+
+        self.current
+    }
+}
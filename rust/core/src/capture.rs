@@ -0,0 +1,247 @@
+//! Traffic capture files: every message a `NetworkDriver` sends or receives,
+//! in order, so a session can be replayed offline through `Packet::decode`.
+//!
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! header:  magic "BRCAP" | version u16 | role u8
+//! record:  timestamp_us u64 | flags u8 | peer_id u16 | len u32 | payload [u8; len]
+//! ```
+//!
+//! `timestamp_us` counts from when recording started. On client captures the
+//! remote end is always the server and `peer_id` is 0.
+
+use crate::packet::{Packet, PeerId};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+pub const MAGIC: &[u8; 5] = b"BRCAP";
+pub const VERSION: u16 = 1;
+
+const FLAG_RECEIVED: u8 = 1 << 0;
+const FLAG_RELIABLE: u8 = 1 << 1;
+const RECORD_HEADER_LEN: usize = 8 + 1 + 2 + 4;
+/// Larger than any message GNS will deliver; anything bigger means a corrupt file.
+const MAX_PAYLOAD_LEN: u32 = 512 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureRole {
+    Server,
+    Client,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord {
+    pub timestamp_us: u64,
+    pub direction: Direction,
+    pub peer_id: PeerId,
+    pub reliable: bool,
+    pub payload: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Decodes the payload the same way the driver does on receipt.
+    pub fn decode(&self) -> io::Result<Packet> {
+        Packet::decode(&self.payload)
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnknownRole(u8),
+    Corrupt(String),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Io(e) => write!(f, "capture i/o error: {e}"),
+            CaptureError::BadMagic => write!(f, "not a capture file"),
+            CaptureError::UnsupportedVersion(version) => {
+                write!(f, "unsupported capture version {version} (expected {VERSION})")
+            }
+            CaptureError::UnknownRole(role) => write!(f, "unknown capture role {role}"),
+            CaptureError::Corrupt(reason) => write!(f, "corrupt capture: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(e: io::Error) -> Self {
+        CaptureError::Io(e)
+    }
+}
+
+pub struct CaptureWriter<W: Write> {
+    out: W,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, role: CaptureRole) -> Result<Self, CaptureError> {
+        Self::new(BufWriter::new(File::create(path)?), role)
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut out: W, role: CaptureRole) -> Result<Self, CaptureError> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&[match role {
+            CaptureRole::Server => 0,
+            CaptureRole::Client => 1,
+        }])?;
+        Ok(Self { out })
+    }
+
+    pub fn write(
+        &mut self,
+        timestamp_us: u64,
+        direction: Direction,
+        peer_id: PeerId,
+        reliable: bool,
+        payload: &[u8],
+    ) -> Result<(), CaptureError> {
+        let len = u32::try_from(payload.len())
+            .ok()
+            .filter(|len| *len <= MAX_PAYLOAD_LEN)
+            .ok_or_else(|| CaptureError::Corrupt(format!("payload of {} bytes is too large", payload.len())))?;
+
+        let mut flags = 0;
+        if direction == Direction::Received {
+            flags |= FLAG_RECEIVED;
+        }
+        if reliable {
+            flags |= FLAG_RELIABLE;
+        }
+
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        header[0..8].copy_from_slice(&timestamp_us.to_le_bytes());
+        header[8] = flags;
+        header[9..11].copy_from_slice(&peer_id.to_le_bytes());
+        header[11..15].copy_from_slice(&len.to_le_bytes());
+        self.out.write_all(&header)?;
+        self.out.write_all(payload)?;
+        Ok(())
+    }
+
+    pub fn write_record(&mut self, record: &CaptureRecord) -> Result<(), CaptureError> {
+        self.write(record.timestamp_us, record.direction, record.peer_id, record.reliable, &record.payload)
+    }
+
+    pub fn flush(&mut self) -> Result<(), CaptureError> {
+        Ok(self.out.flush()?)
+    }
+}
+
+/// Iterates the records of a capture. A file truncated mid-record (e.g. the
+/// process was killed while recording) ends iteration with a `Corrupt` error.
+pub struct CaptureReader<R: Read> {
+    input: R,
+    role: CaptureRole,
+    done: bool,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CaptureError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut input: R) -> Result<Self, CaptureError> {
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => CaptureError::BadMagic,
+            _ => CaptureError::Io(e),
+        })?;
+
+        if &header[0..5] != MAGIC {
+            return Err(CaptureError::BadMagic);
+        }
+        let version = u16::from_le_bytes([header[5], header[6]]);
+        if version != VERSION {
+            return Err(CaptureError::UnsupportedVersion(version));
+        }
+        let role = match header[7] {
+            0 => CaptureRole::Server,
+            1 => CaptureRole::Client,
+            other => return Err(CaptureError::UnknownRole(other)),
+        };
+
+        Ok(Self { input, role, done: false })
+    }
+
+    pub fn role(&self) -> CaptureRole {
+        self.role
+    }
+
+    fn read_record(&mut self) -> Result<Option<CaptureRecord>, CaptureError> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.input.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(CaptureError::Corrupt("truncated record header".to_string())),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let timestamp_us = u64::from_le_bytes(header[0..8].try_into().expect("slice is 8 bytes"));
+        let flags = header[8];
+        let peer_id = PeerId::from_le_bytes([header[9], header[10]]);
+        let len = u32::from_le_bytes(header[11..15].try_into().expect("slice is 4 bytes"));
+        if len > MAX_PAYLOAD_LEN {
+            return Err(CaptureError::Corrupt(format!("record payload of {len} bytes")));
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        self.input.read_exact(&mut payload).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => CaptureError::Corrupt("truncated record payload".to_string()),
+            _ => CaptureError::Io(e),
+        })?;
+
+        Ok(Some(CaptureRecord {
+            timestamp_us,
+            direction: if flags & FLAG_RECEIVED != 0 { Direction::Received } else { Direction::Sent },
+            peer_id,
+            reliable: flags & FLAG_RELIABLE != 0,
+            payload,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
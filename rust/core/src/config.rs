@@ -0,0 +1,554 @@
+use serde::Deserialize;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::data_structures::peer_slots::PeerSlots;
+use crate::lobby::DEFAULT_COUNTDOWN;
+use crate::log::{DEFAULT_FILE_KEEP, DEFAULT_FILE_MAX_BYTES, LogCategory};
+use crate::net_log;
+use crate::server_query::DEFAULT_QUERY_PORT;
+
+pub use crate::log::LogLevel;
+
+/// Prefix for environment overrides, e.g. `BR_SERVER_PORT=45877` or `BR_SERVER_LINK_LAG_SEND_MS=40`.
+pub const ENV_PREFIX: &str = "BR_SERVER_";
+/// Command-line flag naming the TOML file, e.g. `--config=server.toml`.
+pub const CONFIG_FLAG: &str = "config";
+
+/// Backends a dedicated server can listen on. Loopback is in-process only.
+pub const TRANSPORTS: &[&str] = &["gns", "udp", "websocket"];
+
+/// Every key accepted by `ServerConfig::set`, in TOML dotted form.
+const KEYS: &[&str] = &[
+    "bind_address",
+    "port",
+    "capacity",
+    "reserved_slots",
+    "tick_rate",
+    "send_rate",
+    "max_messages_per_poll",
+    "poll_time_budget_ms",
+    "network_thread",
+    "log_level",
+    "transport",
+    "websocket_port",
+    "query_port",
+    "metrics_port",
+    "server_name",
+    "map",
+    "master_server",
+    "lobby_min_players",
+    "lobby_countdown_secs",
+    "log.gns",
+    "log.server",
+    "log.client",
+    "log.packet",
+    "log.file",
+    "log.file_max_kb",
+    "log.file_keep",
+    "link.lag_send_ms",
+    "link.lag_recv_ms",
+    "link.loss_send_pct",
+    "link.loss_recv_pct",
+    "link.jitter_send_ms",
+    "link.jitter_recv_ms",
+    "link.dup_send_pct",
+    "link.dup_recv_pct",
+    "link.dup_ms_max",
+    "link.reorder_send_pct",
+    "link.reorder_recv_pct",
+    "link.reorder_ms",
+];
+
+/// Per-category log levels and the optional log file. Categories left unset
+/// use `log_level`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub gns: Option<LogLevel>,
+    pub server: Option<LogLevel>,
+    pub client: Option<LogLevel>,
+    pub packet: Option<LogLevel>,
+    /// Also write every record here; empty disables.
+    pub file: String,
+    /// Size at which the file is rotated.
+    pub file_max_kb: u64,
+    /// Rotated files kept next to the current one.
+    pub file_keep: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            gns: None,
+            server: None,
+            client: None,
+            packet: None,
+            file: String::new(),
+            file_max_kb: DEFAULT_FILE_MAX_BYTES / 1024,
+            file_keep: DEFAULT_FILE_KEEP,
+        }
+    }
+}
+
+impl LogConfig {
+    /// The level for `category`, falling back to `default`.
+    pub fn level(&self, category: LogCategory, default: LogLevel) -> LogLevel {
+        let level = match category {
+            LogCategory::Gns => self.gns,
+            LogCategory::Server => self.server,
+            LogCategory::Client => self.client,
+            LogCategory::Packet => self.packet,
+        };
+        level.unwrap_or(default)
+    }
+}
+
+/// Mirrors the GNS fake-network knobs exposed by `NetworkDriver::set_fake_*`.
+/// Times are in milliseconds, the rest are percentages. Backends other than
+/// GNS simulate them with a `ConditionedTransport`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkSimulationConfig {
+    pub lag_send_ms: u32,
+    pub lag_recv_ms: u32,
+    pub loss_send_pct: u32,
+    pub loss_recv_pct: u32,
+    pub jitter_send_ms: u32,
+    pub jitter_recv_ms: u32,
+    pub dup_send_pct: u32,
+    pub dup_recv_pct: u32,
+    pub dup_ms_max: u32,
+    pub reorder_send_pct: u32,
+    pub reorder_recv_pct: u32,
+    pub reorder_ms: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub capacity: usize,
+    pub reserved_slots: usize,
+    pub tick_rate: u32,
+    pub send_rate: u32,
+    pub max_messages_per_poll: usize,
+    pub poll_time_budget_ms: u64,
+    /// Poll and decode on a dedicated thread; the budgets above then only
+    /// bound handing packets to Godot.
+    pub network_thread: bool,
+    /// Default level for every log category; see `log` to set them apart.
+    pub log_level: LogLevel,
+    /// Network backend, one of `TRANSPORTS`.
+    pub transport: String,
+    /// Also accept WebSocket clients on this TCP port; 0 disables.
+    pub websocket_port: u16,
+    /// UDP port answering out-of-band status queries; 0 disables. Must differ
+    /// from `port`.
+    pub query_port: u16,
+    /// Serves Prometheus metrics on `127.0.0.1:<metrics_port>/metrics`; 0
+    /// disables.
+    pub metrics_port: u16,
+    /// Shown in server browsers.
+    pub server_name: String,
+    pub map: String,
+    /// `host[:port]` of the master server to list this server with; empty
+    /// keeps it unlisted.
+    pub master_server: String,
+    /// Ready players needed to start a match; 0 skips the lobby and lets
+    /// players in straight away.
+    pub lobby_min_players: u16,
+    pub lobby_countdown_secs: u32,
+    pub log: LogConfig,
+    pub link: LinkSimulationConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 45876,
+            capacity: 100,
+            reserved_slots: 0,
+            tick_rate: 60,
+            send_rate: 60,
+            max_messages_per_poll: 1024,
+            poll_time_budget_ms: 2,
+            network_thread: false,
+            log_level: LogLevel::Info,
+            transport: "gns".to_string(),
+            websocket_port: 0,
+            query_port: DEFAULT_QUERY_PORT,
+            metrics_port: 0,
+            server_name: "Dedicated server".to_string(),
+            map: String::new(),
+            master_server: String::new(),
+            lobby_min_players: 0,
+            lobby_countdown_secs: DEFAULT_COUNTDOWN.as_secs() as u32,
+            log: LogConfig::default(),
+            link: LinkSimulationConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Override { key: String, value: String, reason: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "failed to read {path}: {e}"),
+            ConfigError::Parse(path, e) => write!(f, "failed to parse {path}: {e}"),
+            ConfigError::Override { key, value, reason } => {
+                write!(f, "invalid override {key}={value}: {reason}")
+            }
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {reason}"),
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value.trim().parse().map_err(|e: T::Err| ConfigError::Override {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+impl ServerConfig {
+    /// Builds the effective config: defaults, then the TOML file (if any), then
+    /// `BR_SERVER_*` environment variables, then `--key=value` arguments.
+    pub fn load(
+        path: Option<&Path>,
+        env: impl IntoIterator<Item = (String, String)>,
+        args: &[String],
+    ) -> Result<Self, ConfigError> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env(env)?;
+        config.apply_args(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let display = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(display.clone(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(display, e))
+    }
+
+    /// Returns the value of `--config=<path>` or `--config <path>` if present.
+    pub fn config_path_from_args(args: &[String]) -> Option<String> {
+        let flag = format!("--{CONFIG_FLAG}");
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if let Some(path) = arg.strip_prefix(&format!("{flag}=")) {
+                return Some(path.to_string());
+            }
+            if *arg == flag {
+                return iter.next().cloned();
+            }
+        }
+        None
+    }
+
+    /// Applies `BR_SERVER_*` variables. Unknown ones are skipped with a
+    /// warning, since the environment is shared with whatever launched us.
+    pub fn apply_env(&mut self, env: impl IntoIterator<Item = (String, String)>) -> Result<(), ConfigError> {
+        for (name, value) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            // BR_SERVER_LINK_LAG_SEND_MS -> link.lag_send_ms, BR_SERVER_LOG_GNS -> log.gns
+            let key = key.to_ascii_lowercase();
+            let key = ["link", "log"]
+                .iter()
+                .find_map(|table| {
+                    let dotted = format!("{table}.{}", key.strip_prefix(&format!("{table}_"))?);
+                    Self::is_key(&dotted).then_some(dotted)
+                })
+                .unwrap_or(key);
+            if !Self::is_key(&key) {
+                net_log!(Warning, Server, "Ignoring unknown environment override {name}");
+                continue;
+            }
+            self.set(&key, &value)?;
+        }
+        Ok(())
+    }
+
+    /// Applies `--key=value` and `--key value` arguments. Dashes in keys are
+    /// accepted in place of underscores. Bare unknown flags such as `--server`
+    /// are left for the game, but an unknown `--key=value` is an error.
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), ConfigError> {
+        let mut iter = args.iter().peekable();
+        while let Some(arg) = iter.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                continue;
+            };
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key.replace('-', "_"), value.to_string()),
+                None => {
+                    let key = flag.replace('-', "_");
+                    if !Self::is_key(&key) {
+                        continue;
+                    }
+                    match iter.next_if(|next| !next.starts_with("--")) {
+                        Some(value) => (key, value.clone()),
+                        None => {
+                            return Err(ConfigError::Override {
+                                key,
+                                value: String::new(),
+                                reason: "missing value".to_string(),
+                            });
+                        }
+                    }
+                }
+            };
+            if key == CONFIG_FLAG {
+                continue;
+            }
+            self.set(&key, &value)?;
+        }
+        Ok(())
+    }
+
+    fn is_key(key: &str) -> bool {
+        KEYS.contains(&key)
+    }
+
+    /// Sets a single value by its TOML key, e.g. `port` or `link.lag_send_ms`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "bind_address" => self.bind_address = parse_value(key, value)?,
+            "port" => self.port = parse_value(key, value)?,
+            "capacity" => self.capacity = parse_value(key, value)?,
+            "reserved_slots" => self.reserved_slots = parse_value(key, value)?,
+            "tick_rate" => self.tick_rate = parse_value(key, value)?,
+            "send_rate" => self.send_rate = parse_value(key, value)?,
+            "max_messages_per_poll" => self.max_messages_per_poll = parse_value(key, value)?,
+            "poll_time_budget_ms" => self.poll_time_budget_ms = parse_value(key, value)?,
+            "network_thread" => self.network_thread = parse_value(key, value)?,
+            "log_level" => self.log_level = parse_value(key, value)?,
+            "transport" => self.transport = value.trim().to_ascii_lowercase(),
+            "websocket_port" => self.websocket_port = parse_value(key, value)?,
+            "query_port" => self.query_port = parse_value(key, value)?,
+            "metrics_port" => self.metrics_port = parse_value(key, value)?,
+            "server_name" => self.server_name = value.to_string(),
+            "map" => self.map = value.to_string(),
+            "master_server" => self.master_server = value.trim().to_string(),
+            "lobby_min_players" => self.lobby_min_players = parse_value(key, value)?,
+            "lobby_countdown_secs" => self.lobby_countdown_secs = parse_value(key, value)?,
+            "log.gns" => self.log.gns = Some(parse_value(key, value)?),
+            "log.server" => self.log.server = Some(parse_value(key, value)?),
+            "log.client" => self.log.client = Some(parse_value(key, value)?),
+            "log.packet" => self.log.packet = Some(parse_value(key, value)?),
+            "log.file" => self.log.file = value.trim().to_string(),
+            "log.file_max_kb" => self.log.file_max_kb = parse_value(key, value)?,
+            "log.file_keep" => self.log.file_keep = parse_value(key, value)?,
+            "link.lag_send_ms" => self.link.lag_send_ms = parse_value(key, value)?,
+            "link.lag_recv_ms" => self.link.lag_recv_ms = parse_value(key, value)?,
+            "link.loss_send_pct" => self.link.loss_send_pct = parse_value(key, value)?,
+            "link.loss_recv_pct" => self.link.loss_recv_pct = parse_value(key, value)?,
+            "link.jitter_send_ms" => self.link.jitter_send_ms = parse_value(key, value)?,
+            "link.jitter_recv_ms" => self.link.jitter_recv_ms = parse_value(key, value)?,
+            "link.dup_send_pct" => self.link.dup_send_pct = parse_value(key, value)?,
+            "link.dup_recv_pct" => self.link.dup_recv_pct = parse_value(key, value)?,
+            "link.dup_ms_max" => self.link.dup_ms_max = parse_value(key, value)?,
+            "link.reorder_send_pct" => self.link.reorder_send_pct = parse_value(key, value)?,
+            "link.reorder_recv_pct" => self.link.reorder_recv_pct = parse_value(key, value)?,
+            "link.reorder_ms" => self.link.reorder_ms = parse_value(key, value)?,
+            _ => return Err(ConfigError::Invalid(format!("unknown key '{key}'"))),
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: String| Err(ConfigError::Invalid(reason));
+
+        if self.port == 0 {
+            return invalid("port must be non-zero".to_string());
+        }
+        if let Err(e) = PeerSlots::new(self.capacity, self.reserved_slots) {
+            return invalid(e);
+        }
+        if !(1..=1000).contains(&self.tick_rate) {
+            return invalid(format!("tick_rate {} must be between 1 and 1000", self.tick_rate));
+        }
+        if self.send_rate == 0 || self.send_rate > self.tick_rate {
+            return invalid(format!(
+                "send_rate {} must be between 1 and tick_rate {}",
+                self.send_rate, self.tick_rate
+            ));
+        }
+        if !TRANSPORTS.contains(&self.transport.as_str()) {
+            return invalid(format!(
+                "transport '{}' must be one of {}",
+                self.transport,
+                TRANSPORTS.join(", ")
+            ));
+        }
+        if self.websocket_port != 0 && self.transport == "websocket" {
+            return invalid("websocket_port is only for non-websocket transports".to_string());
+        }
+        if self.query_port == self.port {
+            return invalid(format!("query_port {} must differ from port", self.query_port));
+        }
+        if usize::from(self.lobby_min_players) > self.capacity {
+            return invalid(format!(
+                "lobby_min_players {} must not exceed capacity {}",
+                self.lobby_min_players, self.capacity
+            ));
+        }
+        if self.max_messages_per_poll == 0 {
+            return invalid("max_messages_per_poll must be at least 1".to_string());
+        }
+        let budget_per_second = self.poll_time_budget_ms.checked_mul(u64::from(self.tick_rate));
+        if self.poll_time_budget_ms == 0 || budget_per_second.is_none_or(|ms| ms >= 1000) {
+            return invalid(format!(
+                "poll_time_budget_ms {} must be non-zero and fit inside one tick at {} Hz",
+                self.poll_time_budget_ms, self.tick_rate
+            ));
+        }
+
+        let link = &self.link;
+        for (name, pct) in [
+            ("loss_send_pct", link.loss_send_pct),
+            ("loss_recv_pct", link.loss_recv_pct),
+            ("dup_send_pct", link.dup_send_pct),
+            ("dup_recv_pct", link.dup_recv_pct),
+            ("reorder_send_pct", link.reorder_send_pct),
+            ("reorder_recv_pct", link.reorder_recv_pct),
+        ] {
+            if pct > 100 {
+                return invalid(format!("link.{name} {pct} must be a percentage"));
+            }
+        }
+        for (name, ms) in [
+            ("lag_send_ms", link.lag_send_ms),
+            ("lag_recv_ms", link.lag_recv_ms),
+            ("jitter_send_ms", link.jitter_send_ms),
+            ("jitter_recv_ms", link.jitter_recv_ms),
+            ("dup_ms_max", link.dup_ms_max),
+            ("reorder_ms", link.reorder_ms),
+        ] {
+            if ms > 10_000 {
+                return invalid(format!("link.{name} {ms} exceeds 10000 ms"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn write_config(name: &str, text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("br-config-{}-{name}.toml", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn args_override_env_override_file() {
+        let path = write_config(
+            "precedence",
+            "port = 40000\ntick_rate = 30\nsend_rate = 30\n[link]\nlag_send_ms = 10\n",
+        );
+        let config = ServerConfig::load(
+            Some(&path),
+            env(&[
+                ("BR_SERVER_PORT", "41000"),
+                ("BR_SERVER_LINK_LAG_SEND_MS", "20"),
+                ("PATH", "/bin"),
+            ]),
+            &args(&["--server", "--port=42000", "--send-rate", "15"]),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.port, 42000);
+        assert_eq!(config.link.lag_send_ms, 20);
+        assert_eq!(config.tick_rate, 30);
+        assert_eq!(config.send_rate, 15);
+    }
+
+    #[test]
+    fn unknown_env_keys_are_skipped() {
+        let config = ServerConfig::load(
+            None,
+            env(&[("BR_SERVER_HOST", "example.org"), ("BR_SERVER_MAP", "dunes")]),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config.map, "dunes");
+    }
+
+    #[test]
+    fn unknown_file_and_arg_keys_are_rejected() {
+        let path = write_config("unknown", "host = \"example.org\"\n");
+        let result = ServerConfig::load(Some(&path), Vec::new(), &[]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(ConfigError::Parse(..))));
+
+        let result = ServerConfig::load(None, Vec::new(), &args(&["--host=example.org"]));
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn bad_values_name_their_key() {
+        let result = ServerConfig::load(None, env(&[("BR_SERVER_PORT", "lots")]), &[]);
+        assert!(matches!(result, Err(ConfigError::Override { key, .. }) if key == "port"));
+
+        let result = ServerConfig::load(None, Vec::new(), &args(&["--tick-rate"]));
+        assert!(matches!(result, Err(ConfigError::Override { key, .. }) if key == "tick_rate"));
+    }
+
+    #[test]
+    fn validation_rejects_inconsistent_settings() {
+        let invalid = |overrides: &[&str]| {
+            matches!(
+                ServerConfig::load(None, Vec::new(), &args(overrides)),
+                Err(ConfigError::Invalid(_))
+            )
+        };
+
+        assert!(!invalid(&[]));
+        assert!(invalid(&["--port=0"]));
+        assert!(invalid(&["--tick-rate=30", "--send-rate=60"]));
+        assert!(invalid(&["--transport=carrier-pigeon"]));
+        assert!(invalid(&["--port=40000", "--query-port=40000"]));
+        assert!(invalid(&["--link.loss_send_pct=101"]));
+        assert!(invalid(&["--link.reorder_ms=20000"]));
+        assert!(invalid(&["--capacity=2", "--lobby-min-players=3"]));
+        assert!(invalid(&["--poll-time-budget-ms=20", "--tick-rate=60"]));
+        assert!(invalid(&["--poll-time-budget-ms=18446744073709551615"]));
+    }
+}
@@ -0,0 +1,40 @@
+/// Why a sequenced buffer is starving or piling up, counted since creation
+/// or the last `reset`. Each buffer documents what the counters mean for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BufferStats {
+    /// Arrived after newer sequences had.
+    pub late_arrivals: u64,
+    /// A sequence that was already buffered.
+    pub duplicates: u64,
+    /// Dropped for being too old for the buffer to take.
+    pub out_of_window: u64,
+    /// Sequences given up on without ever arriving.
+    pub skipped: u64,
+    /// Reads that found nothing, or not enough, to use.
+    pub underruns: u64,
+    /// Times the buffer held more than it could or should.
+    pub overflows: u64,
+    pub peak_occupancy: usize,
+    occupancy_sum: u64,
+    occupancy_samples: u64,
+}
+
+impl BufferStats {
+    /// Records how many entries the buffer holds; called once per read.
+    pub fn sample_occupancy(&mut self, occupancy: usize) {
+        self.peak_occupancy = self.peak_occupancy.max(occupancy);
+        self.occupancy_sum += occupancy as u64;
+        self.occupancy_samples += 1;
+    }
+
+    pub fn average_occupancy(&self) -> f64 {
+        if self.occupancy_samples == 0 {
+            return 0.0;
+        }
+        self.occupancy_sum as f64 / self.occupancy_samples as f64
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,534 @@
+use super::buffer_stats::BufferStats;
+use crate::math::sequence::{seq_diff, seq_is_newer};
+use num_derive::FromPrimitive;
+use std::collections::{HashMap, VecDeque};
+
+pub const MAX_FRAMES_PER_TICK: usize = 4;
+/// Newer frames that must have arrived before a missing one counts as lost.
+/// The target depth raises this when it is deeper.
+pub const PACKET_LOSS_TOLERANCE: i32 = 4;
+pub const MIN_TARGET_DEPTH: usize = 1;
+pub const MAX_TARGET_DEPTH: usize = 8;
+/// Frames of buffer kept per frame interval of measured jitter.
+const JITTER_DEPTH_FACTOR: f64 = 2.0;
+/// Ticks between two catch-up or hold steps, so depth changes are spread out
+/// rather than felt as a burst or a stall.
+const ADJUST_INTERVAL_TICKS: u32 = 8;
+/// Consecutive frames gap filling makes up before the buffer stalls instead.
+pub const MAX_SYNTHETIC_FRAMES: u32 = 8;
+/// `GapFill::RepeatDecay` scales movement by this per synthetic frame.
+pub const SYNTHETIC_DECAY: f64 = 0.75;
+/// Late frames and synthesized sequence ids kept for reconciling.
+const MAX_RECONCILE_FRAMES: usize = 64;
+
+/// What `JitterBuffer::consume` releases on a tick whose frame hasn't
+/// arrived yet.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromPrimitive)]
+pub enum GapFill {
+    /// Nothing; the tick goes unsimulated.
+    #[default]
+    Skip,
+    /// The last input again, movement scaled down by `SYNTHETIC_DECAY` for
+    /// every frame in a row that is made up.
+    RepeatDecay,
+    /// The last input's movement and look, with every action released.
+    HoldMovement,
+    /// The last input unchanged, for the caller to treat as it sees fit.
+    MarkSynthetic,
+}
+
+/// Inputs a `JitterBuffer` can make up frames from.
+pub trait SyntheticInput: Clone {
+    /// A copy with movement scaled by `weight`, from 1 down to 0.
+    fn decayed(&self, weight: f64) -> Self;
+    /// A copy that keeps movement and look but releases every action.
+    fn movement_only(&self) -> Self;
+}
+
+pub struct TimestampedFrame<T> {
+    pub delta: f64,
+    pub timestamp_us: u32,
+    pub sequence_id: u16,
+    /// Made up by gap filling; the real frame may still turn up through
+    /// `JitterBuffer::take_late_frames`.
+    pub synthetic: bool,
+    pub packet: T,
+}
+
+/// Reorders sequenced inputs and releases one per tick, skipping a missing
+/// sequence once enough newer ones have arrived.
+///
+/// The buffer measures the jitter of arrivals against their send timestamps
+/// and keeps a target depth that covers it. When deeper than that it catches
+/// up by releasing an extra frame, when shallower it holds one back, at most
+/// once every `ADJUST_INTERVAL_TICKS`. A backlog past `MAX_FRAMES_PER_TICK`
+/// over target is released `MAX_FRAMES_PER_TICK` at a time.
+///
+/// In its `BufferStats`, late arrivals are frames older than one already
+/// received or released, including those gap filling stood in for; skipped
+/// are sequences given up on as lost; underruns are ticks with no frame to
+/// release; overflows are ticks that released a backlog at once. Nothing is
+/// out of window, as the buffer takes any newer sequence.
+pub struct JitterBuffer<T> {
+    packets: HashMap<u16, TimestampedFrame<T>>,
+    next_sequence_id: u16,
+    last_received_timestamp_us: u32,
+    last_sequence_id: u16,
+    // receive time of the last accepted frame, on the local clock
+    last_arrival_us: Option<u64>,
+    // RFC 3550 style interarrival jitter, smoothed over ~16 frames
+    jitter_us: f64,
+    frame_interval_us: f64,
+    target_depth: usize,
+    // nothing is released until the buffer first reaches its target depth
+    primed: bool,
+    ticks_since_adjust: u32,
+    stats: BufferStats,
+    gap_fill: GapFill,
+    // last frame released, real or synthetic, to make up the next from
+    last_released: Option<(u32, T)>,
+    synthetic_run: u32,
+    synthesized: VecDeque<u16>,
+    late: VecDeque<TimestampedFrame<T>>,
+}
+
+impl<T: SyntheticInput> Default for JitterBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: SyntheticInput> JitterBuffer<T> {
+    pub fn new() -> Self {
+        Self {
+            packets: HashMap::with_capacity(64),
+            next_sequence_id: 0,
+            last_received_timestamp_us: 0,
+            last_sequence_id: 65535,
+            last_arrival_us: None,
+            jitter_us: 0.0,
+            frame_interval_us: 0.0,
+            target_depth: MIN_TARGET_DEPTH,
+            primed: false,
+            ticks_since_adjust: 0,
+            stats: BufferStats::default(),
+            gap_fill: GapFill::Skip,
+            last_released: None,
+            synthetic_run: 0,
+            synthesized: VecDeque::new(),
+            late: VecDeque::new(),
+        }
+    }
+
+    pub fn set_gap_fill(&mut self, gap_fill: GapFill) {
+        self.gap_fill = gap_fill;
+    }
+
+    pub fn gap_fill(&self) -> GapFill {
+        self.gap_fill
+    }
+
+    /// Buffers `packet`, returning false if it is not newer than the last one
+    /// accepted. `timestamp_us` is when it was sent and `arrival_us` when it
+    /// was received, each on its own clock. `fallback_delta` is the tick
+    /// length, used for the very first frame and to size the target depth.
+    ///
+    /// A frame that gap filling already made up goes to `take_late_frames`.
+    pub fn enqueue(
+        &mut self,
+        sequence_id: u16,
+        timestamp_us: u32,
+        arrival_us: u64,
+        fallback_delta: f64,
+        packet: T,
+    ) -> bool {
+        self.prune_synthesized();
+        // only a frame already released past can have been made up
+        let synthesized = if seq_is_newer(self.next_sequence_id, sequence_id) {
+            self.synthesized.iter().position(|synthesized| *synthesized == sequence_id)
+        } else {
+            None
+        };
+        if !seq_is_newer(sequence_id, self.last_sequence_id) {
+            let Some(index) = synthesized else {
+                if sequence_id == self.last_sequence_id || self.packets.contains_key(&sequence_id) {
+                    self.stats.duplicates += 1;
+                } else {
+                    self.stats.late_arrivals += 1;
+                }
+                return false;
+            };
+            self.stats.late_arrivals += 1;
+            self.synthesized.remove(index);
+            let delta = self.frame_interval_us / 1_000_000.0;
+            self.push_late(TimestampedFrame { delta, timestamp_us, sequence_id, synthetic: false, packet });
+            return true;
+        }
+
+        self.last_sequence_id = sequence_id;
+
+        let delta = if self.last_received_timestamp_us > 0 {
+            timestamp_us.wrapping_sub(self.last_received_timestamp_us) as f64 / 1_000_000.0
+        } else {
+            fallback_delta
+        };
+        if let Some(last_arrival_us) = self.last_arrival_us
+            && self.last_received_timestamp_us > 0
+        {
+            let sent_us = delta * 1_000_000.0;
+            let received_us = arrival_us.saturating_sub(last_arrival_us) as f64;
+            self.jitter_us += ((received_us - sent_us).abs() - self.jitter_us) / 16.0;
+        }
+        self.last_arrival_us = Some(arrival_us);
+        self.last_received_timestamp_us = timestamp_us;
+        self.frame_interval_us = fallback_delta * 1_000_000.0;
+        self.update_target_depth();
+
+        let frame = TimestampedFrame { delta, timestamp_us, sequence_id, synthetic: false, packet };
+        if let Some(index) = synthesized {
+            self.synthesized.remove(index);
+            self.stats.late_arrivals += 1;
+            self.push_late(frame);
+            return true;
+        }
+        if seq_is_newer(self.next_sequence_id, sequence_id) {
+            // skipped as lost before it arrived
+            self.stats.late_arrivals += 1;
+            return false;
+        }
+        self.packets.insert(sequence_id, frame);
+        true
+    }
+
+    /// Forgets synthesized ids that have fallen out of the reconcile window,
+    /// so a sequence id reused after wraparound isn't taken for a late frame.
+    fn prune_synthesized(&mut self) {
+        let next_sequence_id = self.next_sequence_id;
+        self.synthesized.retain(|synthesized| {
+            let behind = seq_diff(next_sequence_id, *synthesized);
+            behind > 0 && behind <= MAX_RECONCILE_FRAMES as i32
+        });
+    }
+
+    fn push_late(&mut self, frame: TimestampedFrame<T>) {
+        if self.late.len() == MAX_RECONCILE_FRAMES {
+            self.late.pop_front();
+        }
+        self.late.push_back(frame);
+    }
+
+    /// Real frames that arrived after gap filling had stood in for them,
+    /// oldest first, so the caller can reconcile. Only the most recent
+    /// `MAX_RECONCILE_FRAMES` are kept.
+    pub fn take_late_frames(&mut self) -> Vec<TimestampedFrame<T>> {
+        self.late.drain(..).collect()
+    }
+
+    /// Makes up the frame for `next_sequence_id` from the last real one
+    /// released, as `gap_fill` says.
+    fn synthesize(&mut self) -> Option<TimestampedFrame<T>> {
+        if self.synthetic_run >= MAX_SYNTHETIC_FRAMES {
+            return None;
+        }
+        let (last_timestamp_us, last_packet) = self.last_released.as_mut()?;
+        let run = self.synthetic_run + 1;
+        let packet = match self.gap_fill {
+            GapFill::Skip => return None,
+            GapFill::RepeatDecay => last_packet.decayed(SYNTHETIC_DECAY.powi(run as i32)),
+            GapFill::HoldMovement => last_packet.movement_only(),
+            GapFill::MarkSynthetic => last_packet.clone(),
+        };
+        *last_timestamp_us = last_timestamp_us.wrapping_add(self.frame_interval_us as u32);
+        let frame = TimestampedFrame {
+            delta: self.frame_interval_us / 1_000_000.0,
+            timestamp_us: *last_timestamp_us,
+            sequence_id: self.next_sequence_id,
+            synthetic: true,
+            packet,
+        };
+
+        self.synthetic_run = run;
+        self.synthesized.push_back(self.next_sequence_id);
+        self.next_sequence_id = self.next_sequence_id.wrapping_add(1);
+        self.prune_synthesized();
+        Some(frame)
+    }
+
+    fn update_target_depth(&mut self) {
+        if self.frame_interval_us <= 0.0 {
+            return;
+        }
+        let frames = (self.jitter_us * JITTER_DEPTH_FACTOR / self.frame_interval_us).ceil() as usize;
+        self.target_depth = (frames + 1).clamp(MIN_TARGET_DEPTH, MAX_TARGET_DEPTH);
+    }
+
+    /// How many frames this tick should release, given the current depth.
+    fn frames_to_release(&mut self) -> usize {
+        let depth = self.packets.len();
+        if !self.primed {
+            if depth < self.target_depth {
+                return 0;
+            }
+            self.primed = true;
+        }
+
+        if depth > self.target_depth + MAX_FRAMES_PER_TICK {
+            self.stats.overflows += 1;
+            self.ticks_since_adjust = 0;
+            return MAX_FRAMES_PER_TICK;
+        }
+
+        self.ticks_since_adjust = self.ticks_since_adjust.saturating_add(1);
+        if self.ticks_since_adjust < ADJUST_INTERVAL_TICKS {
+            return 1;
+        }
+        if depth > self.target_depth + 1 {
+            self.ticks_since_adjust = 0;
+            2
+        } else if depth > 0 && depth < self.target_depth {
+            self.ticks_since_adjust = 0;
+            0
+        } else {
+            1
+        }
+    }
+
+    pub fn consume(&mut self) -> Vec<TimestampedFrame<T>> {
+        self.stats.sample_occupancy(self.packets.len());
+        let release = self.frames_to_release();
+        if release == 0 {
+            return Vec::new();
+        }
+
+        let loss_tolerance = PACKET_LOSS_TOLERANCE.max(self.target_depth as i32);
+        for _ in 0..MAX_FRAMES_PER_TICK {
+            // packet available, stop skipping
+            if self.packets.contains_key(&self.next_sequence_id) {
+                break;
+            }
+
+            let diff = seq_diff(self.last_sequence_id, self.next_sequence_id);
+            if diff < loss_tolerance {
+                self.stats.underruns += 1;
+                return self.synthesize().into_iter().collect();
+            }
+
+            // until a frame is released, skipping only finds the first one
+            if self.last_released.is_some() {
+                self.stats.skipped += 1;
+            }
+            self.next_sequence_id = self.next_sequence_id.wrapping_add(1);
+        }
+
+        let mut consumed = Vec::new();
+        for _ in 0..release {
+            if let Some(frame) = self.packets.remove(&self.next_sequence_id) {
+                self.last_released = Some((frame.timestamp_us, frame.packet.clone()));
+                self.synthetic_run = 0;
+                consumed.push(frame);
+                self.next_sequence_id = self.next_sequence_id.wrapping_add(1);
+            } else {
+                break;
+            }
+        }
+
+        self.prune_synthesized();
+        consumed
+    }
+
+    pub fn size(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Frames the buffer aims to hold, from the measured jitter.
+    pub fn target_depth(&self) -> usize {
+        self.target_depth
+    }
+
+    pub fn jitter_us(&self) -> f64 {
+        self.jitter_us
+    }
+
+    pub fn stats(&self) -> &BufferStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    pub fn last_sequence_id(&self) -> u16 {
+        self.last_sequence_id
+    }
+
+    pub fn last_received_timestamp_us(&self) -> u32 {
+        self.last_received_timestamp_us
+    }
+
+    pub fn next_sequence_id(&self) -> u16 {
+        self.next_sequence_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f64 = 1.0 / 60.0;
+    const TICK_US: u32 = 16_667;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Input {
+        forward: f64,
+        jump: bool,
+    }
+
+    impl SyntheticInput for Input {
+        fn decayed(&self, weight: f64) -> Self {
+            Input { forward: self.forward * weight, ..self.clone() }
+        }
+
+        fn movement_only(&self) -> Self {
+            Input { jump: false, ..self.clone() }
+        }
+    }
+
+    fn input() -> Input {
+        Input { forward: 1.0, jump: true }
+    }
+
+    /// Enqueues `seq` as sent and received on a steady tick, so no jitter is measured.
+    fn enqueue(buffer: &mut JitterBuffer<Input>, seq: u16) -> bool {
+        let sent_us = (u32::from(seq) + 1) * TICK_US;
+        buffer.enqueue(seq, sent_us, u64::from(sent_us), TICK, input())
+    }
+
+    fn drain(buffer: &mut JitterBuffer<Input>, ticks: usize) -> Vec<TimestampedFrame<Input>> {
+        (0..ticks).flat_map(|_| buffer.consume()).collect()
+    }
+
+    #[test]
+    fn releases_one_frame_per_tick_in_order() {
+        let mut buffer = JitterBuffer::new();
+        for seq in 0..4 {
+            assert!(enqueue(&mut buffer, seq));
+            let frames = buffer.consume();
+            assert_eq!(frames.len(), 1);
+            assert_eq!(frames[0].sequence_id, seq);
+            assert!(!frames[0].synthetic);
+        }
+        assert_eq!(buffer.target_depth(), MIN_TARGET_DEPTH);
+        assert_eq!(buffer.jitter_us(), 0.0);
+    }
+
+    #[test]
+    fn rejects_duplicates_and_reordered_frames() {
+        let mut buffer = JitterBuffer::new();
+        assert!(enqueue(&mut buffer, 0));
+        assert!(enqueue(&mut buffer, 2));
+        assert!(!enqueue(&mut buffer, 2));
+        assert!(!enqueue(&mut buffer, 1));
+
+        let released: Vec<u16> = drain(&mut buffer, 4).iter().map(|frame| frame.sequence_id).collect();
+        assert_eq!(released, vec![0]);
+        assert_eq!(buffer.stats().duplicates, 1);
+        assert_eq!(buffer.stats().late_arrivals, 1);
+    }
+
+    #[test]
+    fn skips_a_lost_frame_once_enough_newer_ones_arrive() {
+        let mut buffer = JitterBuffer::new();
+        assert!(enqueue(&mut buffer, 0));
+        assert_eq!(buffer.consume().len(), 1);
+
+        // 1 is lost; the buffer waits until it is PACKET_LOSS_TOLERANCE behind
+        for seq in 2..2 + PACKET_LOSS_TOLERANCE as u16 {
+            assert!(enqueue(&mut buffer, seq));
+        }
+        let released: Vec<u16> = drain(&mut buffer, 8).iter().map(|frame| frame.sequence_id).collect();
+        assert_eq!(released, (2..2 + PACKET_LOSS_TOLERANCE as u16).collect::<Vec<_>>());
+        assert_eq!(buffer.stats().skipped, 1);
+
+        // too late to be used once skipped
+        assert!(!enqueue(&mut buffer, 1));
+    }
+
+    #[test]
+    fn jitter_deepens_the_target() {
+        let mut buffer = JitterBuffer::new();
+        for seq in 0..32u16 {
+            let sent_us = (u32::from(seq) + 1) * TICK_US;
+            let late_us = if seq % 2 == 0 { 0 } else { 2 * u64::from(TICK_US) };
+            buffer.enqueue(seq, sent_us, u64::from(sent_us) + late_us, TICK, input());
+        }
+        assert!(buffer.jitter_us() > f64::from(TICK_US));
+        assert!(buffer.target_depth() > MIN_TARGET_DEPTH);
+    }
+
+    #[test]
+    fn gap_fill_stands_in_until_the_real_frame_arrives() {
+        let mut buffer = JitterBuffer::new();
+        buffer.set_gap_fill(GapFill::RepeatDecay);
+        assert!(enqueue(&mut buffer, 0));
+        assert_eq!(buffer.consume().len(), 1);
+
+        let frames = buffer.consume();
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].synthetic);
+        assert_eq!(frames[0].sequence_id, 1);
+        assert_eq!(frames[0].packet.forward, SYNTHETIC_DECAY);
+        assert_eq!(buffer.stats().underruns, 1);
+
+        // the real 1 goes to reconciliation rather than being released again
+        assert!(enqueue(&mut buffer, 1));
+        assert!(enqueue(&mut buffer, 2));
+        let late = buffer.take_late_frames();
+        assert_eq!(late.len(), 1);
+        assert_eq!(late[0].sequence_id, 1);
+        assert!(!late[0].synthetic);
+
+        let frames = buffer.consume();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].sequence_id, 2);
+        assert!(!frames[0].synthetic);
+    }
+
+    #[test]
+    fn reused_sequence_ids_after_wraparound_are_not_late() {
+        let mut buffer = JitterBuffer::new();
+        buffer.set_gap_fill(GapFill::MarkSynthetic);
+        let enqueue_nth = |buffer: &mut JitterBuffer<Input>, n: u32| {
+            let sent_us = (n + 1) * TICK_US;
+            buffer.enqueue(n as u16, sent_us, u64::from(sent_us), TICK, input())
+        };
+        assert!(enqueue_nth(&mut buffer, 0));
+        assert_eq!(buffer.consume().len(), 1);
+        let frames = buffer.consume();
+        assert!(frames[0].synthetic);
+        assert_eq!(frames[0].sequence_id, 1);
+
+        // 1 is never sent; the stream goes all the way around to 1 again
+        for n in 2..=u32::from(u16::MAX) + 2 {
+            assert!(enqueue_nth(&mut buffer, n));
+            let frames = buffer.consume();
+            assert_eq!(frames.len(), 1);
+            assert_eq!(frames[0].sequence_id, n as u16);
+            assert!(!frames[0].synthetic);
+        }
+        assert!(buffer.take_late_frames().is_empty());
+        assert_eq!(buffer.stats().late_arrivals, 0);
+    }
+
+    #[test]
+    fn gap_fill_gives_up_after_max_synthetic_frames() {
+        let mut buffer = JitterBuffer::new();
+        buffer.set_gap_fill(GapFill::HoldMovement);
+        assert!(enqueue(&mut buffer, 0));
+        assert_eq!(buffer.consume().len(), 1);
+
+        let frames = drain(&mut buffer, MAX_SYNTHETIC_FRAMES as usize + 4);
+        assert_eq!(frames.len(), MAX_SYNTHETIC_FRAMES as usize);
+        assert!(frames.iter().all(|frame| frame.synthetic && !frame.packet.jump));
+    }
+}
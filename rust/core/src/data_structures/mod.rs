@@ -0,0 +1,4 @@
+pub mod buffer_stats;
+pub mod jitter_buffer;
+pub mod peer_slots;
+pub mod seq_ring_buffer;
@@ -0,0 +1,73 @@
+use crate::packet::PeerId;
+
+/// Tracks which peer ids are free on the server.
+///
+/// Ids `0..capacity` are public slots handed to any client. Ids
+/// `capacity..capacity + reserved` are only handed out when the caller allows
+/// it (admins, spectators), so they stay available on a full server.
+pub struct PeerSlots {
+    capacity: usize,
+    reserved: usize,
+    // both stacks are kept in reverse so `pop` hands out the lowest id first
+    available_public: Vec<PeerId>,
+    available_reserved: Vec<PeerId>,
+}
+
+impl PeerSlots {
+    pub const MAX_PEERS: usize = PeerId::MAX as usize + 1;
+
+    pub fn new(capacity: usize, reserved: usize) -> Result<Self, String> {
+        if capacity == 0 {
+            return Err("capacity must be at least 1".to_string());
+        }
+        if capacity + reserved > Self::MAX_PEERS {
+            return Err(format!(
+                "capacity {capacity} + reserved {reserved} exceeds the peer id range of {}",
+                Self::MAX_PEERS
+            ));
+        }
+
+        let public_end = capacity as PeerId;
+        let reserved_end = (capacity + reserved) as PeerId;
+        Ok(Self {
+            capacity,
+            reserved,
+            available_public: (0..public_end).rev().collect(),
+            available_reserved: (public_end..reserved_end).rev().collect(),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn reserved(&self) -> usize {
+        self.reserved
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.capacity + self.reserved - self.available_public.len() - self.available_reserved.len()
+    }
+
+    pub fn has_available(&self, allow_reserved: bool) -> bool {
+        !self.available_public.is_empty() || (allow_reserved && !self.available_reserved.is_empty())
+    }
+
+    pub fn acquire(&mut self, allow_reserved: bool) -> Option<PeerId> {
+        self.available_public.pop().or_else(|| {
+            if allow_reserved {
+                self.available_reserved.pop()
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn release(&mut self, peer_id: PeerId) {
+        if (peer_id as usize) < self.capacity {
+            self.available_public.push(peer_id);
+        } else {
+            self.available_reserved.push(peer_id);
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use super::buffer_stats::BufferStats;
+use crate::math::sequence::{seq_diff, seq_is_newer};
+
+pub const BUFFER_SIZE: usize = 128;
+pub const MAX_DELAY_US: i64 = 150_000i64; // 150ms
+pub const MIN_DELAY_US: i64 = 33_000i64; // 33ms
+
+#[derive(Clone)]
+struct BufferEntry<T> {
+    sequence_id: u16,
+    arrival_timestamp_us: i64,  // when this client received the packet
+    // server_timestamp_us: i64,
+    value: T,
+}
+
+pub struct InterpolationPair<T> {
+    pub from: Option<T>,
+    pub to: Option<T>,
+    pub alpha: f64,
+    pub extrapolation_s: f64,
+    pub is_valid: bool,
+}
+
+impl<T> Default for InterpolationPair<T> {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            alpha: 0.0,
+            extrapolation_s: 0.0,
+            is_valid: false,
+        }
+    }
+}
+
+/// In its `BufferStats`, late arrivals are sequences older than the newest
+/// one; out of window are those too old to keep; skipped are the gaps before
+/// a newer sequence; overflows are entries overwritten while still buffered,
+/// by a sequence `BUFFER_SIZE` apart; underruns are interpolation reads with
+/// nothing to interpolate towards.
+pub struct SequenceRingBuffer<T> {
+    buffer: Vec<Option<BufferEntry<T>>>,
+    size: usize, // must be power of 2
+    mask: u16,
+    oldest_sequence_id: u16,
+    newest_sequence_id: u16,
+    count: usize,
+    buffer_delay_us: i64,
+    stats: BufferStats,
+}
+
+impl<T: Clone> Default for SequenceRingBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> SequenceRingBuffer<T> {
+    pub fn new() -> Self {
+        Self {
+            buffer: vec![None; BUFFER_SIZE],
+            size: BUFFER_SIZE,
+            mask: (BUFFER_SIZE - 1) as u16,
+            oldest_sequence_id: 65535,
+            newest_sequence_id: 65535,
+            count: 0,
+            buffer_delay_us: 0,
+            stats: BufferStats::default(),
+        }
+    }
+
+    /// Stores `value` under `seq_id`. Returns false if the sequence is older
+    /// than the buffer window and was dropped.
+    pub fn insert(&mut self, seq_id: u16, arrival_timestamp_us: i64, value: T) -> bool {
+        let window = seq_diff(self.newest_sequence_id, seq_id);
+        if self.count > 0 && window > self.size as i32 {
+            self.stats.out_of_window += 1;
+            return false;
+        }
+
+        let index = (seq_id & self.mask) as usize;
+        let was_empty = self.buffer[index].is_none();
+        let extends_front = self.count == 0 || seq_is_newer(seq_id, self.newest_sequence_id);
+
+        match &self.buffer[index] {
+            Some(entry) if entry.sequence_id == seq_id => self.stats.duplicates += 1,
+            Some(_) => self.stats.overflows += 1,
+            None => {}
+        }
+        if self.count > 0 {
+            if extends_front {
+                self.stats.skipped += (seq_diff(seq_id, self.newest_sequence_id) - 1) as u64;
+            } else if window > 0 {
+                self.stats.late_arrivals += 1;
+            }
+        }
+
+        // adaptive delay
+        let mut prev_arrival_timestamp_us = None;
+        if self.count > 0 && extends_front {
+            let prev_index = (self.newest_sequence_id & self.mask) as usize;
+
+            if let Some(ref prev_entry) = self.buffer[prev_index] {
+                prev_arrival_timestamp_us = Some(prev_entry.arrival_timestamp_us);
+            }
+        }
+
+        self.buffer[index] = Some(BufferEntry { sequence_id: seq_id, arrival_timestamp_us, /*server_timestamp_us,*/ value });
+
+        if was_empty {
+            self.count += 1;
+        }
+
+        if self.count == 1 {
+            self.oldest_sequence_id = seq_id;
+            self.newest_sequence_id = seq_id;
+            return true;
+        }
+
+        if extends_front {
+            if let Some(prev_arrival_timestamp_us) = prev_arrival_timestamp_us {
+                let delta_us = arrival_timestamp_us - prev_arrival_timestamp_us;
+                // non-positive deltas come from out-of-order arrivals or from
+                // unacked inputs inserted with -1, neither should move the delay
+                if delta_us > 0 {
+                    self.buffer_delay_us = delta_us.clamp(MIN_DELAY_US, MAX_DELAY_US);
+                }
+            }
+            self.newest_sequence_id = seq_id;
+        } else if seq_is_newer(self.oldest_sequence_id, seq_id) {
+            self.oldest_sequence_id = seq_id;
+        }
+
+        true
+    }
+
+    pub fn prune_up_to(&mut self, prune_seq_id: u16) -> usize {
+        let mut pruned = 0;
+        let mut current = self.oldest_sequence_id;
+
+        loop {
+            let index = (current & self.mask) as usize;
+            if self.buffer[index].is_some() && !seq_is_newer(current, prune_seq_id) {
+                self.buffer[index] = None;
+                self.count -= 1;
+                pruned += 1;
+            }
+
+            if current == self.newest_sequence_id {
+                break;
+            }
+
+            current = current.wrapping_add(1);
+
+            if seq_diff(current, self.oldest_sequence_id) > self.size as i32 {
+                break;
+            }
+        }
+
+        pruned
+    }
+
+    pub fn get_starting_at(&self, start_seq_id: u16) -> Vec<T> {
+        let mut result = Vec::new();
+        let mut current = start_seq_id;
+
+        loop {
+            let index = (current & self.mask) as usize;
+            if let Some(ref value) = self.buffer[index] {
+                result.push(value.value.clone());
+            }
+
+            if current == self.newest_sequence_id {
+                break;
+            }
+
+            current = current.wrapping_add(1);
+
+            if seq_diff(current, start_seq_id) > self.size as i32 {
+                break;
+            }
+        }
+
+        result
+    }
+
+    pub fn get_interpolation_pair(&mut self, now_us: i64) -> InterpolationPair<T> {
+        self.stats.sample_occupancy(self.count);
+        let pair = self.find_interpolation_pair(now_us);
+        if pair.to.is_none() {
+            self.stats.underruns += 1;
+        }
+        pair
+    }
+
+    fn find_interpolation_pair(&mut self, now_us: i64) -> InterpolationPair<T> {
+        if self.count >= 3 {
+            let target_time = now_us - self.buffer_delay_us;
+
+            loop {
+                if self.count < 3 {
+                    break;
+                }
+
+                let mut candidate = self.oldest_sequence_id.wrapping_add(1);
+                let mut next_entry = None;
+                while candidate != self.newest_sequence_id.wrapping_add(1) {
+                    let index = (candidate & self.mask) as usize;
+                    if let Some(ref entry) = self.buffer[index] {
+                        next_entry = Some((candidate, entry.arrival_timestamp_us));
+                        break;
+                    }
+                    candidate = candidate.wrapping_add(1);
+                }
+
+                let Some((next_seq, next_arrival_timestamp_us)) = next_entry else { break; };
+
+                if target_time > next_arrival_timestamp_us {
+                    let old_index = (self.oldest_sequence_id & self.mask) as usize;
+                    if self.buffer[old_index].is_some() {
+                        self.buffer[old_index] = None;
+                        self.count -= 1;
+                    }
+                    self.oldest_sequence_id = next_seq;
+                    continue;
+                }
+
+                break;
+            }
+        }
+
+        let mut pair = InterpolationPair::default();
+
+        if self.count == 0 {
+            return pair;
+        }
+
+        if self.count == 1 {
+            let index = (self.oldest_sequence_id & self.mask) as usize;
+            if let Some(ref entry) = self.buffer[index] {
+                pair.from = Some(entry.value.clone());
+                pair.is_valid = true;
+            }
+            return pair;
+        }
+
+        let target_time = now_us - self.buffer_delay_us;
+        let mut current = self.oldest_sequence_id;
+        let mut from: Option<&BufferEntry<T>> = None;
+        let mut to: Option<&BufferEntry<T>> = None;
+
+        loop {
+            let index = (current & self.mask) as usize;
+
+            if let Some(ref entry) = self.buffer[index] {
+                if entry.arrival_timestamp_us <= target_time {
+                    from = Some(entry)
+                } else if to.is_none() {
+                    to = Some(entry);
+                    break;
+                }
+            }
+
+            if current == self.newest_sequence_id {
+                break;
+            }
+
+            current = current.wrapping_add(1);
+
+            if seq_diff(current, self.oldest_sequence_id) > self.size as i32 {
+                break;
+            }
+        }
+
+        if let (Some(from), Some(to)) = (from, to) {
+            let span = (to.arrival_timestamp_us - from.arrival_timestamp_us).max(1);
+            let alpha = ((target_time - from.arrival_timestamp_us) as f64 / span as f64).clamp(0.0, 1.0);
+            let extrapolation_us = (target_time - to.arrival_timestamp_us).max(0);
+            pair.from = Some(from.value.clone());
+            pair.to = Some(to.value.clone());
+            pair.alpha = alpha;
+            pair.extrapolation_s = extrapolation_us as f64 / 1_000_000.0;
+            pair.is_valid = true;
+        } else if let Some(from) = from {
+            pair.from = Some(from.value.clone());
+            pair.is_valid = true;
+        }
+
+        pair
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn oldest_sequence_id(&self) -> u16 {
+        self.oldest_sequence_id
+    }
+
+    pub fn newest_sequence_id(&self) -> u16 {
+        self.newest_sequence_id
+    }
+
+    /// Arrival time of the newest entry, if it is still buffered.
+    pub fn newest_arrival_timestamp_us(&self) -> Option<i64> {
+        if self.count == 0 {
+            return None;
+        }
+        let index = (self.newest_sequence_id & self.mask) as usize;
+        self.buffer[index].as_ref().map(|entry| entry.arrival_timestamp_us)
+    }
+
+    pub fn buffer_delay_us(&self) -> i64 {
+        self.buffer_delay_us
+    }
+
+    pub fn stats(&self) -> &BufferStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_in_sequence_order() {
+        let mut buffer = SequenceRingBuffer::new();
+        assert!(buffer.insert(5, 0, 5));
+        assert!(buffer.insert(7, 0, 7));
+        assert!(buffer.insert(6, 0, 6));
+        assert!(buffer.insert(7, 0, 7));
+
+        assert_eq!(buffer.get_starting_at(5), vec![5, 6, 7]);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.stats().skipped, 1);
+        assert_eq!(buffer.stats().late_arrivals, 1);
+        assert_eq!(buffer.stats().duplicates, 1);
+    }
+
+    #[test]
+    fn sequences_wrap_around() {
+        let mut buffer = SequenceRingBuffer::new();
+        for seq in [65534, 0, 65535, 1] {
+            assert!(buffer.insert(seq, 0, seq));
+        }
+
+        assert_eq!(buffer.oldest_sequence_id(), 65534);
+        assert_eq!(buffer.newest_sequence_id(), 1);
+        assert_eq!(buffer.get_starting_at(65534), vec![65534, 65535, 0, 1]);
+        assert_eq!(buffer.prune_up_to(65535), 2);
+        assert_eq!(buffer.get_starting_at(0), vec![0, 1]);
+    }
+
+    #[test]
+    fn drops_sequences_older_than_the_window() {
+        let mut buffer = SequenceRingBuffer::new();
+        assert!(buffer.insert(10, 0, 10));
+        assert!(buffer.insert(10 + BUFFER_SIZE as u16 + 1, 0, 0));
+        assert!(!buffer.insert(10, 0, 10));
+        assert_eq!(buffer.stats().out_of_window, 1);
+        assert_eq!(buffer.stats().skipped, BUFFER_SIZE as u64);
+    }
+
+    #[test]
+    fn interpolates_between_arrivals_behind_the_delay() {
+        let mut buffer = SequenceRingBuffer::new();
+        buffer.insert(0, 0, 0.0);
+        buffer.insert(1, 50_000, 1.0);
+        buffer.insert(2, 100_000, 2.0);
+        assert_eq!(buffer.buffer_delay_us(), 50_000);
+
+        let pair = buffer.get_interpolation_pair(125_000);
+        assert!(pair.is_valid);
+        assert_eq!(pair.from, Some(1.0));
+        assert_eq!(pair.to, Some(2.0));
+        assert_eq!(pair.alpha, 0.5);
+        assert_eq!(buffer.len(), 2);
+
+        // past the newest arrival there is nothing to interpolate towards
+        let pair = buffer.get_interpolation_pair(200_000);
+        assert_eq!(pair.from, Some(2.0));
+        assert_eq!(pair.to, None);
+        assert_eq!(buffer.stats().underruns, 1);
+    }
+}
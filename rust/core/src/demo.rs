@@ -0,0 +1,348 @@
+//! Match demos: the authoritative stream a server broadcasts, grouped by tick,
+//! with periodic keyframes holding the full world so playback can seek.
+//!
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! header:  magic "BRDEMO" | version u16 | tick_rate u32
+//! frame:   tick u32 | flags u8 | count u16 | count * (len u16 | Packet::encode bytes)
+//! ```
+//!
+//! A keyframe for tick `t` is written after that tick's delta frame and
+//! describes the world once it has been applied, so seeking to `t` replays the
+//! keyframe and then only deltas newer than `t`.
+
+use crate::packet::{IdAssignmentPacketWire, LobbyStatePacketWire, Packet, PeerId, PlayerStatePacketWire};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+pub const MAGIC: &[u8; 6] = b"BRDEMO";
+pub const VERSION: u16 = 1;
+/// Id handed to the viewer in keyframes so client code sees every recorded
+/// player as remote. Only collides on a server using the full peer id range.
+pub const SPECTATOR_ID: PeerId = PeerId::MAX;
+
+const FLAG_KEYFRAME: u8 = 1 << 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DemoFrame {
+    pub tick: u32,
+    pub keyframe: bool,
+    pub packets: Vec<Packet>,
+}
+
+#[derive(Debug)]
+pub enum DemoError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Corrupt(String),
+}
+
+impl fmt::Display for DemoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DemoError::Io(e) => write!(f, "demo i/o error: {e}"),
+            DemoError::BadMagic => write!(f, "not a demo file"),
+            DemoError::UnsupportedVersion(version) => {
+                write!(f, "unsupported demo version {version} (expected {VERSION})")
+            }
+            DemoError::Corrupt(reason) => write!(f, "corrupt demo: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for DemoError {
+    fn from(e: io::Error) -> Self {
+        DemoError::Io(e)
+    }
+}
+
+/// Players known at a point in the match and their latest state. `None` means
+/// the player has been assigned an id but no state has been broadcast yet.
+#[derive(Default)]
+struct World {
+    players: BTreeMap<PeerId, Option<PlayerStatePacketWire>>,
+    lobby: Option<LobbyStatePacketWire>,
+}
+
+impl World {
+    fn apply(&mut self, packet: &Packet) {
+        match packet {
+            Packet::IdAssignment(assignment) => {
+                for id in assignment.remote_ids.iter().chain(std::iter::once(&assignment.id)) {
+                    self.players.entry(*id).or_insert(None);
+                }
+            }
+            Packet::PlayerState(state) => {
+                self.players.insert(state.player_id, Some(state.clone()));
+            }
+            Packet::PlayerDisconnected(disconnected) => {
+                self.players.remove(&disconnected.player_id);
+            }
+            Packet::LobbyState(lobby) => {
+                self.lobby = Some(lobby.clone());
+            }
+            Packet::PlayerInput(_) | Packet::Chat(_) | Packet::Null(_) | Packet::LobbyReady(_) => {}
+        }
+    }
+
+    fn snapshot(&self) -> Vec<Packet> {
+        let mut packets = vec![Packet::IdAssignment(IdAssignmentPacketWire {
+            id: SPECTATOR_ID,
+            remote_ids: self.players.keys().copied().collect(),
+        })];
+        packets.extend(self.players.values().flatten().cloned().map(Packet::PlayerState));
+        packets.extend(self.lobby.clone().map(Packet::LobbyState));
+        packets
+    }
+}
+
+/// Whether `packet` belongs in a demo. Inputs and ready flags are client to
+/// server only, and null packets carry nothing a viewer could use.
+fn is_recorded(packet: &Packet) -> bool {
+    !matches!(packet, Packet::PlayerInput(_) | Packet::Null(_) | Packet::LobbyReady(_))
+}
+
+pub struct DemoWriter<W: Write> {
+    out: W,
+    keyframe_interval: u32,
+    last_keyframe_tick: Option<u32>,
+    pending: Vec<Packet>,
+    world: World,
+}
+
+impl DemoWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, tick_rate: u32, keyframe_interval: u32) -> Result<Self, DemoError> {
+        Self::new(BufWriter::new(File::create(path)?), tick_rate, keyframe_interval)
+    }
+}
+
+impl<W: Write> DemoWriter<W> {
+    /// `keyframe_interval` is in ticks; seeking replays at most that many
+    /// delta frames.
+    pub fn new(mut out: W, tick_rate: u32, keyframe_interval: u32) -> Result<Self, DemoError> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&tick_rate.to_le_bytes())?;
+        Ok(Self {
+            out,
+            keyframe_interval: keyframe_interval.max(1),
+            last_keyframe_tick: None,
+            pending: Vec::new(),
+            world: World::default(),
+        })
+    }
+
+    /// Queues a broadcast packet for the current tick.
+    pub fn record(&mut self, packet: &Packet) {
+        if is_recorded(packet) {
+            self.world.apply(packet);
+            self.pending.push(packet.clone());
+        }
+    }
+
+    /// Writes everything recorded since the last call as `tick`, followed by a
+    /// keyframe if one is due. The first tick always gets a keyframe.
+    pub fn end_tick(&mut self, tick: u32) -> Result<(), DemoError> {
+        if !self.pending.is_empty() {
+            let packets = std::mem::take(&mut self.pending);
+            self.write_frame(tick, false, &packets)?;
+        }
+
+        let keyframe_due = match self.last_keyframe_tick {
+            Some(last) => tick.wrapping_sub(last) >= self.keyframe_interval,
+            None => true,
+        };
+        if keyframe_due {
+            let snapshot = self.world.snapshot();
+            self.write_frame(tick, true, &snapshot)?;
+            self.last_keyframe_tick = Some(tick);
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), DemoError> {
+        Ok(self.out.flush()?)
+    }
+
+    fn write_frame(&mut self, tick: u32, keyframe: bool, packets: &[Packet]) -> Result<(), DemoError> {
+        let count = u16::try_from(packets.len())
+            .map_err(|_| DemoError::Corrupt(format!("{} packets in tick {tick}", packets.len())))?;
+
+        let mut frame = Vec::with_capacity(7 + packets.len() * 64);
+        frame.extend_from_slice(&tick.to_le_bytes());
+        frame.push(if keyframe { FLAG_KEYFRAME } else { 0 });
+        frame.extend_from_slice(&count.to_le_bytes());
+        for packet in packets {
+            let bytes = packet.encode();
+            let len = u16::try_from(bytes.len())
+                .map_err(|_| DemoError::Corrupt(format!("{:?} packet of {} bytes", packet.id(), bytes.len())))?;
+            frame.extend_from_slice(&len.to_le_bytes());
+            frame.extend_from_slice(&bytes);
+        }
+
+        self.out.write_all(&frame)?;
+        Ok(())
+    }
+}
+
+/// A demo loaded into memory, frames in tick order.
+pub struct Demo {
+    pub tick_rate: u32,
+    pub frames: Vec<DemoFrame>,
+    keyframes: Vec<usize>,
+}
+
+impl Demo {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DemoError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a whole demo. A file cut off mid-frame (e.g. the server crashed)
+    /// keeps every complete frame before the cut, but one without a complete
+    /// keyframe has nothing to play and is corrupt.
+    pub fn read(mut input: impl Read) -> Result<Self, DemoError> {
+        let mut header = [0u8; 12];
+        input.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => DemoError::BadMagic,
+            _ => DemoError::Io(e),
+        })?;
+        if &header[0..6] != MAGIC {
+            return Err(DemoError::BadMagic);
+        }
+        let version = u16::from_le_bytes([header[6], header[7]]);
+        if version != VERSION {
+            return Err(DemoError::UnsupportedVersion(version));
+        }
+        let tick_rate = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+
+        let mut frames = Vec::new();
+        let mut keyframes = Vec::new();
+        let mut cursor = Cursor { bytes: &bytes, at: 0 };
+        while !cursor.is_at_end() {
+            let Some(frame) = cursor.frame()? else {
+                break;
+            };
+            if frame.keyframe {
+                keyframes.push(frames.len());
+            }
+            frames.push(frame);
+        }
+        if keyframes.is_empty() {
+            return Err(DemoError::Corrupt("no complete keyframe".to_string()));
+        }
+
+        Ok(Self { tick_rate, frames, keyframes })
+    }
+
+    pub fn first_tick(&self) -> u32 {
+        self.frames.first().map_or(0, |frame| frame.tick)
+    }
+
+    pub fn last_tick(&self) -> u32 {
+        self.frames.last().map_or(0, |frame| frame.tick)
+    }
+
+    /// Index of the last keyframe at or before `tick`.
+    pub fn keyframe_at_or_before(&self, tick: u32) -> Option<usize> {
+        let after = self.keyframes.partition_point(|index| self.frames[*index].tick <= tick);
+        after.checked_sub(1).map(|i| self.keyframes[i])
+    }
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Cursor<'_> {
+    fn is_at_end(&self) -> bool {
+        self.at >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let slice = self.bytes.get(self.at..self.at + len)?;
+        self.at += len;
+        Some(slice)
+    }
+
+    /// `Ok(None)` for a truncated trailing frame, `Err` for one that is
+    /// complete but undecodable.
+    fn frame(&mut self) -> Result<Option<DemoFrame>, DemoError> {
+        let Some(header) = self.take(7) else {
+            return Ok(None);
+        };
+        let tick = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let keyframe = header[4] & FLAG_KEYFRAME != 0;
+        let count = u16::from_le_bytes([header[5], header[6]]);
+
+        let mut packets = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let Some(len) = self.take(2) else {
+                return Ok(None);
+            };
+            let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+            let Some(bytes) = self.take(len) else {
+                return Ok(None);
+            };
+            let packet = Packet::decode(bytes).map_err(|e| DemoError::Corrupt(format!("tick {tick}: {e}")))?;
+            packets.push(packet);
+        }
+
+        Ok(Some(DemoFrame { tick, keyframe, packets }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ChatPacketWire;
+
+    fn chat(message: &str) -> Packet {
+        Packet::Chat(ChatPacketWire { username: "host".to_string(), message: message.to_string() })
+    }
+
+    fn record(ticks: u32) -> Vec<u8> {
+        let mut writer = DemoWriter::new(Vec::new(), 60, 4).unwrap();
+        for tick in 0..ticks {
+            writer.record(&chat(&tick.to_string()));
+            writer.end_tick(tick).unwrap();
+        }
+        writer.out
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let demo = Demo::read(record(10).as_slice()).unwrap();
+        assert_eq!(demo.tick_rate, 60);
+        assert_eq!((demo.first_tick(), demo.last_tick()), (0, 9));
+        assert_eq!(demo.frames[0].packets, vec![chat("0")]);
+        assert_eq!(demo.keyframe_at_or_before(0), Some(1));
+        assert_eq!(demo.frames[demo.keyframe_at_or_before(6).unwrap()].tick, 4);
+    }
+
+    #[test]
+    fn truncated_demo_keeps_complete_frames() {
+        let bytes = record(10);
+        let demo = Demo::read(&bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(demo.last_tick(), 8);
+    }
+
+    #[test]
+    fn demo_without_a_keyframe_is_corrupt() {
+        let bytes = record(1);
+        assert!(matches!(Demo::read(&bytes[..12]), Err(DemoError::Corrupt(_))));
+        assert!(matches!(Demo::read(&bytes[..bytes.len() - 1]), Err(DemoError::Corrupt(_))));
+        assert!(matches!(Demo::read(&bytes[..4]), Err(DemoError::BadMagic)));
+    }
+}
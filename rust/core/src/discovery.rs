@@ -0,0 +1,181 @@
+//! LAN server discovery. Servers broadcast a small beacon to
+//! `DISCOVERY_PORT` every so often; clients listening there keep a list of
+//! servers seen recently and forget ones that go quiet.
+//!
+//! ```text
+//! beacon:  magic "BRLAN" | postcard Beacon
+//! ```
+//!
+//! The game port is in the beacon, the address is the datagram's source.
+
+use crate::packet::PROTOCOL_VERSION;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+pub const MAGIC: &[u8; 5] = b"BRLAN";
+pub const DISCOVERY_PORT: u16 = 45870;
+pub const DEFAULT_BEACON_INTERVAL: Duration = Duration::from_secs(1);
+/// A server is lost after missing this many beacons' worth of time.
+pub const DEFAULT_EXPIRY: Duration = Duration::from_secs(5);
+
+const MAX_BEACON_LEN: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Beacon {
+    pub protocol_version: u16,
+    pub name: String,
+    pub map: String,
+    pub player_count: u16,
+    pub capacity: u16,
+    /// Game port, which is not the port the beacon came from.
+    pub port: u16,
+}
+
+impl Beacon {
+    pub fn new(name: String, map: String, player_count: u16, capacity: u16, port: u16) -> Self {
+        Self { protocol_version: PROTOCOL_VERSION, name, map, player_count, capacity, port }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(postcard::to_allocvec(self).expect("beacons always serialize"));
+        bytes
+    }
+
+    /// `None` for anything that isn't one of our beacons.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC.as_slice())?;
+        postcard::from_bytes(body).ok()
+    }
+}
+
+/// Sends a server's beacon to the LAN broadcast address.
+pub struct BeaconBroadcaster {
+    socket: UdpSocket,
+    target: SocketAddr,
+    interval: Duration,
+    last_sent: Option<Instant>,
+    beacon: Beacon,
+}
+
+impl BeaconBroadcaster {
+    pub fn new(beacon: Beacon, discovery_port: u16, interval: Duration) -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            target: SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), discovery_port),
+            interval,
+            last_sent: None,
+            beacon,
+        })
+    }
+
+    pub fn beacon(&self) -> &Beacon {
+        &self.beacon
+    }
+
+    /// Replaces the advertised info and sends it right away if it changed.
+    pub fn set_beacon(&mut self, beacon: Beacon) {
+        if beacon != self.beacon {
+            self.beacon = beacon;
+            self.last_sent = None;
+        }
+    }
+
+    /// Sends the beacon if one is due. Returns whether it was sent.
+    pub fn update(&mut self, now: Instant) -> io::Result<bool> {
+        if self.last_sent.is_some_and(|sent| now.duration_since(sent) < self.interval) {
+            return Ok(false);
+        }
+        self.last_sent = Some(now);
+        match self.socket.send_to(&self.beacon.encode(), self.target) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    /// Where to connect: the beacon's source address with its game port.
+    pub addr: SocketAddr,
+    pub beacon: Beacon,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryEvent {
+    Discovered(DiscoveredServer),
+    /// A known server's info changed, e.g. its player count.
+    Updated(DiscoveredServer),
+    Lost(DiscoveredServer),
+}
+
+/// Listens for beacons. Several listeners on one machine can share the
+/// discovery port, so more than one game instance can browse at a time.
+pub struct DiscoveryListener {
+    socket: UdpSocket,
+    expiry: Duration,
+    servers: HashMap<SocketAddr, (DiscoveredServer, Instant)>,
+}
+
+impl DiscoveryListener {
+    pub fn new(discovery_port: u16, expiry: Duration) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), discovery_port).into())?;
+        Ok(Self { socket: socket.into(), expiry, servers: HashMap::new() })
+    }
+
+    /// Servers currently known, in no particular order.
+    pub fn servers(&self) -> impl Iterator<Item = &DiscoveredServer> {
+        self.servers.values().map(|(server, _)| server)
+    }
+
+    /// Reads every pending beacon and expires quiet servers. Beacons from
+    /// other protocol versions are still reported so menus can grey them out.
+    pub fn update(&mut self, now: Instant) -> Vec<DiscoveryEvent> {
+        let mut events = Vec::new();
+        let mut buffer = [0u8; MAX_BEACON_LEN];
+        loop {
+            let (len, source) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            let Some(beacon) = Beacon::decode(&buffer[..len]) else {
+                continue;
+            };
+
+            let addr = SocketAddr::new(source.ip(), beacon.port);
+            let server = DiscoveredServer { addr, beacon };
+            match self.servers.insert(addr, (server.clone(), now)) {
+                None => events.push(DiscoveryEvent::Discovered(server)),
+                Some((previous, _)) if previous != server => events.push(DiscoveryEvent::Updated(server)),
+                Some(_) => {}
+            }
+        }
+
+        let expiry = self.expiry;
+        self.servers.retain(|_, (server, last_seen)| {
+            let alive = now.duration_since(*last_seen) < expiry;
+            if !alive {
+                events.push(DiscoveryEvent::Lost(server.clone()));
+            }
+            alive
+        });
+        events
+    }
+}
@@ -0,0 +1,18 @@
+//! Engine-free half of the networking stack: packet wire formats and codec,
+//! sequence math, buffers and server-side bookkeeping. Nothing in here may
+//! depend on `godot`; the extension crate wraps these types for GDScript.
+
+pub mod capture;
+pub mod config;
+pub mod data_structures;
+pub mod demo;
+pub mod discovery;
+pub mod link_conditioner;
+pub mod lobby;
+pub mod log;
+pub mod math;
+pub mod metrics;
+pub mod packet;
+pub mod registry;
+pub mod server_query;
+pub mod transport;
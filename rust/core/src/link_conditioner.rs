@@ -0,0 +1,196 @@
+//! Seeded stand-in for the GNS fake-network settings. A `LinkConditioner`
+//! sits between encoding and delivery for one direction of a link and
+//! applies lag, jitter, loss, duplication and reordering from its own `Rng`,
+//! so the same seed and send schedule always produce the same deliveries.
+//! Use two for a duplex link, as `ConditionedTransport` does around a
+//! transport.
+
+use crate::config::LinkSimulationConfig;
+use crate::math::rng::Rng;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+/// Impairments for one direction. Times are in milliseconds, the rest are
+/// percentages, mirroring `LinkSimulationConfig`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkProfile {
+    pub lag_ms: u32,
+    /// Extra delay in `[0, jitter_ms)`. Jitter alone never reorders.
+    pub jitter_ms: u32,
+    pub loss_pct: f64,
+    pub dup_pct: f64,
+    /// Duplicates arrive up to this much after the original.
+    pub dup_ms_max: u32,
+    pub reorder_pct: f64,
+    /// Extra delay for reordered packets, letting later ones overtake them.
+    pub reorder_ms: u32,
+}
+
+impl LinkProfile {
+    /// Client to server half of a server config's link settings.
+    pub fn recv(config: &LinkSimulationConfig) -> Self {
+        Self {
+            lag_ms: config.lag_recv_ms,
+            jitter_ms: config.jitter_recv_ms,
+            loss_pct: config.loss_recv_pct.into(),
+            dup_pct: config.dup_recv_pct.into(),
+            dup_ms_max: config.dup_ms_max,
+            reorder_pct: config.reorder_recv_pct.into(),
+            reorder_ms: config.reorder_ms,
+        }
+    }
+
+    /// Server to client half of a server config's link settings.
+    pub fn send(config: &LinkSimulationConfig) -> Self {
+        Self {
+            lag_ms: config.lag_send_ms,
+            jitter_ms: config.jitter_send_ms,
+            loss_pct: config.loss_send_pct.into(),
+            dup_pct: config.dup_send_pct.into(),
+            dup_ms_max: config.dup_ms_max,
+            reorder_pct: config.reorder_send_pct.into(),
+            reorder_ms: config.reorder_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub sent: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub delivered: u64,
+}
+
+struct InFlight<T> {
+    deliver_at_us: u64,
+    // send order, breaks ties so equal delivery times stay FIFO
+    order: u64,
+    payload: T,
+}
+
+impl<T> PartialEq for InFlight<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for InFlight<T> {}
+
+impl<T> PartialOrd for InFlight<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for InFlight<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deliver_at_us, self.order).cmp(&(other.deliver_at_us, other.order))
+    }
+}
+
+pub struct LinkConditioner<T> {
+    profile: LinkProfile,
+    rng: Rng,
+    in_flight: BinaryHeap<Reverse<InFlight<T>>>,
+    next_order: u64,
+    // latest delivery time handed to an in-order packet
+    in_order_deliver_at_us: u64,
+    stats: LinkStats,
+}
+
+impl<T: Clone> LinkConditioner<T> {
+    pub fn new(profile: LinkProfile, seed: u64) -> Self {
+        Self {
+            profile,
+            rng: Rng::new(seed),
+            in_flight: BinaryHeap::new(),
+            next_order: 0,
+            in_order_deliver_at_us: 0,
+            stats: LinkStats::default(),
+        }
+    }
+
+    /// A link that delivers everything immediately and in order.
+    pub fn perfect() -> Self {
+        Self::new(LinkProfile::default(), 0)
+    }
+
+    pub fn profile(&self) -> &LinkProfile {
+        &self.profile
+    }
+
+    /// Changes impairments for packets sent from now on; packets already in
+    /// flight keep their delivery times.
+    pub fn set_profile(&mut self, profile: LinkProfile) {
+        self.profile = profile;
+    }
+
+    /// Puts `payload` on the wire at `now_us`. Returns false if it was lost.
+    pub fn send(&mut self, now_us: u64, payload: T) -> bool {
+        self.stats.sent += 1;
+
+        if self.rng.chance(self.profile.loss_pct / 100.0) {
+            self.stats.dropped += 1;
+            return false;
+        }
+
+        let lag_us = u64::from(self.profile.lag_ms) * 1000;
+        let jitter_us = self.rng.range_u64(0, u64::from(self.profile.jitter_ms) * 1000);
+        let mut deliver_at_us = now_us + lag_us + jitter_us;
+
+        if self.rng.chance(self.profile.reorder_pct / 100.0) {
+            deliver_at_us += u64::from(self.profile.reorder_ms) * 1000;
+            self.stats.reordered += 1;
+        } else {
+            deliver_at_us = deliver_at_us.max(self.in_order_deliver_at_us);
+            self.in_order_deliver_at_us = deliver_at_us;
+        }
+
+        if self.rng.chance(self.profile.dup_pct / 100.0) {
+            let dup_delay_us = self.rng.range_u64(0, u64::from(self.profile.dup_ms_max) * 1000 + 1);
+            self.push(deliver_at_us + dup_delay_us, payload.clone());
+            self.stats.duplicated += 1;
+        }
+
+        self.push(deliver_at_us, payload);
+        true
+    }
+
+    /// Everything due at or before `now_us`, in delivery order.
+    pub fn poll(&mut self, now_us: u64) -> Vec<T> {
+        let mut delivered = Vec::new();
+        while let Some(Reverse(next)) = self.in_flight.peek() {
+            if next.deliver_at_us > now_us {
+                break;
+            }
+            let Some(Reverse(next)) = self.in_flight.pop() else {
+                break;
+            };
+            delivered.push(next.payload);
+        }
+        self.stats.delivered += delivered.len() as u64;
+        delivered
+    }
+
+    /// Delivery time of the next packet in flight, for stepping simulated clocks.
+    pub fn next_delivery_us(&self) -> Option<u64> {
+        self.in_flight.peek().map(|Reverse(next)| next.deliver_at_us)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    fn push(&mut self, deliver_at_us: u64, payload: T) {
+        self.in_flight.push(Reverse(InFlight { deliver_at_us, order: self.next_order, payload }));
+        self.next_order += 1;
+    }
+}
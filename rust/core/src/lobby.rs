@@ -0,0 +1,251 @@
+//! Pre-match lobby: players join, ready up, and once enough are ready a
+//! countdown runs before the match starts. The server owns a `Lobby` and
+//! replicates `Lobby::state` to clients as a `LobbyState` packet whenever it
+//! changes.
+
+use crate::packet::{LobbyStatePacketWire, PeerId};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_COUNTDOWN: Duration = Duration::from_secs(10);
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LobbyPhase {
+    /// Fewer than `min_players` are ready.
+    Waiting = 0,
+    Countdown = 1,
+    /// The match has started; nobody else joins the lobby.
+    InProgress = 2,
+}
+
+impl LobbyPhase {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(LobbyPhase::Waiting),
+            1 => Some(LobbyPhase::Countdown),
+            2 => Some(LobbyPhase::InProgress),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LobbyPhase::Waiting => "waiting",
+            LobbyPhase::Countdown => "countdown",
+            LobbyPhase::InProgress => "in_progress",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LobbySettings {
+    /// Ready players needed to start the countdown.
+    pub min_players: u16,
+    /// Players the lobby takes; later joiners only watch.
+    pub max_players: u16,
+    pub countdown: Duration,
+}
+
+impl LobbySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_players == 0 || self.min_players > self.max_players {
+            return Err(format!(
+                "min_players {} must be between 1 and max_players {}",
+                self.min_players, self.max_players
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Lobby {
+    settings: LobbySettings,
+    phase: LobbyPhase,
+    // peer -> ready
+    players: BTreeMap<PeerId, bool>,
+    countdown_ends: Option<Instant>,
+    changed: bool,
+}
+
+impl Lobby {
+    pub fn new(settings: LobbySettings) -> Result<Self, String> {
+        settings.validate()?;
+        Ok(Self { settings, phase: LobbyPhase::Waiting, players: BTreeMap::new(), countdown_ends: None, changed: true })
+    }
+
+    pub fn settings(&self) -> &LobbySettings {
+        &self.settings
+    }
+
+    pub fn phase(&self) -> LobbyPhase {
+        self.phase
+    }
+
+    pub fn contains(&self, peer_id: PeerId) -> bool {
+        self.players.contains_key(&peer_id)
+    }
+
+    pub fn ready_count(&self) -> usize {
+        self.players.values().filter(|ready| **ready).count()
+    }
+
+    /// Adds `peer_id`, not yet ready. Refused once the match is running or
+    /// the lobby is full.
+    pub fn join(&mut self, peer_id: PeerId) -> bool {
+        if self.phase == LobbyPhase::InProgress || self.players.len() >= usize::from(self.settings.max_players) {
+            return false;
+        }
+        self.changed |= self.players.insert(peer_id, false).is_none();
+        true
+    }
+
+    pub fn leave(&mut self, peer_id: PeerId) {
+        self.changed |= self.players.remove(&peer_id).is_some();
+    }
+
+    /// Ignored for peers that aren't in the lobby or once the match started.
+    pub fn set_ready(&mut self, peer_id: PeerId, ready: bool) {
+        if self.phase == LobbyPhase::InProgress {
+            return;
+        }
+        if let Some(current) = self.players.get_mut(&peer_id)
+            && *current != ready
+        {
+            *current = ready;
+            self.changed = true;
+        }
+    }
+
+    /// Starts or cancels the countdown and starts the match when it runs
+    /// out. Returns the new phase if it changed.
+    pub fn update(&mut self, now: Instant) -> Option<LobbyPhase> {
+        let enough_ready = self.ready_count() >= usize::from(self.settings.min_players);
+        let next = match (self.phase, self.countdown_ends) {
+            (LobbyPhase::Waiting, _) if enough_ready => {
+                self.countdown_ends = Some(now + self.settings.countdown);
+                LobbyPhase::Countdown
+            }
+            (LobbyPhase::Countdown, _) if !enough_ready => {
+                self.countdown_ends = None;
+                LobbyPhase::Waiting
+            }
+            (LobbyPhase::Countdown, Some(ends)) if now >= ends => {
+                self.countdown_ends = None;
+                LobbyPhase::InProgress
+            }
+            (phase, _) => phase,
+        };
+        if next == self.phase {
+            return None;
+        }
+        self.phase = next;
+        self.changed = true;
+        Some(next)
+    }
+
+    /// Makes the next `take_changed` report a change, e.g. so a peer that
+    /// joined as a spectator still gets the state.
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
+    }
+
+    /// Whether anything clients see changed since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn state(&self, now: Instant) -> LobbyStatePacketWire {
+        let countdown_ms = self.countdown_ends.map_or(0, |ends| ends.saturating_duration_since(now).as_millis() as u32);
+        LobbyStatePacketWire {
+            phase: self.phase as u8,
+            countdown_ms,
+            min_players: self.settings.min_players,
+            max_players: self.settings.max_players,
+            players: self.players.keys().copied().collect(),
+            ready: self.players.iter().filter(|(_, ready)| **ready).map(|(peer_id, _)| *peer_id).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNTDOWN: Duration = Duration::from_secs(3);
+
+    fn lobby(min_players: u16, max_players: u16) -> Lobby {
+        Lobby::new(LobbySettings { min_players, max_players, countdown: COUNTDOWN }).unwrap()
+    }
+
+    #[test]
+    fn countdown_starts_once_enough_are_ready_and_then_starts_the_match() {
+        let now = Instant::now();
+        let mut lobby = lobby(2, 4);
+        assert!(lobby.join(1));
+        assert!(lobby.join(2));
+        lobby.set_ready(1, true);
+        assert_eq!(lobby.update(now), None);
+
+        lobby.set_ready(2, true);
+        assert_eq!(lobby.update(now), Some(LobbyPhase::Countdown));
+        assert_eq!(lobby.state(now).countdown_ms, COUNTDOWN.as_millis() as u32);
+        assert_eq!(lobby.update(now + COUNTDOWN / 2), None);
+
+        assert_eq!(lobby.update(now + COUNTDOWN), Some(LobbyPhase::InProgress));
+        assert_eq!(lobby.state(now + COUNTDOWN).countdown_ms, 0);
+    }
+
+    #[test]
+    fn countdown_cancels_when_a_player_unreadies_or_leaves() {
+        let now = Instant::now();
+        let mut lobby = lobby(2, 4);
+        for peer_id in [1, 2] {
+            lobby.join(peer_id);
+            lobby.set_ready(peer_id, true);
+        }
+        assert_eq!(lobby.update(now), Some(LobbyPhase::Countdown));
+
+        lobby.set_ready(2, false);
+        assert_eq!(lobby.update(now), Some(LobbyPhase::Waiting));
+        assert_eq!(lobby.state(now).countdown_ms, 0);
+
+        lobby.set_ready(2, true);
+        assert_eq!(lobby.update(now), Some(LobbyPhase::Countdown));
+        lobby.leave(1);
+        assert_eq!(lobby.update(now), Some(LobbyPhase::Waiting));
+        assert_eq!(lobby.update(now + COUNTDOWN), None);
+    }
+
+    #[test]
+    fn joins_are_refused_once_the_match_is_in_progress() {
+        let now = Instant::now();
+        let mut lobby = lobby(1, 4);
+        lobby.join(1);
+        lobby.set_ready(1, true);
+        lobby.update(now);
+        assert_eq!(lobby.update(now + COUNTDOWN), Some(LobbyPhase::InProgress));
+
+        assert!(!lobby.join(2));
+        assert!(!lobby.contains(2));
+        lobby.set_ready(1, false);
+        assert_eq!(lobby.ready_count(), 1);
+    }
+
+    #[test]
+    fn joins_past_max_players_are_refused() {
+        let mut lobby = lobby(1, 2);
+        assert!(lobby.join(1));
+        assert!(lobby.join(2));
+        assert!(!lobby.join(3));
+        assert_eq!(lobby.state(Instant::now()).players, vec![1, 2]);
+
+        // a seat frees up when someone leaves
+        lobby.leave(1);
+        assert!(lobby.join(3));
+        assert!(lobby.contains(3));
+    }
+}
@@ -0,0 +1,317 @@
+//! Structured, leveled logging for the networking stack. Records carry a
+//! severity, a category and optionally the peer they concern. Anything that
+//! can reach the process-wide `logger()` may log, including GNS's debug
+//! callback on its own thread; the extension drains the queue every frame
+//! and hands records to Godot. An optional rotating file gets every record
+//! as it is logged.
+
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU8, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::packet::PeerId;
+
+/// Records waiting for the extension beyond this drop the oldest.
+const QUEUE_CAPACITY: usize = 4096;
+pub const DEFAULT_FILE_MAX_BYTES: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_FILE_KEEP: u32 = 5;
+
+/// A record's severity, or a category's threshold. `Off` and `Everything`
+/// only make sense as thresholds.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warning,
+    Info,
+    Verbose,
+    Debug,
+    Everything,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 7] = [
+        LogLevel::Off,
+        LogLevel::Error,
+        LogLevel::Warning,
+        LogLevel::Info,
+        LogLevel::Verbose,
+        LogLevel::Debug,
+        LogLevel::Everything,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(value)).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warning => "warning",
+            LogLevel::Info => "info",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Debug => "debug",
+            LogLevel::Everything => "everything",
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warning" | "warn" => Ok(LogLevel::Warning),
+            "info" => Ok(LogLevel::Info),
+            "verbose" => Ok(LogLevel::Verbose),
+            "debug" => Ok(LogLevel::Debug),
+            "everything" | "all" => Ok(LogLevel::Everything),
+            _ => Err(format!("unknown log level '{value}'")),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogCategory {
+    /// GameNetworkingSockets' own debug output.
+    Gns,
+    Server,
+    Client,
+    /// Encoding, decoding and recording of packets.
+    Packet,
+}
+
+impl LogCategory {
+    pub const ALL: [LogCategory; 4] = [LogCategory::Gns, LogCategory::Server, LogCategory::Client, LogCategory::Packet];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogCategory::Gns => "gns",
+            LogCategory::Server => "server",
+            LogCategory::Client => "client",
+            LogCategory::Packet => "packet",
+        }
+    }
+}
+
+impl FromStr for LogCategory {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|category| category.name().eq_ignore_ascii_case(value.trim()))
+            .ok_or_else(|| format!("unknown log category '{value}'"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub category: LogCategory,
+    pub peer_id: Option<PeerId>,
+    pub message: String,
+    pub time: SystemTime,
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.category.name(), self.level.name().to_ascii_uppercase())?;
+        if let Some(peer_id) = self.peer_id {
+            write!(f, " peer {peer_id}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Appends to `path`, moving it to `path.1` (and `path.1` to `path.2`, ...)
+/// once it grows past `max_bytes`. At most `keep` old files are kept.
+pub struct RotatingLogFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: u32,
+    out: BufWriter<File>,
+    written: u64,
+}
+
+impl RotatingLogFile {
+    pub fn open(path: impl AsRef<Path>, max_bytes: u64, keep: u32) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self { path, max_bytes: max_bytes.max(1), keep, out: BufWriter::new(file), written })
+    }
+
+    pub fn write(&mut self, record: &LogRecord) -> io::Result<()> {
+        let since_epoch = record.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let line = format!("{}.{:03} {record}\n", since_epoch.as_secs(), since_epoch.subsec_millis());
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.out.write_all(line.as_bytes())?;
+        self.written += line.len() as u64;
+        // records are rare enough, and a crash is exactly when they matter
+        self.out.flush()
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.out = BufWriter::new(file);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    records: VecDeque<LogRecord>,
+    dropped: u64,
+}
+
+pub struct Logger {
+    levels: [AtomicU8; LogCategory::ALL.len()],
+    queue: Mutex<Queue>,
+    file: Mutex<Option<RotatingLogFile>>,
+}
+
+impl Logger {
+    fn new() -> Self {
+        let levels = LogCategory::ALL.map(|category| AtomicU8::new(default_level(category) as u8));
+        Self { levels, queue: Mutex::new(Queue::default()), file: Mutex::new(None) }
+    }
+
+    pub fn level(&self, category: LogCategory) -> LogLevel {
+        LogLevel::from_u8(self.levels[category as usize].load(Ordering::Relaxed)).unwrap_or(LogLevel::Off)
+    }
+
+    pub fn set_level(&self, category: LogCategory, level: LogLevel) {
+        self.levels[category as usize].store(level as u8, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self, level: LogLevel, category: LogCategory) -> bool {
+        level != LogLevel::Off && level <= self.level(category)
+    }
+
+    /// Logs `message()` if `category` lets `level` through; the message is
+    /// only built if it does.
+    pub fn log_with(
+        &self,
+        level: LogLevel,
+        category: LogCategory,
+        peer_id: Option<PeerId>,
+        message: impl FnOnce() -> String,
+    ) {
+        if !self.enabled(level, category) {
+            return;
+        }
+        let record = LogRecord { level, category, peer_id, message: message(), time: SystemTime::now() };
+
+        if let Ok(mut file) = self.file.lock()
+            && let Some(out) = file.as_mut()
+            && out.write(&record).is_err()
+        {
+            // a full disk shouldn't take the server down; stop writing
+            *file = None;
+        }
+
+        if let Ok(mut queue) = self.queue.lock() {
+            if queue.records.len() == QUEUE_CAPACITY {
+                queue.records.pop_front();
+                queue.dropped += 1;
+            }
+            queue.records.push_back(record);
+        }
+    }
+
+    /// Takes every queued record, and how many were dropped since the last
+    /// call because nobody drained them in time.
+    pub fn drain(&self) -> (Vec<LogRecord>, u64) {
+        match self.queue.lock() {
+            Ok(mut queue) => (queue.records.drain(..).collect(), std::mem::take(&mut queue.dropped)),
+            Err(_) => (Vec::new(), 0),
+        }
+    }
+
+    /// Also writes every record to `file`, replacing any file already open.
+    pub fn set_file(&self, file: Option<RotatingLogFile>) {
+        if let Ok(mut current) = self.file.lock() {
+            *current = file;
+        }
+    }
+}
+
+fn default_level(category: LogCategory) -> LogLevel {
+    match category {
+        // GNS is chatty below this
+        LogCategory::Gns => LogLevel::Warning,
+        LogCategory::Server | LogCategory::Client | LogCategory::Packet => LogLevel::Info,
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// The process-wide logger every `net_log!` goes to.
+pub fn logger() -> &'static Logger {
+    LOGGER.get_or_init(Logger::new)
+}
+
+/// Logs to `logger()`, formatting only if the category's level allows it:
+///
+/// ```ignore
+/// net_log!(Warning, Server, peer = peer_id, "dropped {} inputs", count);
+/// net_log!(Info, Client, "connected to {}", addr);
+/// ```
+#[macro_export]
+macro_rules! net_log {
+    ($level:ident, $category:ident, peer = $peer:expr, $($arg:tt)+) => {
+        $crate::log::logger().log_with(
+            $crate::log::LogLevel::$level,
+            $crate::log::LogCategory::$category,
+            Some($peer),
+            || format!($($arg)+),
+        )
+    };
+    ($level:ident, $category:ident, $($arg:tt)+) => {
+        $crate::log::logger().log_with(
+            $crate::log::LogLevel::$level,
+            $crate::log::LogCategory::$category,
+            None,
+            || format!($($arg)+),
+        )
+    };
+}
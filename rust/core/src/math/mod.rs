@@ -0,0 +1,2 @@
+pub mod rng;
+pub mod sequence;
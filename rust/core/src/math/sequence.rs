@@ -0,0 +1,47 @@
+
+pub const SEQUENCE_MODULO: i32 = 65536;
+pub const SEQUENCE_HALF_RANGE: i32 = SEQUENCE_MODULO / 2;
+
+pub fn seq_is_newer(a: u16, b: u16) -> bool {
+    seq_diff(a, b) > 0
+}
+
+pub fn seq_diff(a: u16, b: u16) -> i32 {
+    let mut diff = (a as i32) - (b as i32);
+
+    if diff > SEQUENCE_HALF_RANGE {
+        diff -= SEQUENCE_MODULO;
+    } else if diff < -SEQUENCE_HALF_RANGE {
+        diff += SEQUENCE_MODULO;
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_wraps_around() {
+        assert_eq!(seq_diff(0, 65535), 1);
+        assert_eq!(seq_diff(65535, 0), -1);
+        assert_eq!(seq_diff(5, 65530), 11);
+        assert_eq!(seq_diff(65530, 5), -11);
+        for base in [0u16, 1, 32767, 32768, 65000, 65535] {
+            for offset in -1000i32..=1000 {
+                assert_eq!(seq_diff(base.wrapping_add(offset as u16), base), offset);
+            }
+        }
+    }
+
+    #[test]
+    fn newer_across_wraparound() {
+        assert!(seq_is_newer(0, 65535));
+        assert!(seq_is_newer(10, 65500));
+        assert!(!seq_is_newer(65535, 0));
+        assert!(!seq_is_newer(7, 7));
+        assert!(seq_is_newer(SEQUENCE_HALF_RANGE as u16 - 1, 0));
+        // more than half the range behind reads as ahead
+        assert!(seq_is_newer(0, SEQUENCE_HALF_RANGE as u16 + 1));
+    }
+}
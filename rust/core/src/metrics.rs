@@ -0,0 +1,380 @@
+//! Server health metrics and a minimal HTTP endpoint serving them in the
+//! Prometheus text format, for headless servers:
+//!
+//! ```text
+//! curl http://127.0.0.1:9100/metrics
+//! ```
+//!
+//! The driver feeds `ServerMetrics` as it sends, receives and ticks, and
+//! `MetricsEndpoint::poll` answers scrapes from the game thread, so nothing
+//! here is shared across threads. Sockets never block: requests are read and
+//! responses written a bit at a time over as many polls as they take.
+
+use crate::capture::Direction;
+use crate::packet::{PacketId, PeerId};
+use num_traits::FromPrimitive;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+/// Tick durations in seconds; a 60 Hz tick has 16.7 ms.
+const TICK_BUCKETS: &[f64] = &[0.001, 0.002, 0.004, 0.008, 0.0167, 0.033, 0.066, 0.1];
+const PING_BUCKETS_MS: &[f64] = &[10.0, 25.0, 50.0, 75.0, 100.0, 150.0, 200.0, 300.0, 500.0];
+/// How long a scraper gets to send its request and take the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+const MAX_PENDING: usize = 16;
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    // per bucket, not cumulative; the last one is +Inf
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, counts: vec![0; bounds.len() + 1], sum: 0.0, count: 0 }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// `labels` are prepended to `le`, e.g. `peer="3",`.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{labels}le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels}le=\"+Inf\"}} {}", self.count);
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{labels}}}"),
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Traffic {
+    messages: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerMetrics {
+    connected_peers: usize,
+    sent: BTreeMap<PacketId, Traffic>,
+    received: BTreeMap<PacketId, Traffic>,
+    decode_failures: u64,
+    poll_budget_overruns: u64,
+    tick_duration: Histogram,
+    peer_ping: BTreeMap<PeerId, Histogram>,
+}
+
+impl Default for ServerMetrics {
+    fn default() -> Self {
+        Self {
+            connected_peers: 0,
+            sent: BTreeMap::new(),
+            received: BTreeMap::new(),
+            decode_failures: 0,
+            poll_budget_overruns: 0,
+            tick_duration: Histogram::new(TICK_BUCKETS),
+            peer_ping: BTreeMap::new(),
+        }
+    }
+}
+
+impl ServerMetrics {
+    pub fn set_connected_peers(&mut self, count: usize) {
+        self.connected_peers = count;
+    }
+
+    /// Counts one encoded packet. Payloads with an unknown id only count as
+    /// a decode failure when received.
+    pub fn count_message(&mut self, direction: Direction, payload: &[u8]) {
+        let Some(id) = payload.first().copied().and_then(PacketId::from_u8) else {
+            return;
+        };
+        let traffic = match direction {
+            Direction::Sent => self.sent.entry(id).or_default(),
+            Direction::Received => self.received.entry(id).or_default(),
+        };
+        traffic.messages += 1;
+        traffic.bytes += payload.len() as u64;
+    }
+
+    pub fn count_decode_failure(&mut self) {
+        self.decode_failures += 1;
+    }
+
+    /// A poll stopped at its message or time budget with messages left.
+    pub fn count_poll_budget_overrun(&mut self) {
+        self.poll_budget_overruns += 1;
+    }
+
+    pub fn observe_tick(&mut self, duration: Duration) {
+        self.tick_duration.observe(duration.as_secs_f64());
+    }
+
+    pub fn observe_ping(&mut self, peer_id: PeerId, ping_ms: u32) {
+        self.peer_ping.entry(peer_id).or_insert_with(|| Histogram::new(PING_BUCKETS_MS)).observe(f64::from(ping_ms));
+    }
+
+    /// Drops a disconnected peer's ping histogram.
+    pub fn forget_peer(&mut self, peer_id: PeerId) {
+        self.peer_ping.remove(&peer_id);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP br_connected_peers Peers currently connected, including a listen server's host.");
+        let _ = writeln!(out, "# TYPE br_connected_peers gauge");
+        let _ = writeln!(out, "br_connected_peers {}", self.connected_peers);
+
+        let traffic = [("out", &self.sent), ("in", &self.received)];
+        let _ = writeln!(out, "# HELP br_messages_total Messages by packet type and direction.");
+        let _ = writeln!(out, "# TYPE br_messages_total counter");
+        for (direction, counters) in traffic {
+            for (id, traffic) in counters {
+                let _ = writeln!(
+                    out,
+                    "br_messages_total{{type=\"{}\",direction=\"{direction}\"}} {}",
+                    id.name(),
+                    traffic.messages
+                );
+            }
+        }
+        let _ = writeln!(out, "# HELP br_bytes_total Payload bytes by packet type and direction.");
+        let _ = writeln!(out, "# TYPE br_bytes_total counter");
+        for (direction, counters) in traffic {
+            for (id, traffic) in counters {
+                let _ = writeln!(
+                    out,
+                    "br_bytes_total{{type=\"{}\",direction=\"{direction}\"}} {}",
+                    id.name(),
+                    traffic.bytes
+                );
+            }
+        }
+
+        let _ = writeln!(out, "# HELP br_decode_failures_total Received messages that failed to decode.");
+        let _ = writeln!(out, "# TYPE br_decode_failures_total counter");
+        let _ = writeln!(out, "br_decode_failures_total {}", self.decode_failures);
+
+        let _ = writeln!(out, "# HELP br_poll_budget_overruns_total Polls cut short by the message or time budget.");
+        let _ = writeln!(out, "# TYPE br_poll_budget_overruns_total counter");
+        let _ = writeln!(out, "br_poll_budget_overruns_total {}", self.poll_budget_overruns);
+
+        let _ = writeln!(out, "# HELP br_tick_duration_seconds Time spent in the driver each physics tick.");
+        let _ = writeln!(out, "# TYPE br_tick_duration_seconds histogram");
+        self.tick_duration.render(&mut out, "br_tick_duration_seconds", "");
+
+        let _ = writeln!(out, "# HELP br_peer_ping_ms Round trip time per connected peer, sampled every second.");
+        let _ = writeln!(out, "# TYPE br_peer_ping_ms histogram");
+        for (peer_id, histogram) in &self.peer_ping {
+            histogram.render(&mut out, "br_peer_ping_ms", &format!("peer=\"{peer_id}\","));
+        }
+
+        out
+    }
+}
+
+struct PendingRequest {
+    stream: TcpStream,
+    request: Vec<u8>,
+    accepted: Instant,
+    /// Set once the request is in, with how much of it has been written.
+    response: Option<(Vec<u8>, usize)>,
+}
+
+impl PendingRequest {
+    /// Reads what has arrived. Returns the request line once the headers
+    /// are complete, or an error if the scraper hung up or sent too much.
+    fn read_request(&mut self) -> io::Result<Option<&[u8]>> {
+        let mut buffer = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.request.extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+            if self.request.len() > MAX_REQUEST_LEN {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+        }
+        if !self.request.windows(4).any(|window| window == b"\r\n\r\n") {
+            return Ok(None);
+        }
+        Ok(self.request.split(|byte| *byte == b'\r').next())
+    }
+
+    /// Writes as much of the response as the socket takes. Returns true
+    /// once all of it has been written.
+    fn write_response(&mut self) -> io::Result<bool> {
+        let Some((response, written)) = self.response.as_mut() else {
+            return Ok(false);
+        };
+        while *written < response.len() {
+            match self.stream.write(&response[*written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => *written += len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Serves `GET /metrics` over plain HTTP/1.0, one response per connection.
+pub struct MetricsEndpoint {
+    listener: TcpListener,
+    pending: Vec<PendingRequest>,
+}
+
+impl MetricsEndpoint {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, pending: Vec::new() })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts scrapers and answers those whose request has arrived.
+    /// `render` is only called if one asked for the metrics.
+    pub fn poll(&mut self, now: Instant, render: impl FnOnce() -> String) {
+        while self.pending.len() < MAX_PENDING {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if stream.set_nonblocking(true).is_ok() {
+                        let pending = PendingRequest { stream, request: Vec::new(), accepted: now, response: None };
+                        self.pending.push(pending);
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+
+        let mut render = Some(render);
+        let mut body: Option<String> = None;
+        self.pending.retain_mut(|pending| {
+            if pending.response.is_none() {
+                let request_line = match pending.read_request() {
+                    Ok(Some(request_line)) => request_line,
+                    Ok(None) => return now.duration_since(pending.accepted) < REQUEST_TIMEOUT,
+                    Err(_) => return false,
+                };
+                let response = if request_line.starts_with(b"GET /metrics ") {
+                    let body = body.get_or_insert_with(|| render.take().map(|render| render()).unwrap_or_default());
+                    format!(
+                        "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                } else {
+                    "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+                };
+                pending.response = Some((response.into_bytes(), 0));
+            }
+
+            match pending.write_response() {
+                Ok(true) => {
+                    let _ = pending.stream.shutdown(Shutdown::Write);
+                    false
+                }
+                // a scraper that stops reading is cut off at the deadline
+                Ok(false) => now.duration_since(pending.accepted) < REQUEST_TIMEOUT,
+                Err(_) => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        thread,
+    };
+
+    const WAIT: Duration = Duration::from_secs(2);
+
+    fn scrape(endpoint: &mut MetricsEndpoint, request: &str, render: impl Fn() -> String) -> String {
+        let mut client = TcpStream::connect(endpoint.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        client.set_nonblocking(true).unwrap();
+
+        let deadline = Instant::now() + WAIT;
+        let mut response = Vec::new();
+        let mut buffer = [0u8; 1024];
+        loop {
+            assert!(Instant::now() < deadline, "timed out scraping");
+            endpoint.poll(Instant::now(), &render);
+            match client.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => response.extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+                Err(e) => panic!("scrape failed: {e}"),
+            }
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn scrapes_get_the_exposition_text() {
+        let mut endpoint = MetricsEndpoint::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+        let mut metrics = ServerMetrics::default();
+        metrics.set_connected_peers(2);
+        metrics.count_message(Direction::Sent, &[PacketId::Chat as u8, 1, 2, 3]);
+        metrics.count_decode_failure();
+        metrics.observe_tick(Duration::from_millis(3));
+        metrics.observe_ping(4, 60);
+
+        let response = scrape(&mut endpoint, "GET /metrics HTTP/1.0\r\n\r\n", || metrics.render());
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert_eq!(body, metrics.render());
+        for line in [
+            "# TYPE br_connected_peers gauge",
+            "br_connected_peers 2",
+            "br_messages_total{type=\"chat\",direction=\"out\"} 1",
+            "br_bytes_total{type=\"chat\",direction=\"out\"} 4",
+            "br_decode_failures_total 1",
+            "br_tick_duration_seconds_bucket{le=\"0.002\"} 0",
+            "br_tick_duration_seconds_bucket{le=\"0.004\"} 1",
+            "br_tick_duration_seconds_count 1",
+            "br_peer_ping_ms_bucket{peer=\"4\",le=\"75\"} 1",
+            "br_peer_ping_ms_sum{peer=\"4\"} 60",
+        ] {
+            assert!(body.lines().any(|body_line| body_line == line), "missing {line:?} in\n{body}");
+        }
+    }
+
+    #[test]
+    fn other_paths_are_not_found_without_rendering() {
+        let mut endpoint = MetricsEndpoint::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+        let response = scrape(&mut endpoint, "GET / HTTP/1.0\r\n\r\n", || panic!("rendered for a 404"));
+        assert!(response.starts_with("HTTP/1.0 404 Not Found\r\n"));
+    }
+}
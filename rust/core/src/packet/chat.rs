@@ -0,0 +1,11 @@
+use super::PacketData;
+
+define_wire_packet! {
+    name: ChatPacket,
+    reliable: true,
+    fields: {
+        username: String,
+        message: String,
+    },
+    codec: postcard
+}
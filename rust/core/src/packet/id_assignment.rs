@@ -0,0 +1,11 @@
+use super::{PacketData, PeerId};
+
+define_wire_packet! {
+    name: IdAssignmentPacket,
+    reliable: true,
+    fields: {
+        id: PeerId,
+        remote_ids: Vec<PeerId>,
+    },
+    codec: postcard
+}
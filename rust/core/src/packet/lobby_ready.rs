@@ -0,0 +1,10 @@
+use super::PacketData;
+
+define_wire_packet! {
+    name: LobbyReadyPacket,
+    reliable: true,
+    fields: {
+        ready: bool,
+    },
+    codec: postcard
+}
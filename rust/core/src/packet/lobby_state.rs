@@ -0,0 +1,15 @@
+use super::{PacketData, PeerId};
+
+define_wire_packet! {
+    name: LobbyStatePacket,
+    reliable: true,
+    fields: {
+        phase: u8,
+        countdown_ms: u32,
+        min_players: u16,
+        max_players: u16,
+        players: Vec<PeerId>,
+        ready: Vec<PeerId>,
+    },
+    codec: postcard
+}
@@ -0,0 +1,113 @@
+/*
+Macro schema: define_wire_packet!
+
+Invocation shape (postcard/serde codec)
+
+    define_wire_packet! {
+        name: PacketTypeName,
+        reliable: <bool>,
+        fields: {
+            field_a: <WireFieldType>,
+            field_b: <WireFieldType>,
+        },
+        codec: postcard
+    }
+
+What gets generated
+
+1) Wire/data struct: `PacketTypeNameWire`
+   - `#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]`
+   - Pure Rust data for network encoding/decoding; never contains Godot types.
+
+2) PacketData impl for `PacketTypeNameWire`
+   - `const IS_RELIABLE: bool = reliable`
+   - `fn encode(&self) -> Vec<u8>`: uses `postcard::to_allocvec(self)`; logs errors on failure.
+   - `fn decode(data: &[u8]) -> std::io::Result<Self>`: uses `postcard::from_bytes(data)`.
+
+Notes
+   - The Godot-facing class is generated separately by `define_packet!` in the
+     extension crate, which converts to and from this struct.
+   - The macro does not modify `PacketId` or the `Packet` enum; add new variants in `packet.rs`.
+*/
+macro_rules! define_wire_packet {
+    (
+        name: $name:ident,
+        reliable: $reliable:expr,
+        fields: {
+            $( $field:ident : $wire_ty:ty ),+ $(,)?
+        },
+        codec: postcard
+    ) => {
+        paste::paste! {
+            #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+            pub struct [<$name Wire>] {
+                $( pub $field: $wire_ty ),+
+            }
+
+            impl PacketData for [<$name Wire>] {
+                const IS_RELIABLE: bool = $reliable;
+
+                fn encode(&self) -> Vec<u8> {
+                    match postcard::to_allocvec(self) {
+                        Ok(bytes) => bytes,
+                        Err(err) => {
+                            $crate::net_log!(
+                                Error,
+                                Packet,
+                                "Failed to encode {}: {}",
+                                concat!(stringify!($name), "Wire"),
+                                err
+                            );
+                            Vec::new()
+                        }
+                    }
+                }
+
+                fn decode(data: &[u8]) -> std::io::Result<Self> {
+                    postcard::from_bytes(data).map_err(|err| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("postcard decode failed: {}", err)
+                        )
+                    })
+                }
+            }
+        }
+    };
+}
+
+// A specialized macro for packets with no fields ("null"/"empty" payload)
+// Generates a unit wire struct `<Name>Wire`.
+// Example:
+// define_null_wire_packet! { name: NullPacket, reliable: false }
+macro_rules! define_null_wire_packet {
+    (
+        name: $name:ident,
+        reliable: $reliable:expr $(,)?
+    ) => {
+        paste::paste! {
+            // Unit-like wire struct for an empty payload
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct [<$name Wire>];
+
+            impl PacketData for [<$name Wire>] {
+                const IS_RELIABLE: bool = $reliable;
+
+                fn encode(&self) -> Vec<u8> {
+                    Vec::new()
+                }
+
+                fn decode(data: &[u8]) -> std::io::Result<Self> {
+                    if data.is_empty() {
+                        Ok(Self)
+                    } else {
+                        Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "Null packet should have empty payload",
+                        ))
+                    }
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,30 @@
+#[macro_use]
+mod macros;
+#[allow(clippy::module_inception)]
+mod packet;
+mod packet_data;
+mod peer_id;
+mod null;
+mod chat;
+mod id_assignment;
+mod player_disconnected;
+mod player_input;
+mod player_state;
+mod lobby_state;
+mod lobby_ready;
+
+/// Bumped whenever any packet's wire format changes; peers on different
+/// versions can't talk to each other.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+pub use packet::{Packet, PacketId};
+pub use packet_data::PacketData;
+pub use peer_id::PeerId;
+pub use null::NullPacketWire;
+pub use chat::ChatPacketWire;
+pub use id_assignment::IdAssignmentPacketWire;
+pub use player_disconnected::PlayerDisconnectedPacketWire;
+pub use player_input::PlayerInputPacketWire;
+pub use player_state::PlayerStatePacketWire;
+pub use lobby_state::LobbyStatePacketWire;
+pub use lobby_ready::LobbyReadyPacketWire;
@@ -0,0 +1,6 @@
+use super::PacketData;
+
+define_null_wire_packet! {
+    name: NullPacket,
+    reliable: false,
+}
@@ -0,0 +1,187 @@
+use super::*;
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::io::{Error, ErrorKind, Result};
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, FromPrimitive, ToPrimitive)]
+pub enum PacketId {
+    IdAssignment,
+    Chat,
+    PlayerInput,
+    PlayerState,
+    PlayerDisconnected,
+    Null,
+    LobbyState,
+    LobbyReady,
+}
+
+impl PacketId {
+    pub fn name(self) -> &'static str {
+        match self {
+            PacketId::IdAssignment => "id_assignment",
+            PacketId::Chat => "chat",
+            PacketId::PlayerInput => "player_input",
+            PacketId::PlayerState => "player_state",
+            PacketId::PlayerDisconnected => "player_disconnected",
+            PacketId::Null => "null",
+            PacketId::LobbyState => "lobby_state",
+            PacketId::LobbyReady => "lobby_ready",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    IdAssignment(IdAssignmentPacketWire),
+    PlayerInput(PlayerInputPacketWire),
+    PlayerState(PlayerStatePacketWire),
+    Chat(ChatPacketWire),
+    PlayerDisconnected(PlayerDisconnectedPacketWire),
+    Null(NullPacketWire),
+    LobbyState(LobbyStatePacketWire),
+    LobbyReady(LobbyReadyPacketWire),
+}
+
+impl Packet {
+    pub fn id(&self) -> PacketId {
+        match self {
+            Packet::IdAssignment(_) => PacketId::IdAssignment,
+            Packet::PlayerInput(_) => PacketId::PlayerInput,
+            Packet::PlayerState(_) => PacketId::PlayerState,
+            Packet::Chat(_) => PacketId::Chat,
+            Packet::PlayerDisconnected(_) => PacketId::PlayerDisconnected,
+            Packet::Null(_) => PacketId::Null,
+            Packet::LobbyState(_) => PacketId::LobbyState,
+            Packet::LobbyReady(_) => PacketId::LobbyReady,
+        }
+    }
+
+    pub fn is_reliable(&self) -> bool {
+        match self {
+            Packet::IdAssignment(_) => IdAssignmentPacketWire::IS_RELIABLE,
+            Packet::PlayerInput(_) => PlayerInputPacketWire::IS_RELIABLE,
+            Packet::PlayerState(_) => PlayerStatePacketWire::IS_RELIABLE,
+            Packet::Chat(_) => ChatPacketWire::IS_RELIABLE,
+            Packet::PlayerDisconnected(_) => PlayerDisconnectedPacketWire::IS_RELIABLE,
+            Packet::Null(_) => NullPacketWire::IS_RELIABLE,
+            Packet::LobbyState(_) => LobbyStatePacketWire::IS_RELIABLE,
+            Packet::LobbyReady(_) => LobbyReadyPacketWire::IS_RELIABLE,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.id().to_u8().unwrap()];
+        bytes.extend(match self {
+            Packet::IdAssignment(packet) => packet.encode(),
+            Packet::PlayerInput(packet) => packet.encode(),
+            Packet::PlayerState(packet) => packet.encode(),
+            Packet::Chat(packet) => packet.encode(),
+            Packet::PlayerDisconnected(packet) => packet.encode(),
+            Packet::Null(packet) => packet.encode(),
+            Packet::LobbyState(packet) => packet.encode(),
+            Packet::LobbyReady(packet) => packet.encode(),
+        });
+        bytes
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "Empty packet data"));
+        }
+
+        // 2. Get the ID byte and the rest of the data
+        let id_byte = data[0];
+        let packet_data = &data[1..];
+
+        // 3. Use FromPrimitive to convert the u8 byte back to a PacketId
+        let packet_id = PacketId::from_u8(id_byte)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Unknown packet ID"))?;
+
+        match packet_id {
+            PacketId::IdAssignment => Ok(Packet::IdAssignment(IdAssignmentPacketWire::decode(
+                packet_data,
+            )?)),
+            PacketId::PlayerInput => Ok(Packet::PlayerInput(PlayerInputPacketWire::decode(packet_data)?)),
+            PacketId::PlayerState => Ok(Packet::PlayerState(PlayerStatePacketWire::decode(packet_data)?)),
+            PacketId::Chat => Ok(Packet::Chat(ChatPacketWire::decode(packet_data)?)),
+            PacketId::PlayerDisconnected => Ok(Packet::PlayerDisconnected(
+                PlayerDisconnectedPacketWire::decode(packet_data)?,
+            )),
+            PacketId::Null => Ok(Packet::Null(NullPacketWire::decode(packet_data)?)),
+            PacketId::LobbyState => Ok(Packet::LobbyState(LobbyStatePacketWire::decode(packet_data)?)),
+            PacketId::LobbyReady => Ok(Packet::LobbyReady(LobbyReadyPacketWire::decode(packet_data)?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_packet() -> Vec<Packet> {
+        vec![
+            Packet::IdAssignment(IdAssignmentPacketWire { id: 3, remote_ids: vec![1, 2, 65535] }),
+            Packet::PlayerInput(PlayerInputPacketWire {
+                sequence_id: 65535,
+                timestamp_us: u32::MAX,
+                move_forward_backward: -1,
+                move_left_right: 1,
+                look_abs: [0.25, -1.5],
+                jump: true,
+                crouch: false,
+                sprint: true,
+                prone: false,
+                peek_left_right: -1,
+            }),
+            Packet::PlayerState(PlayerStatePacketWire {
+                player_id: 7,
+                last_input_sequence_id: 12,
+                timestamp_us: 123_456,
+                position: [1.0, -2.0, 3.5],
+                look_abs: [0.5, 0.75],
+                velocity: [0.0, -9.8, 0.0],
+                movement_state: 2,
+                crouch_progress: 0.5,
+                prone_progress: 0.0,
+                peek_state: 1,
+                peek_progress: 1.0,
+            }),
+            Packet::Chat(ChatPacketWire { username: "ünïcode".to_string(), message: String::new() }),
+            Packet::PlayerDisconnected(PlayerDisconnectedPacketWire { player_id: 9 }),
+            Packet::Null(NullPacketWire),
+            Packet::LobbyState(LobbyStatePacketWire {
+                phase: 1,
+                countdown_ms: 5_000,
+                min_players: 2,
+                max_players: 16,
+                players: vec![1, 2, 3],
+                ready: vec![2],
+            }),
+            Packet::LobbyReady(LobbyReadyPacketWire { ready: true }),
+        ]
+    }
+
+    #[test]
+    fn every_packet_round_trips() {
+        for packet in every_packet() {
+            let bytes = packet.encode();
+            assert_eq!(bytes[0], packet.id().to_u8().unwrap());
+            assert_eq!(Packet::decode(&bytes).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn malformed_data_is_rejected() {
+        assert!(Packet::decode(&[]).is_err());
+        assert!(Packet::decode(&[u8::MAX]).is_err());
+        assert!(Packet::decode(&[PacketId::Null as u8, 0]).is_err());
+
+        for packet in every_packet() {
+            let bytes = packet.encode();
+            if bytes.len() > 1 {
+                assert!(Packet::decode(&bytes[..bytes.len() - 1]).is_err(), "{:?} decoded truncated", packet.id());
+            }
+        }
+    }
+}
@@ -0,0 +1,3 @@
+/// Peer identifier shared by the driver and every packet that carries one.
+/// Widen this alias (and nothing else) if capacity ever needs to exceed its range.
+pub type PeerId = u16;
@@ -0,0 +1,10 @@
+use super::{PacketData, PeerId};
+
+define_wire_packet! {
+    name: PlayerDisconnectedPacket,
+    reliable: true,
+    fields: {
+        player_id: PeerId,
+    },
+    codec: postcard
+}
@@ -0,0 +1,21 @@
+use super::PacketData;
+
+// TODO: quantization of inputs
+// TODO: wrap timestamp_us to save bytes
+define_wire_packet! {
+    name: PlayerInputPacket,
+    reliable: false,
+    fields: {
+        sequence_id: u16,
+        timestamp_us: u32,
+        move_forward_backward: i8,
+        move_left_right: i8,
+        look_abs: [f32; 2],
+        jump: bool,
+        crouch: bool,
+        sprint: bool,
+        prone: bool,
+        peek_left_right: i8,
+    },
+    codec: postcard
+}
@@ -0,0 +1,21 @@
+use super::{PacketData, PeerId};
+
+// TODO: wrap timestamp_us to save bytes
+define_wire_packet! {
+    name: PlayerStatePacket,
+    reliable: false,
+    fields: {
+        player_id: PeerId,
+        last_input_sequence_id: u16,
+        timestamp_us: u32,
+        position: [f32; 3],
+        look_abs: [f32; 2],
+        velocity: [f32; 3],
+        movement_state: u8,
+        crouch_progress: f32,
+        prone_progress: f32,
+        peek_state: u8,
+        peek_progress: f32,
+    },
+    codec: postcard
+}
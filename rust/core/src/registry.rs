@@ -0,0 +1,583 @@
+//! Internet server list. Dedicated servers heartbeat their `Beacon` to a
+//! master server, which drops any that stop, and clients query it for a
+//! filtered list. Everything is one UDP datagram per message:
+//!
+//! ```text
+//! datagram:  magic "BRREG" | postcard Message
+//! ```
+//!
+//! A server is identified by its heartbeat's source address with the game
+//! port from its beacon, which is also where clients connect. Results are
+//! split into pages so each fits in a datagram.
+//!
+//! As with `server_query`, the master only acts on messages carrying a
+//! challenge it handed to the sender's address, so a spoofed source can
+//! neither pull the list at someone else nor delist a server:
+//!
+//! ```text
+//! client -> master:  Query { challenge: 0, query_id, filter, padding }
+//! master -> client:  Challenge { challenge }
+//! client -> master:  Query { challenge, query_id, filter, padding }
+//! master -> client:  QueryResult { .. } per page
+//! ```
+//!
+//! Heartbeats go through the same exchange, and the master hands out a fresh
+//! challenge whenever a heartbeat's is stale, so the one a server keeps for
+//! its `Unregister` on shutdown is still accepted.
+
+use crate::discovery::Beacon;
+use crate::server_query::Challenges;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+pub const MAGIC: &[u8; 5] = b"BRREG";
+pub const DEFAULT_MASTER_PORT: u16 = 45880;
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// Servers missing this many seconds of heartbeats are dropped.
+pub const DEFAULT_SERVER_TTL: Duration = Duration::from_secs(35);
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+const SERVERS_PER_PAGE: usize = 8;
+const MAX_DATAGRAM_LEN: usize = 2048;
+/// Keeps a query at least as large as the challenge reply to it.
+const QUERY_PADDING: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListedServer {
+    pub addr: SocketAddr,
+    pub beacon: Beacon,
+}
+
+/// Conditions a listed server must meet. The default matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerFilter {
+    pub protocol_version: Option<u16>,
+    pub map: Option<String>,
+    /// Case-insensitive substring of the server name.
+    pub name_contains: Option<String>,
+    pub not_full: bool,
+    pub not_empty: bool,
+}
+
+impl ServerFilter {
+    pub fn matches(&self, beacon: &Beacon) -> bool {
+        self.protocol_version.is_none_or(|version| beacon.protocol_version == version)
+            && self.map.as_ref().is_none_or(|map| beacon.map == *map)
+            && self
+                .name_contains
+                .as_ref()
+                .is_none_or(|name| beacon.name.to_lowercase().contains(&name.to_lowercase()))
+            && (!self.not_full || beacon.player_count < beacon.capacity)
+            && (!self.not_empty || beacon.player_count > 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Message {
+    /// Server to master, every `HEARTBEAT_INTERVAL` or when its info changes.
+    Heartbeat { challenge: u32, beacon: Beacon },
+    /// Server to master on shutdown. Ignored without a valid challenge.
+    Unregister { challenge: u32, port: u16 },
+    /// Client to master.
+    Query { challenge: u32, query_id: u32, filter: ServerFilter, padding: [u8; QUERY_PADDING] },
+    /// Master to a server or client whose challenge was missing or stale.
+    Challenge { challenge: u32 },
+    /// Master to client, one per page. A query with no results still gets
+    /// one empty page.
+    QueryResult { query_id: u32, page: u16, pages: u16, servers: Vec<ListedServer> },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(postcard::to_allocvec(self).expect("registry messages always serialize"));
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC.as_slice())?;
+        postcard::from_bytes(body).ok()
+    }
+}
+
+fn bind_any() -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryEvent {
+    Registered(SocketAddr),
+    Unregistered(SocketAddr),
+    Expired(SocketAddr),
+}
+
+/// The master server's list, with no I/O so it can be driven directly.
+pub struct Registry {
+    ttl: Duration,
+    servers: HashMap<SocketAddr, (Beacon, Instant)>,
+}
+
+impl Registry {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, servers: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    /// Returns true if the server wasn't listed yet.
+    pub fn heartbeat(&mut self, addr: SocketAddr, beacon: Beacon, now: Instant) -> bool {
+        self.servers.insert(addr, (beacon, now)).is_none()
+    }
+
+    pub fn unregister(&mut self, addr: SocketAddr) -> bool {
+        self.servers.remove(&addr).is_some()
+    }
+
+    /// Drops servers that have gone quiet, returning their addresses.
+    pub fn expire(&mut self, now: Instant) -> Vec<SocketAddr> {
+        let mut expired = Vec::new();
+        self.servers.retain(|addr, (_, last_seen)| {
+            let alive = now.duration_since(*last_seen) < self.ttl;
+            if !alive {
+                expired.push(*addr);
+            }
+            alive
+        });
+        expired
+    }
+
+    /// Matching servers, fullest first so menus show active games on top.
+    pub fn query(&self, filter: &ServerFilter) -> Vec<ListedServer> {
+        let mut servers = self
+            .servers
+            .iter()
+            .filter(|(_, (beacon, _))| filter.matches(beacon))
+            .map(|(addr, (beacon, _))| ListedServer { addr: *addr, beacon: beacon.clone() })
+            .collect::<Vec<_>>();
+        servers.sort_by(|a, b| b.beacon.player_count.cmp(&a.beacon.player_count).then(a.addr.cmp(&b.addr)));
+        servers
+    }
+}
+
+/// A `Registry` answering on a UDP socket.
+pub struct MasterServer {
+    socket: UdpSocket,
+    registry: Registry,
+    challenges: Challenges,
+}
+
+impl MasterServer {
+    pub fn bind(addr: SocketAddr, ttl: Duration) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, registry: Registry::new(ttl), challenges: Challenges::new() })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Handles every pending datagram and expires quiet servers.
+    pub fn poll(&mut self, now: Instant) -> Vec<RegistryEvent> {
+        let mut events = Vec::new();
+        let mut buffer = [0u8; MAX_DATAGRAM_LEN];
+        loop {
+            let (len, source) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            match Message::decode(&buffer[..len]) {
+                Some(Message::Heartbeat { challenge, beacon }) => {
+                    // stale but still valid ones get a fresh challenge too
+                    if challenge != self.challenges.current(source, now) {
+                        self.send_challenge(source, now);
+                    }
+                    if !self.challenges.is_valid(source, challenge, now) {
+                        continue;
+                    }
+                    let addr = SocketAddr::new(source.ip(), beacon.port);
+                    if self.registry.heartbeat(addr, beacon, now) {
+                        events.push(RegistryEvent::Registered(addr));
+                    }
+                }
+                Some(Message::Unregister { challenge, port }) => {
+                    if !self.challenges.is_valid(source, challenge, now) {
+                        continue;
+                    }
+                    let addr = SocketAddr::new(source.ip(), port);
+                    if self.registry.unregister(addr) {
+                        events.push(RegistryEvent::Unregistered(addr));
+                    }
+                }
+                Some(Message::Query { challenge, query_id, filter, .. }) => {
+                    if self.challenges.is_valid(source, challenge, now) {
+                        self.answer(source, query_id, &filter);
+                    } else {
+                        self.send_challenge(source, now);
+                    }
+                }
+                Some(Message::Challenge { .. } | Message::QueryResult { .. }) | None => {}
+            }
+        }
+
+        events.extend(self.registry.expire(now).into_iter().map(RegistryEvent::Expired));
+        events
+    }
+
+    fn send_challenge(&self, to: SocketAddr, now: Instant) {
+        let challenge = Message::Challenge { challenge: self.challenges.current(to, now) };
+        let _ = self.socket.send_to(&challenge.encode(), to);
+    }
+
+    fn answer(&self, to: SocketAddr, query_id: u32, filter: &ServerFilter) {
+        let servers = self.registry.query(filter);
+        let pages: Vec<&[ListedServer]> =
+            if servers.is_empty() { vec![&[]] } else { servers.chunks(SERVERS_PER_PAGE).collect() };
+        let page_count = u16::try_from(pages.len()).unwrap_or(u16::MAX);
+        for (page, servers) in pages.into_iter().take(usize::from(page_count)).enumerate() {
+            let result = Message::QueryResult { query_id, page: page as u16, pages: page_count, servers: servers.to_vec() };
+            let _ = self.socket.send_to(&result.encode(), to);
+        }
+    }
+}
+
+/// Keeps a dedicated server listed with a master server.
+pub struct Heartbeater {
+    socket: UdpSocket,
+    master: SocketAddr,
+    last_sent: Option<Instant>,
+    beacon: Beacon,
+    /// Last challenge from the master, 0 until the first one arrives.
+    challenge: u32,
+}
+
+impl Heartbeater {
+    pub fn new(master: SocketAddr, beacon: Beacon) -> io::Result<Self> {
+        Ok(Self { socket: bind_any()?, master, last_sent: None, beacon, challenge: 0 })
+    }
+
+    pub fn beacon(&self) -> &Beacon {
+        &self.beacon
+    }
+
+    /// Replaces the listed info, sending it right away if it changed.
+    pub fn set_beacon(&mut self, beacon: Beacon) {
+        if beacon != self.beacon {
+            self.beacon = beacon;
+            self.last_sent = None;
+        }
+    }
+
+    /// Sends a heartbeat if one is due, or right away when the master hands
+    /// out a new challenge since the last one may have been refused. Returns
+    /// whether it was sent.
+    pub fn update(&mut self, now: Instant) -> io::Result<bool> {
+        let mut buffer = [0u8; MAX_DATAGRAM_LEN];
+        loop {
+            let len = match self.socket.recv_from(&mut buffer) {
+                Ok((len, source)) if source == self.master => len,
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            if let Some(Message::Challenge { challenge }) = Message::decode(&buffer[..len])
+                && challenge != self.challenge
+            {
+                self.challenge = challenge;
+                self.last_sent = None;
+            }
+        }
+
+        if self.last_sent.is_some_and(|sent| now.duration_since(sent) < HEARTBEAT_INTERVAL) {
+            return Ok(false);
+        }
+        self.last_sent = Some(now);
+        let heartbeat = Message::Heartbeat { challenge: self.challenge, beacon: self.beacon.clone() };
+        match self.socket.send_to(&heartbeat.encode(), self.master) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for Heartbeater {
+    fn drop(&mut self) {
+        let unregister = Message::Unregister { challenge: self.challenge, port: self.beacon.port };
+        let _ = self.socket.send_to(&unregister.encode(), self.master);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryOutcome {
+    Complete(Vec<ListedServer>),
+    /// Some or all pages never arrived.
+    TimedOut,
+}
+
+struct PendingQuery {
+    id: u32,
+    filter: ServerFilter,
+    sent_at: Instant,
+    pages: Vec<Option<Vec<ListedServer>>>,
+}
+
+/// Client side: asks a master server for servers, one query at a time.
+pub struct ServerListClient {
+    socket: UdpSocket,
+    master: SocketAddr,
+    next_query_id: u32,
+    pending: Option<PendingQuery>,
+    /// Last challenge from the master, reused for later queries.
+    challenge: u32,
+}
+
+impl ServerListClient {
+    pub fn new(master: SocketAddr) -> io::Result<Self> {
+        Ok(Self { socket: bind_any()?, master, next_query_id: 0, pending: None, challenge: 0 })
+    }
+
+    pub fn is_querying(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Sends a query, abandoning any still in flight.
+    pub fn query(&mut self, filter: ServerFilter, now: Instant) -> io::Result<()> {
+        let id = self.next_query_id;
+        self.next_query_id = self.next_query_id.wrapping_add(1);
+        let pending = PendingQuery { id, filter, sent_at: now, pages: Vec::new() };
+        self.send_query(&pending)?;
+        self.pending = Some(pending);
+        Ok(())
+    }
+
+    fn send_query(&self, pending: &PendingQuery) -> io::Result<()> {
+        let query = Message::Query {
+            challenge: self.challenge,
+            query_id: pending.id,
+            filter: pending.filter.clone(),
+            padding: [0; QUERY_PADDING],
+        };
+        self.socket.send_to(&query.encode(), self.master).map(drop)
+    }
+
+    /// Reads any results. Returns the outcome once the query has every page
+    /// or has timed out.
+    pub fn poll(&mut self, now: Instant) -> Option<QueryOutcome> {
+        let mut buffer = [0u8; MAX_DATAGRAM_LEN];
+        loop {
+            let len = match self.socket.recv_from(&mut buffer) {
+                Ok((len, source)) if source == self.master => len,
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            let (query_id, page, pages, servers) = match Message::decode(&buffer[..len]) {
+                Some(Message::QueryResult { query_id, page, pages, servers }) => (query_id, page, pages, servers),
+                Some(Message::Challenge { challenge }) => {
+                    // the query was refused; ask again with the challenge
+                    self.challenge = challenge;
+                    if let Some(pending) = &self.pending {
+                        let _ = self.send_query(pending);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let Some(pending) = self.pending.as_mut().filter(|pending| pending.id == query_id) else {
+                continue;
+            };
+            if pending.pages.is_empty() {
+                pending.pages = vec![None; usize::from(pages.max(1))];
+            }
+            if let Some(slot) = pending.pages.get_mut(usize::from(page)) {
+                *slot = Some(servers);
+            }
+        }
+
+        let pending = self.pending.as_ref()?;
+        if !pending.pages.is_empty() && pending.pages.iter().all(Option::is_some) {
+            let pending = self.pending.take()?;
+            return Some(QueryOutcome::Complete(pending.pages.into_iter().flatten().flatten().collect()));
+        }
+        if now.duration_since(pending.sent_at) >= QUERY_TIMEOUT {
+            self.pending = None;
+            return Some(QueryOutcome::TimedOut);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PROTOCOL_VERSION;
+    use std::thread;
+
+    const WAIT: Duration = Duration::from_secs(2);
+
+    fn beacon(name: &str, map: &str, player_count: u16, port: u16) -> Beacon {
+        Beacon::new(name.into(), map.into(), player_count, 8, port)
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+
+    fn master() -> MasterServer {
+        MasterServer::bind(addr(0), DEFAULT_SERVER_TTL).unwrap()
+    }
+
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + WAIT;
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out waiting");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn listed(master: &mut MasterServer, heartbeater: &mut Heartbeater) {
+        wait_until(|| {
+            heartbeater.update(Instant::now()).unwrap();
+            master.poll(Instant::now());
+            master.registry().len() == 1
+        });
+    }
+
+    #[test]
+    fn quiet_servers_expire() {
+        let start = Instant::now();
+        let mut registry = Registry::new(Duration::from_secs(10));
+        assert!(registry.heartbeat(addr(1), beacon("a", "m", 0, 1), start));
+        assert!(registry.heartbeat(addr(2), beacon("b", "m", 0, 2), start));
+        assert!(!registry.heartbeat(addr(1), beacon("a", "m", 1, 1), start + Duration::from_secs(5)));
+
+        assert_eq!(registry.expire(start + Duration::from_secs(9)), Vec::new());
+        assert_eq!(registry.expire(start + Duration::from_secs(10)), vec![addr(2)]);
+        assert_eq!(registry.len(), 1);
+        assert_eq!(registry.expire(start + Duration::from_secs(15)), vec![addr(1)]);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn queries_filter_and_put_fullest_first() {
+        let now = Instant::now();
+        let mut registry = Registry::new(DEFAULT_SERVER_TTL);
+        registry.heartbeat(addr(1), beacon("Alpha", "dust", 3, 1), now);
+        registry.heartbeat(addr(2), beacon("Bravo", "dust", 8, 2), now);
+        registry.heartbeat(addr(3), beacon("alphabet", "docks", 0, 3), now);
+        registry.heartbeat(addr(4), beacon("Charlie", "dust", 3, 4), now);
+        let mut old = beacon("Delta", "dust", 5, 5);
+        old.protocol_version += 1;
+        registry.heartbeat(addr(5), old, now);
+
+        let ports = |filter: ServerFilter| registry.query(&filter).iter().map(|s| s.addr.port()).collect::<Vec<_>>();
+        // ties broken by address
+        assert_eq!(ports(ServerFilter::default()), vec![2, 5, 1, 4, 3]);
+        let current = ServerFilter { protocol_version: Some(PROTOCOL_VERSION), ..Default::default() };
+        assert_eq!(ports(current), vec![2, 1, 4, 3]);
+        assert_eq!(ports(ServerFilter { map: Some("docks".into()), ..Default::default() }), vec![3]);
+        assert_eq!(ports(ServerFilter { name_contains: Some("ALPHA".into()), ..Default::default() }), vec![1, 3]);
+        assert_eq!(ports(ServerFilter { not_full: true, not_empty: true, ..Default::default() }), vec![5, 1, 4]);
+    }
+
+    #[test]
+    fn unchallenged_query_only_gets_a_challenge() {
+        let mut master = master();
+        let now = Instant::now();
+        master.registry.heartbeat(addr(1), beacon("a", "m", 0, 1), now);
+
+        let client = bind_any().unwrap();
+        let query =
+            Message::Query { challenge: 0, query_id: 7, filter: ServerFilter::default(), padding: [0; QUERY_PADDING] };
+        let query = query.encode();
+        client.send_to(&query, master.local_addr().unwrap()).unwrap();
+
+        let mut buffer = [0u8; MAX_DATAGRAM_LEN];
+        let mut replies = Vec::new();
+        wait_until(|| {
+            master.poll(now);
+            while let Ok((len, _)) = client.recv_from(&mut buffer) {
+                replies.push((len, Message::decode(&buffer[..len])));
+            }
+            !replies.is_empty()
+        });
+        let [(len, Some(Message::Challenge { challenge }))] = replies[..] else {
+            panic!("expected a lone challenge, got {replies:?}");
+        };
+        assert_ne!(challenge, 0);
+        assert!(len <= query.len(), "reply of {len} bytes to a {} byte query", query.len());
+    }
+
+    #[test]
+    fn heartbeats_are_listed_and_queried() {
+        let mut master = master();
+        let mut heartbeater = Heartbeater::new(master.local_addr().unwrap(), beacon("a", "m", 2, 4000)).unwrap();
+        listed(&mut master, &mut heartbeater);
+
+        let mut client = ServerListClient::new(master.local_addr().unwrap()).unwrap();
+        client.query(ServerFilter::default(), Instant::now()).unwrap();
+        let mut outcome = None;
+        wait_until(|| {
+            master.poll(Instant::now());
+            outcome = client.poll(Instant::now());
+            outcome.is_some()
+        });
+        let Some(QueryOutcome::Complete(servers)) = outcome else {
+            panic!("query failed: {outcome:?}");
+        };
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].addr.port(), 4000);
+        assert_eq!(servers[0].beacon, *heartbeater.beacon());
+    }
+
+    #[test]
+    fn only_the_listed_server_can_unregister() {
+        let mut master = master();
+        let mut heartbeater = Heartbeater::new(master.local_addr().unwrap(), beacon("a", "m", 0, 4000)).unwrap();
+        listed(&mut master, &mut heartbeater);
+
+        // another socket on the same host, reusing the server's challenge
+        let spoofer = bind_any().unwrap();
+        for challenge in [0, heartbeater.challenge] {
+            let unregister = Message::Unregister { challenge, port: 4000 };
+            spoofer.send_to(&unregister.encode(), master.local_addr().unwrap()).unwrap();
+        }
+        let ping =
+            Message::Query { challenge: 0, query_id: 0, filter: ServerFilter::default(), padding: [0; QUERY_PADDING] };
+        spoofer.send_to(&ping.encode(), master.local_addr().unwrap()).unwrap();
+        let mut buffer = [0u8; MAX_DATAGRAM_LEN];
+        wait_until(|| {
+            master.poll(Instant::now());
+            spoofer.recv_from(&mut buffer).is_ok()
+        });
+        assert_eq!(master.registry().len(), 1);
+
+        drop(heartbeater);
+        let mut events = Vec::new();
+        wait_until(|| {
+            events.extend(master.poll(Instant::now()));
+            !events.is_empty()
+        });
+        assert!(matches!(events[..], [RegistryEvent::Unregistered(addr)] if addr.port() == 4000));
+        assert!(master.registry().is_empty());
+    }
+}
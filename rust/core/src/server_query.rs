@@ -0,0 +1,261 @@
+//! Connectionless status queries, in the spirit of Source's A2S_INFO: a
+//! client asks a server's query port for its status without joining.
+//!
+//! ```text
+//! datagram:  magic "BRQRY" | postcard QueryMessage
+//!
+//! client -> server:  InfoRequest { challenge: 0, padding }
+//! server -> client:  Challenge { challenge }
+//! client -> server:  InfoRequest { challenge, padding }
+//! server -> client:  Info(ServerStatus)
+//! ```
+//!
+//! The status is only sent to an address that has echoed a challenge, so a
+//! spoofed source can't be used to reflect it at someone else. Requests are
+//! padded to at least the size of a challenge reply, so the first step
+//! doesn't amplify either. Challenges are derived from the address and a
+//! rotating secret rather than stored, so unanswered ones cost nothing.
+
+use crate::packet::{PROTOCOL_VERSION, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+pub const MAGIC: &[u8; 5] = b"BRQRY";
+pub const DEFAULT_QUERY_PORT: u16 = 45877;
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Challenges stay valid for one to two of these.
+const CHALLENGE_WINDOW: Duration = Duration::from_secs(30);
+const REQUEST_PADDING: usize = 16;
+/// Status replies are trimmed to fit one unfragmented datagram.
+const MAX_RESPONSE_LEN: usize = 1200;
+const MAX_DATAGRAM_LEN: usize = 2048;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryPlayer {
+    pub id: PeerId,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub protocol_version: u16,
+    pub name: String,
+    pub map: String,
+    /// Free-form match phase, e.g. "lobby" or "in_progress".
+    pub phase: String,
+    pub player_count: u16,
+    pub capacity: u16,
+    /// May hold fewer than `player_count` entries if the full list wouldn't
+    /// fit in a datagram.
+    pub players: Vec<QueryPlayer>,
+}
+
+impl ServerStatus {
+    pub fn new(name: String, map: String, phase: String, capacity: u16, players: Vec<QueryPlayer>) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            name,
+            map,
+            phase,
+            player_count: u16::try_from(players.len()).unwrap_or(u16::MAX),
+            capacity,
+            players,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryMessage {
+    InfoRequest { challenge: u32, padding: [u8; REQUEST_PADDING] },
+    Challenge { challenge: u32 },
+    Info(ServerStatus),
+}
+
+impl QueryMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(postcard::to_allocvec(self).expect("query messages always serialize"));
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let body = bytes.strip_prefix(MAGIC.as_slice())?;
+        postcard::from_bytes(body).ok()
+    }
+
+    fn request(challenge: u32) -> Self {
+        QueryMessage::InfoRequest { challenge, padding: [0; REQUEST_PADDING] }
+    }
+}
+
+/// Challenges an address must echo before it is answered, derived from the
+/// address and a rotating secret so nothing is stored per address. Each is
+/// valid for one to two `CHALLENGE_WINDOW`s. Also used by the master server.
+pub(crate) struct Challenges {
+    secret: RandomState,
+    started: Instant,
+}
+
+impl Challenges {
+    pub(crate) fn new() -> Self {
+        Self { secret: RandomState::new(), started: Instant::now() }
+    }
+
+    fn window(&self, now: Instant) -> u64 {
+        (now.duration_since(self.started).as_secs() / CHALLENGE_WINDOW.as_secs()).max(1)
+    }
+
+    fn at(&self, addr: SocketAddr, window: u64) -> u32 {
+        // never 0, which asks for a challenge
+        (self.secret.hash_one((addr, window)) as u32).max(1)
+    }
+
+    /// The challenge to hand `addr` now.
+    pub(crate) fn current(&self, addr: SocketAddr, now: Instant) -> u32 {
+        self.at(addr, self.window(now))
+    }
+
+    pub(crate) fn is_valid(&self, addr: SocketAddr, challenge: u32, now: Instant) -> bool {
+        let window = self.window(now);
+        challenge != 0 && (challenge == self.at(addr, window) || challenge == self.at(addr, window - 1))
+    }
+}
+
+/// Server side: answers status queries on its own UDP port next to the game
+/// transport.
+pub struct QueryResponder {
+    socket: UdpSocket,
+    challenges: Challenges,
+}
+
+impl QueryResponder {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, challenges: Challenges::new() })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Answers every pending request. `status` is only called if some
+    /// request passed its challenge.
+    pub fn poll(&mut self, now: Instant, mut status: impl FnMut() -> ServerStatus) {
+        let mut cached: Option<Vec<u8>> = None;
+        let mut buffer = [0u8; MAX_DATAGRAM_LEN];
+        loop {
+            let (len, source) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            let Some(QueryMessage::InfoRequest { challenge, .. }) = QueryMessage::decode(&buffer[..len]) else {
+                continue;
+            };
+
+            let reply = if self.challenges.is_valid(source, challenge, now) {
+                cached.get_or_insert_with(|| encode_status(status())).clone()
+            } else {
+                QueryMessage::Challenge { challenge: self.challenges.current(source, now) }.encode()
+            };
+            let _ = self.socket.send_to(&reply, source);
+        }
+    }
+}
+
+fn encode_status(mut status: ServerStatus) -> Vec<u8> {
+    loop {
+        let bytes = QueryMessage::Info(status.clone()).encode();
+        if bytes.len() <= MAX_RESPONSE_LEN || status.players.is_empty() {
+            return bytes;
+        }
+        status.players.pop();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryOutcome {
+    /// The status and the round trip of the final request.
+    Status {
+        status: ServerStatus,
+        ping_ms: u32,
+    },
+    TimedOut,
+}
+
+struct PendingQuery {
+    started: Instant,
+    last_sent: Instant,
+}
+
+/// Client side: queries any number of servers at once.
+pub struct ServerQueryClient {
+    socket: UdpSocket,
+    pending: HashMap<SocketAddr, PendingQuery>,
+}
+
+impl ServerQueryClient {
+    pub fn new() -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, pending: HashMap::new() })
+    }
+
+    /// Starts querying `addr`'s query port, restarting any query to it
+    /// already in flight.
+    pub fn query(&mut self, addr: SocketAddr, now: Instant) -> io::Result<()> {
+        self.socket.send_to(&QueryMessage::request(0).encode(), addr)?;
+        self.pending.insert(addr, PendingQuery { started: now, last_sent: now });
+        Ok(())
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Advances every query, returning those that finished.
+    pub fn poll(&mut self, now: Instant) -> Vec<(SocketAddr, QueryOutcome)> {
+        let mut finished = Vec::new();
+        let mut buffer = [0u8; MAX_DATAGRAM_LEN];
+        loop {
+            let (len, source) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            let Some(pending) = self.pending.get_mut(&source) else {
+                continue;
+            };
+            match QueryMessage::decode(&buffer[..len]) {
+                Some(QueryMessage::Challenge { challenge }) => {
+                    pending.last_sent = now;
+                    let _ = self.socket.send_to(&QueryMessage::request(challenge).encode(), source);
+                }
+                Some(QueryMessage::Info(status)) => {
+                    let ping_ms = now.duration_since(pending.last_sent).as_millis() as u32;
+                    self.pending.remove(&source);
+                    finished.push((source, QueryOutcome::Status { status, ping_ms }));
+                }
+                _ => {}
+            }
+        }
+
+        self.pending.retain(|addr, pending| {
+            let alive = now.duration_since(pending.started) < QUERY_TIMEOUT;
+            if !alive {
+                finished.push((*addr, QueryOutcome::TimedOut));
+            }
+            alive
+        });
+        finished
+    }
+}
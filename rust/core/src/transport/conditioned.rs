@@ -0,0 +1,351 @@
+use super::{ConnectionId, Transport, TransportError, TransportEvent};
+use crate::link_conditioner::{LinkConditioner, LinkProfile, LinkStats};
+use crate::math::rng::Rng;
+use std::{net::SocketAddr, sync::Mutex, time::Instant};
+
+type Message = (ConnectionId, bool, Vec<u8>);
+
+/// One direction of a conditioned link. Reliable messages are only delayed,
+/// since the backend's own reliability would hide loss, duplicates and
+/// reordering from the receiver anyway.
+struct Link {
+    reliable: LinkConditioner<Message>,
+    unreliable: LinkConditioner<Message>,
+}
+
+impl Link {
+    fn new(profile: LinkProfile, seeds: &mut Rng) -> Self {
+        let delay_only = LinkProfile {
+            lag_ms: profile.lag_ms,
+            jitter_ms: profile.jitter_ms,
+            ..LinkProfile::default()
+        };
+        Self {
+            reliable: LinkConditioner::new(delay_only, seeds.next_u64()),
+            unreliable: LinkConditioner::new(profile, seeds.next_u64()),
+        }
+    }
+
+    fn send(&mut self, now_us: u64, message: Message) {
+        if message.1 {
+            self.reliable.send(now_us, message);
+        } else {
+            self.unreliable.send(now_us, message);
+        }
+    }
+
+    fn poll(&mut self, now_us: u64) -> Vec<Message> {
+        let mut due = self.reliable.poll(now_us);
+        due.extend(self.unreliable.poll(now_us));
+        due
+    }
+
+    fn stats(&self) -> LinkStats {
+        let (reliable, unreliable) = (self.reliable.stats(), self.unreliable.stats());
+        LinkStats {
+            sent: reliable.sent + unreliable.sent,
+            dropped: reliable.dropped + unreliable.dropped,
+            duplicated: reliable.duplicated + unreliable.duplicated,
+            reordered: reliable.reordered + unreliable.reordered,
+            delivered: reliable.delivered + unreliable.delivered,
+        }
+    }
+}
+
+/// Wraps another transport with a `LinkConditioner` in each direction, so
+/// backends without GNS's fake-network settings can simulate a bad link.
+/// Messages are impaired after encoding and before the backend sends them,
+/// and again after the backend receives them. Connection events pass through
+/// untouched.
+///
+/// Sends due straight away go out immediately; delayed ones leave on a later
+/// `send` or `update`, so call `update` every poll cycle.
+pub struct ConditionedTransport {
+    inner: Box<dyn Transport>,
+    clock: Box<dyn Fn() -> u64 + Send>,
+    // `send` only has `&self`
+    outgoing: Mutex<Link>,
+    incoming: Link,
+    // added to pings, as GNS does for its fake lag
+    lag_ms: u32,
+}
+
+impl ConditionedTransport {
+    /// `send` impairs what `inner` sends and `recv` what it receives. The same
+    /// seed and traffic always give the same deliveries.
+    pub fn new(inner: Box<dyn Transport>, send: LinkProfile, recv: LinkProfile, seed: u64) -> Self {
+        let epoch = Instant::now();
+        Self::with_clock(inner, send, recv, seed, move || epoch.elapsed().as_micros() as u64)
+    }
+
+    /// Like `new`, with `clock` giving the current time in microseconds.
+    pub fn with_clock(
+        inner: Box<dyn Transport>,
+        send: LinkProfile,
+        recv: LinkProfile,
+        seed: u64,
+        clock: impl Fn() -> u64 + Send + 'static,
+    ) -> Self {
+        let lag_ms = send.lag_ms + recv.lag_ms;
+        let mut seeds = Rng::new(seed);
+        Self {
+            inner,
+            clock: Box::new(clock),
+            outgoing: Mutex::new(Link::new(send, &mut seeds)),
+            incoming: Link::new(recv, &mut seeds),
+            lag_ms,
+        }
+    }
+
+    pub fn send_stats(&self) -> LinkStats {
+        self.outgoing
+            .lock()
+            .map(|outgoing| outgoing.stats())
+            .unwrap_or_default()
+    }
+
+    pub fn recv_stats(&self) -> LinkStats {
+        self.incoming.stats()
+    }
+
+    /// Hands every outgoing message due by now to the backend.
+    fn flush(&self, outgoing: &mut Link) {
+        for (connection, reliable, payload) in outgoing.poll((self.clock)()) {
+            self.inner.send(connection, reliable, &payload);
+        }
+    }
+}
+
+impl Transport for ConditionedTransport {
+    fn listen(_addr: SocketAddr) -> Result<Self, TransportError> {
+        Err(TransportError::Backend(
+            "build a ConditionedTransport from another transport with ConditionedTransport::new".to_string(),
+        ))
+    }
+
+    fn connect(_addr: SocketAddr) -> Result<Self, TransportError> {
+        Err(TransportError::Backend(
+            "build a ConditionedTransport from another transport with ConditionedTransport::new".to_string(),
+        ))
+    }
+
+    fn is_server(&self) -> bool {
+        self.inner.is_server()
+    }
+
+    fn server_connection(&self) -> Option<ConnectionId> {
+        self.inner.server_connection()
+    }
+
+    fn update(&mut self) {
+        if let Ok(mut outgoing) = self.outgoing.lock() {
+            self.flush(&mut outgoing);
+        }
+        self.inner.update();
+    }
+
+    fn accept(&mut self, connection: ConnectionId) -> Result<(), TransportError> {
+        self.inner.accept(connection)
+    }
+
+    fn close(&mut self, connection: ConnectionId, reason: u32, debug: &str) {
+        self.inner.close(connection, reason, debug);
+    }
+
+    fn send(&self, connection: ConnectionId, reliable: bool, payload: &[u8]) {
+        if let Ok(mut outgoing) = self.outgoing.lock() {
+            outgoing.send((self.clock)(), (connection, reliable, payload.to_vec()));
+            self.flush(&mut outgoing);
+        }
+    }
+
+    fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize {
+        self.inner.poll_events(on_event)
+    }
+
+    fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize {
+        let now_us = (self.clock)();
+        let incoming = &mut self.incoming;
+        self.inner.poll_messages(&mut |connection, reliable, payload| {
+            incoming.send(now_us, (connection, reliable, payload.to_vec()));
+        });
+
+        let due = incoming.poll(now_us);
+        for (connection, reliable, payload) in &due {
+            on_message(*connection, *reliable, payload);
+        }
+        due.len()
+    }
+
+    fn ping_ms(&self, connection: ConnectionId) -> Option<u32> {
+        self.inner.ping_ms(connection).map(|ping_ms| ping_ms + self.lag_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::jitter_buffer::{JitterBuffer, SyntheticInput};
+    use crate::data_structures::seq_ring_buffer::SequenceRingBuffer;
+    use crate::packet::{Packet, PlayerInputPacketWire};
+    use crate::transport::LoopbackTransport;
+    use std::{
+        collections::BTreeSet,
+        net::{IpAddr, Ipv4Addr},
+        sync::{
+            Arc,
+            atomic::{AtomicU64, Ordering},
+        },
+    };
+
+    const TICK_US: u64 = 16_667;
+    const SENT: u16 = 100;
+
+    #[derive(Clone)]
+    struct Input(u16);
+
+    impl SyntheticInput for Input {
+        fn decayed(&self, _weight: f64) -> Self {
+            self.clone()
+        }
+
+        fn movement_only(&self) -> Self {
+            self.clone()
+        }
+    }
+
+    fn bad_link() -> LinkProfile {
+        LinkProfile {
+            lag_ms: 20,
+            jitter_ms: 10,
+            loss_pct: 10.0,
+            dup_pct: 10.0,
+            dup_ms_max: 10,
+            reorder_pct: 10.0,
+            reorder_ms: 40,
+        }
+    }
+
+    fn input(sequence_id: u16) -> Vec<u8> {
+        Packet::PlayerInput(PlayerInputPacketWire {
+            sequence_id,
+            timestamp_us: (u32::from(sequence_id) + 1) * TICK_US as u32,
+            move_forward_backward: 1,
+            move_left_right: 0,
+            look_abs: [0.0, 0.0],
+            jump: false,
+            crouch: false,
+            sprint: false,
+            prone: false,
+            peek_left_right: 0,
+        })
+        .encode()
+    }
+
+    /// Sends one input a tick from a client over a conditioned loopback link
+    /// and returns what the server received as `(sequence_id, arrival_us)`,
+    /// along with the client's send stats.
+    fn run(port: u16, seed: u64) -> (Vec<(u16, u64)>, LinkStats) {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+        let mut server = LoopbackTransport::listen(addr).unwrap();
+        let client = LoopbackTransport::connect(addr).unwrap();
+        let connection = client.server_connection().unwrap();
+        let mut connecting = Vec::new();
+        server.poll_events(&mut |event| connecting.push(event));
+        for event in connecting {
+            if let TransportEvent::Connecting { connection, .. } = event {
+                server.accept(connection).unwrap();
+            }
+        }
+
+        let now_us = Arc::new(AtomicU64::new(0));
+        let clock = now_us.clone();
+        let mut client =
+            ConditionedTransport::with_clock(Box::new(client), bad_link(), LinkProfile::default(), seed, move || {
+                clock.load(Ordering::Relaxed)
+            });
+        client.poll_events(&mut |_| {});
+
+        let mut received = Vec::new();
+        for tick in 0..u64::from(SENT) + 10 {
+            now_us.store(tick * TICK_US, Ordering::Relaxed);
+            if tick < u64::from(SENT) {
+                client.send(connection, false, &input(tick as u16));
+            }
+            client.update();
+            server.poll_messages(&mut |_, _, payload| {
+                if let Ok(Packet::PlayerInput(input)) = Packet::decode(payload) {
+                    received.push((input.sequence_id, tick * TICK_US));
+                }
+            });
+        }
+        (received, client.send_stats())
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_deliveries() {
+        let (first, stats) = run(47_001, 7);
+        let (second, _) = run(47_002, 7);
+        let (other, _) = run(47_003, 8);
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+
+        assert_eq!(stats.sent, u64::from(SENT));
+        assert!(
+            stats.dropped > 0 && stats.duplicated > 0 && stats.reordered > 0,
+            "{stats:?}"
+        );
+        assert_eq!(first.len() as u64, stats.sent - stats.dropped + stats.duplicated);
+        assert!(first.windows(2).any(|pair| pair[1].0 < pair[0].0));
+    }
+
+    #[test]
+    fn jitter_buffer_releases_in_order_over_a_bad_link() {
+        let (received, stats) = run(47_004, 11);
+        let mut buffer = JitterBuffer::new();
+        let mut released = Vec::new();
+        let mut arrivals = received.iter().peekable();
+        for tick in 0..u64::from(SENT) + 10 {
+            while let Some((sequence_id, arrival_us)) =
+                arrivals.next_if(|(_, arrival_us)| *arrival_us <= tick * TICK_US)
+            {
+                let sent_us = (u32::from(*sequence_id) + 1) * TICK_US as u32;
+                let fallback_delta = TICK_US as f64 / 1_000_000.0;
+                buffer.enqueue(*sequence_id, sent_us, *arrival_us, fallback_delta, Input(*sequence_id));
+            }
+            released.extend(buffer.consume().into_iter().map(|frame| frame.packet.0));
+        }
+
+        assert!(released.windows(2).all(|pair| pair[0] < pair[1]), "{released:?}");
+        let (first, last) = (released[0], released[released.len() - 1]);
+        let buffer_stats = buffer.stats();
+        assert_eq!(
+            released.len() as u64 + buffer_stats.skipped,
+            u64::from(last - first) + 1
+        );
+        assert!(buffer_stats.skipped >= stats.dropped.min(1));
+        assert!(buffer_stats.duplicates + buffer_stats.late_arrivals > 0);
+    }
+
+    #[test]
+    fn ring_buffer_keeps_one_entry_per_sequence_over_a_bad_link() {
+        let (received, _) = run(47_005, 13);
+        let mut buffer = SequenceRingBuffer::new();
+        for (sequence_id, arrival_us) in &received {
+            buffer.insert(*sequence_id, *arrival_us as i64, *sequence_id);
+        }
+
+        let unique: BTreeSet<u16> = received.iter().map(|(sequence_id, _)| *sequence_id).collect();
+        let first = *unique.first().unwrap();
+        assert_eq!(
+            buffer.get_starting_at(first),
+            unique.iter().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(buffer.len(), unique.len());
+        assert_eq!(buffer.stats().duplicates as usize, received.len() - unique.len());
+        assert!(buffer.stats().late_arrivals > 0);
+        // gaps count as skipped when a newer sequence jumps them, even if reordering fills them in later
+        let missing = usize::from(*unique.last().unwrap() - first) + 1 - unique.len();
+        assert!(buffer.stats().skipped as usize >= missing);
+    }
+}
@@ -0,0 +1,289 @@
+use super::{ConnectionId, REASON_TRANSPORT_DROPPED, Transport, TransportError, TransportEvent};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU32, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+};
+
+const BATCH_SIZE: usize = 256;
+
+// in-memory listeners by address, shared by every loopback transport in the process
+static LISTENERS: OnceLock<Mutex<HashMap<SocketAddr, Sender<Envelope>>>> = OnceLock::new();
+static NEXT_CONNECTION: AtomicU32 = AtomicU32::new(1);
+
+fn listeners() -> &'static Mutex<HashMap<SocketAddr, Sender<Envelope>>> {
+    LISTENERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+enum Envelope {
+    Connect { connection: ConnectionId, reply: Sender<Envelope> },
+    Accepted { connection: ConnectionId },
+    Message { connection: ConnectionId, reliable: bool, payload: Vec<u8> },
+    Closed { connection: ConnectionId, reason: u32 },
+}
+
+struct Peer {
+    sender: Sender<Envelope>,
+    accepted: bool,
+}
+
+/// Transport between endpoints in the same process over channels. Servers
+/// listen on a `SocketAddr` that only exists in memory, clients connect to
+/// it. Delivery is instant, ordered and lossless, reliable or not.
+pub struct LoopbackTransport {
+    addr: SocketAddr,
+    is_server: bool,
+    inbox: Receiver<Envelope>,
+    peers: HashMap<ConnectionId, Peer>,
+    events: VecDeque<TransportEvent>,
+    messages: VecDeque<(ConnectionId, bool, Vec<u8>)>,
+}
+
+impl LoopbackTransport {
+    /// Moves everything that arrived on the channel into the local queues.
+    fn pump(&mut self) {
+        while let Ok(envelope) = self.inbox.try_recv() {
+            match envelope {
+                Envelope::Connect { connection, reply } => {
+                    self.peers.insert(connection, Peer { sender: reply, accepted: false });
+                    self.events.push_back(TransportEvent::Connecting { connection, remote: self.addr.ip() });
+                }
+                Envelope::Accepted { connection } => {
+                    if let Some(peer) = self.peers.get_mut(&connection) {
+                        peer.accepted = true;
+                        self.events.push_back(TransportEvent::Connected { connection, remote: self.addr.ip() });
+                    }
+                }
+                Envelope::Message { connection, reliable, payload } => {
+                    if self.peers.get(&connection).is_some_and(|peer| peer.accepted) {
+                        self.messages.push_back((connection, reliable, payload));
+                    }
+                }
+                Envelope::Closed { connection, reason } => {
+                    if self.peers.remove(&connection).is_some() {
+                        self.events.push_back(TransportEvent::Disconnected { connection, reason });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn listen(addr: SocketAddr) -> Result<Self, TransportError> {
+        let mut listeners = listeners().lock().map_err(|e| TransportError::Backend(e.to_string()))?;
+        if listeners.contains_key(&addr) {
+            return Err(TransportError::AddressInUse(addr));
+        }
+
+        let (sender, inbox) = mpsc::channel();
+        listeners.insert(addr, sender);
+        Ok(Self {
+            addr,
+            is_server: true,
+            inbox,
+            peers: HashMap::new(),
+            events: VecDeque::new(),
+            messages: VecDeque::new(),
+        })
+    }
+
+    fn connect(addr: SocketAddr) -> Result<Self, TransportError> {
+        let server = listeners()
+            .lock()
+            .map_err(|e| TransportError::Backend(e.to_string()))?
+            .get(&addr)
+            .cloned()
+            .ok_or(TransportError::Unreachable(addr))?;
+
+        let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+        let (reply, inbox) = mpsc::channel();
+        server
+            .send(Envelope::Connect { connection, reply })
+            .map_err(|_| TransportError::Unreachable(addr))?;
+
+        let mut peers = HashMap::new();
+        peers.insert(connection, Peer { sender: server, accepted: false });
+        Ok(Self {
+            addr,
+            is_server: false,
+            inbox,
+            peers,
+            events: VecDeque::from([TransportEvent::Connecting { connection, remote: addr.ip() }]),
+            messages: VecDeque::new(),
+        })
+    }
+
+    fn is_server(&self) -> bool {
+        self.is_server
+    }
+
+    fn server_connection(&self) -> Option<ConnectionId> {
+        if self.is_server {
+            return None;
+        }
+        self.peers.keys().next().copied()
+    }
+
+    fn accept(&mut self, connection: ConnectionId) -> Result<(), TransportError> {
+        let peer = self.peers.get_mut(&connection).ok_or(TransportError::UnknownConnection(connection))?;
+        if peer.sender.send(Envelope::Accepted { connection }).is_err() {
+            // the client went away before we got to it; its Closed is already queued
+            return Ok(());
+        }
+        peer.accepted = true;
+        self.events.push_back(TransportEvent::Connected { connection, remote: self.addr.ip() });
+        Ok(())
+    }
+
+    fn close(&mut self, connection: ConnectionId, reason: u32, _debug: &str) {
+        if let Some(peer) = self.peers.remove(&connection) {
+            let _ = peer.sender.send(Envelope::Closed { connection, reason });
+        }
+    }
+
+    fn send(&self, connection: ConnectionId, reliable: bool, payload: &[u8]) {
+        if let Some(peer) = self.peers.get(&connection).filter(|peer| peer.accepted) {
+            let _ = peer.sender.send(Envelope::Message { connection, reliable, payload: payload.to_vec() });
+        }
+    }
+
+    fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize {
+        self.pump();
+        let count = self.events.len().min(BATCH_SIZE);
+        for event in self.events.drain(..count) {
+            on_event(event);
+        }
+        count
+    }
+
+    fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize {
+        self.pump();
+        let count = self.messages.len().min(BATCH_SIZE);
+        for (connection, reliable, payload) in self.messages.drain(..count) {
+            on_message(connection, reliable, &payload);
+        }
+        count
+    }
+
+    fn ping_ms(&self, connection: ConnectionId) -> Option<u32> {
+        self.peers.get(&connection).map(|_| 0)
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        if self.is_server
+            && let Ok(mut listeners) = listeners().lock()
+        {
+            listeners.remove(&self.addr);
+        }
+        for (connection, peer) in self.peers.drain() {
+            let _ = peer.sender.send(Envelope::Closed { connection, reason: REASON_TRANSPORT_DROPPED });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+
+    fn events(transport: &mut LoopbackTransport) -> Vec<TransportEvent> {
+        let mut events = Vec::new();
+        transport.poll_events(&mut |event| events.push(event));
+        events
+    }
+
+    fn messages(transport: &mut LoopbackTransport) -> Vec<(ConnectionId, bool, Vec<u8>)> {
+        let mut messages = Vec::new();
+        transport.poll_messages(&mut |connection, reliable, payload| {
+            messages.push((connection, reliable, payload.to_vec()))
+        });
+        messages
+    }
+
+    /// A server on `port` and a client it accepted, with the connection's id.
+    fn connected_pair(port: u16) -> (LoopbackTransport, LoopbackTransport, ConnectionId) {
+        let mut server = LoopbackTransport::listen(addr(port)).unwrap();
+        let mut client = LoopbackTransport::connect(addr(port)).unwrap();
+        let connection = client.server_connection().unwrap();
+        let remote = addr(port).ip();
+
+        assert_eq!(events(&mut client), vec![TransportEvent::Connecting { connection, remote }]);
+        assert_eq!(events(&mut server), vec![TransportEvent::Connecting { connection, remote }]);
+        server.accept(connection).unwrap();
+        assert_eq!(events(&mut server), vec![TransportEvent::Connected { connection, remote }]);
+        assert_eq!(events(&mut client), vec![TransportEvent::Connected { connection, remote }]);
+        (server, client, connection)
+    }
+
+    #[test]
+    fn connecting_and_accepting_connects_both_ends() {
+        let (server, client, connection) = connected_pair(47_101);
+        assert!(server.is_server());
+        assert_eq!(server.server_connection(), None);
+        assert!(!client.is_server());
+        assert_eq!(server.ping_ms(connection), Some(0));
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let (mut server, mut client, connection) = connected_pair(47_102);
+        client.send(connection, true, b"ping");
+        assert_eq!(messages(&mut server), vec![(connection, true, b"ping".to_vec())]);
+
+        server.send(connection, true, b"pong");
+        server.send(connection, false, b"state");
+        assert_eq!(
+            messages(&mut client),
+            vec![(connection, true, b"pong".to_vec()), (connection, false, b"state".to_vec())]
+        );
+    }
+
+    #[test]
+    fn nothing_is_delivered_before_accepting() {
+        let mut server = LoopbackTransport::listen(addr(47_103)).unwrap();
+        let client = LoopbackTransport::connect(addr(47_103)).unwrap();
+        let connection = client.server_connection().unwrap();
+        client.send(connection, true, b"early");
+        server.send(connection, true, b"early");
+        events(&mut server);
+        assert!(messages(&mut server).is_empty());
+    }
+
+    #[test]
+    fn closing_disconnects_the_other_end() {
+        let (mut server, mut client, connection) = connected_pair(47_104);
+        client.close(connection, 1002, "bye");
+        assert_eq!(events(&mut server), vec![TransportEvent::Disconnected { connection, reason: 1002 }]);
+        assert_eq!(server.ping_ms(connection), None);
+
+        // sends to a closed connection go nowhere
+        client.send(connection, true, b"late");
+        assert!(messages(&mut server).is_empty());
+    }
+
+    #[test]
+    fn dropping_disconnects_with_transport_dropped() {
+        let (server, mut client, connection) = connected_pair(47_105);
+        drop(server);
+        assert_eq!(
+            events(&mut client),
+            vec![TransportEvent::Disconnected { connection, reason: REASON_TRANSPORT_DROPPED }]
+        );
+
+        // the address is free again once its server is gone
+        assert!(matches!(LoopbackTransport::connect(addr(47_105)), Err(TransportError::Unreachable(_))));
+        let _server = LoopbackTransport::listen(addr(47_105)).unwrap();
+        assert!(matches!(LoopbackTransport::listen(addr(47_105)), Err(TransportError::AddressInUse(_))));
+    }
+}
@@ -0,0 +1,117 @@
+//! Connection-oriented message transports. `NetworkDriver` only talks to a
+//! `Transport`, so the same client/server packet flow runs over real sockets
+//! or entirely in memory.
+
+mod conditioned;
+mod loopback;
+mod multi;
+mod threaded;
+mod udp;
+mod websocket;
+
+pub use conditioned::ConditionedTransport;
+pub use loopback::LoopbackTransport;
+pub use multi::MultiTransport;
+pub use threaded::ThreadedTransport;
+pub use udp::UdpTransport;
+pub use websocket::WebSocketTransport;
+
+use crate::packet::Packet;
+use std::{
+    fmt,
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Backend-assigned handle for one connection, unique within a process.
+pub type ConnectionId = u32;
+
+/// Disconnect reason used when the remote end drops its transport without
+/// closing first. Matches GNS's application range.
+pub const REASON_TRANSPORT_DROPPED: u32 = 1000;
+/// The remote end stopped responding.
+pub const REASON_TIMEOUT: u32 = 4001;
+/// A connection attempt was never answered.
+pub const REASON_CONNECT_TIMEOUT: u32 = 5003;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportEvent {
+    /// A peer started connecting. Servers must `accept` or `close` it; clients
+    /// get this for their own connection attempt.
+    Connecting { connection: ConnectionId, remote: IpAddr },
+    Connected { connection: ConnectionId, remote: IpAddr },
+    /// The connection is gone, by the remote end or a local problem.
+    /// `close` it to release anything the backend still holds.
+    Disconnected { connection: ConnectionId, reason: u32 },
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    AddressInUse(SocketAddr),
+    Unreachable(SocketAddr),
+    UnknownConnection(ConnectionId),
+    Backend(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::AddressInUse(addr) => write!(f, "{addr} is already in use"),
+            TransportError::Unreachable(addr) => write!(f, "nothing is listening on {addr}"),
+            TransportError::UnknownConnection(connection) => write!(f, "unknown connection {connection}"),
+            TransportError::Backend(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+/// Takes a received message as `(connection, reliable, payload, decoded)`.
+pub type OnPacket<'a> = dyn FnMut(ConnectionId, bool, &[u8], io::Result<Packet>) + 'a;
+
+/// `Send` so `ThreadedTransport` can move any backend to its thread.
+pub trait Transport: Send {
+    fn listen(addr: SocketAddr) -> Result<Self, TransportError>
+    where
+        Self: Sized;
+
+    fn connect(addr: SocketAddr) -> Result<Self, TransportError>
+    where
+        Self: Sized;
+
+    fn is_server(&self) -> bool;
+
+    /// For clients, the connection to the server; `None` for servers.
+    fn server_connection(&self) -> Option<ConnectionId>;
+
+    /// Lets the backend run its own callbacks; call once per poll cycle.
+    fn update(&mut self) {}
+
+    fn accept(&mut self, connection: ConnectionId) -> Result<(), TransportError>;
+
+    fn close(&mut self, connection: ConnectionId, reason: u32, debug: &str);
+
+    fn send(&self, connection: ConnectionId, reliable: bool, payload: &[u8]);
+
+    fn broadcast(&self, connections: &[ConnectionId], reliable: bool, payload: &[u8]) {
+        for connection in connections {
+            self.send(*connection, reliable, payload);
+        }
+    }
+
+    /// Handles one batch of connection events, returning how many there
+    /// were; 0 means none are pending.
+    fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize;
+
+    /// Handles one batch of received messages as `(connection, reliable,
+    /// payload)`, returning how many there were; 0 means none are pending.
+    fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize;
+
+    /// Like `poll_messages`, with each payload also decoded. Backends that
+    /// decode ahead of time, like `ThreadedTransport`, hand over their result.
+    fn poll_packets(&mut self, on_packet: &mut OnPacket<'_>) -> usize {
+        self.poll_messages(&mut |connection, reliable, payload| {
+            on_packet(connection, reliable, payload, Packet::decode(payload))
+        })
+    }
+
+    fn ping_ms(&self, connection: ConnectionId) -> Option<u32>;
+}
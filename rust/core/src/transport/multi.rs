@@ -0,0 +1,219 @@
+use super::{ConnectionId, Transport, TransportError, TransportEvent};
+use std::{collections::HashMap, net::SocketAddr};
+
+/// Server transport listening on several backends at once, e.g. GNS for
+/// native clients and WebSocket for web ones. Connection ids from each
+/// backend are remapped so they stay unique across all of them.
+pub struct MultiTransport {
+    backends: Vec<Box<dyn Transport>>,
+    // outer id -> (backend index, backend id)
+    routes: HashMap<ConnectionId, (usize, ConnectionId)>,
+    ids: HashMap<(usize, ConnectionId), ConnectionId>,
+    next_id: ConnectionId,
+}
+
+impl MultiTransport {
+    /// `backends` must all be listening servers.
+    pub fn new(backends: Vec<Box<dyn Transport>>) -> Self {
+        Self { backends, routes: HashMap::new(), ids: HashMap::new(), next_id: 0 }
+    }
+
+    fn outer_id(&mut self, backend: usize, connection: ConnectionId) -> ConnectionId {
+        if let Some(id) = self.ids.get(&(backend, connection)) {
+            return *id;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.ids.insert((backend, connection), id);
+        self.routes.insert(id, (backend, connection));
+        id
+    }
+}
+
+impl Transport for MultiTransport {
+    fn listen(_addr: SocketAddr) -> Result<Self, TransportError> {
+        Err(TransportError::Backend("build a MultiTransport from listening backends with MultiTransport::new".to_string()))
+    }
+
+    fn connect(_addr: SocketAddr) -> Result<Self, TransportError> {
+        Err(TransportError::Backend("MultiTransport can only listen".to_string()))
+    }
+
+    fn is_server(&self) -> bool {
+        true
+    }
+
+    fn server_connection(&self) -> Option<ConnectionId> {
+        None
+    }
+
+    fn update(&mut self) {
+        for backend in &mut self.backends {
+            backend.update();
+        }
+    }
+
+    fn accept(&mut self, connection: ConnectionId) -> Result<(), TransportError> {
+        let (backend, inner) = *self.routes.get(&connection).ok_or(TransportError::UnknownConnection(connection))?;
+        self.backends[backend].accept(inner)
+    }
+
+    fn close(&mut self, connection: ConnectionId, reason: u32, debug: &str) {
+        if let Some((backend, inner)) = self.routes.remove(&connection) {
+            self.ids.remove(&(backend, inner));
+            self.backends[backend].close(inner, reason, debug);
+        }
+    }
+
+    fn send(&self, connection: ConnectionId, reliable: bool, payload: &[u8]) {
+        if let Some((backend, inner)) = self.routes.get(&connection) {
+            self.backends[*backend].send(*inner, reliable, payload);
+        }
+    }
+
+    fn broadcast(&self, connections: &[ConnectionId], reliable: bool, payload: &[u8]) {
+        let mut by_backend = vec![Vec::new(); self.backends.len()];
+        for (backend, inner) in connections.iter().filter_map(|connection| self.routes.get(connection)) {
+            by_backend[*backend].push(*inner);
+        }
+        for (backend, inners) in self.backends.iter().zip(by_backend) {
+            if !inners.is_empty() {
+                backend.broadcast(&inners, reliable, payload);
+            }
+        }
+    }
+
+    fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize {
+        let mut events = Vec::new();
+        for (index, backend) in self.backends.iter_mut().enumerate() {
+            backend.poll_events(&mut |event| events.push((index, event)));
+        }
+        for (index, event) in &mut events {
+            let connection = match event {
+                TransportEvent::Connecting { connection, .. }
+                | TransportEvent::Connected { connection, .. }
+                | TransportEvent::Disconnected { connection, .. } => connection,
+            };
+            *connection = self.outer_id(*index, *connection);
+        }
+        for (_, event) in &events {
+            on_event(event.clone());
+        }
+        events.len()
+    }
+
+    fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize {
+        let mut processed = 0;
+        for (index, backend) in self.backends.iter_mut().enumerate() {
+            let ids = &self.ids;
+            processed += backend.poll_messages(&mut |connection, reliable, payload| {
+                // messages only come from connections announced by an event
+                if let Some(id) = ids.get(&(index, connection)) {
+                    on_message(*id, reliable, payload);
+                }
+            });
+        }
+        processed
+    }
+
+    fn ping_ms(&self, connection: ConnectionId) -> Option<u32> {
+        let (backend, inner) = self.routes.get(&connection)?;
+        self.backends[*backend].ping_ms(*inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{LoopbackTransport, REASON_TRANSPORT_DROPPED};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+
+    fn events(transport: &mut dyn Transport) -> Vec<TransportEvent> {
+        let mut events = Vec::new();
+        transport.poll_events(&mut |event| events.push(event));
+        events
+    }
+
+    fn messages(transport: &mut dyn Transport) -> Vec<(ConnectionId, Vec<u8>)> {
+        let mut messages = Vec::new();
+        transport.poll_messages(&mut |connection, _, payload| messages.push((connection, payload.to_vec())));
+        messages
+    }
+
+    /// A `MultiTransport` over two loopback servers with one accepted client
+    /// each, and the multi's ids for those clients.
+    fn connected(ports: [u16; 2]) -> (MultiTransport, [LoopbackTransport; 2], [ConnectionId; 2]) {
+        let backends: Vec<Box<dyn Transport>> =
+            ports.iter().map(|port| Box::new(LoopbackTransport::listen(addr(*port)).unwrap()) as _).collect();
+        let mut multi = MultiTransport::new(backends);
+        let mut clients = ports.map(|port| LoopbackTransport::connect(addr(port)).unwrap());
+
+        let mut ids = Vec::new();
+        for event in events(&mut multi) {
+            if let TransportEvent::Connecting { connection, .. } = event {
+                multi.accept(connection).unwrap();
+                ids.push(connection);
+            }
+        }
+        let connected = events(&mut multi);
+        assert_eq!(connected.len(), 2);
+        assert!(connected.iter().all(|event| matches!(event, TransportEvent::Connected { .. })));
+        for client in &mut clients {
+            assert!(events(client).iter().any(|event| matches!(event, TransportEvent::Connected { .. })));
+        }
+        (multi, clients, [ids[0], ids[1]])
+    }
+
+    #[test]
+    fn connections_are_routed_to_their_backend() {
+        let (mut multi, mut clients, ids) = connected([47_201, 47_202]);
+        assert_ne!(ids[0], ids[1]);
+
+        for (client, payload) in clients.iter().zip([b"first", b"other"]) {
+            client.send(client.server_connection().unwrap(), true, payload);
+        }
+        let mut received = messages(&mut multi);
+        received.sort();
+        let mut expected = vec![(ids[0], b"first".to_vec()), (ids[1], b"other".to_vec())];
+        expected.sort();
+        assert_eq!(received, expected);
+
+        multi.send(ids[1], true, b"only the second");
+        assert!(messages(&mut clients[0]).is_empty());
+        assert_eq!(messages(&mut clients[1]).len(), 1);
+
+        multi.broadcast(&ids, true, b"everyone");
+        for client in &mut clients {
+            let received = messages(client);
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0].1, b"everyone");
+        }
+    }
+
+    #[test]
+    fn closing_releases_only_that_connection() {
+        let (mut multi, mut clients, ids) = connected([47_203, 47_204]);
+        multi.close(ids[0], 1002, "bye");
+        let client_connection = clients[0].server_connection().unwrap();
+        assert_eq!(
+            events(&mut clients[0]),
+            vec![TransportEvent::Disconnected { connection: client_connection, reason: 1002 }]
+        );
+        assert!(events(&mut clients[1]).is_empty());
+        assert_eq!(multi.ping_ms(ids[0]), None);
+        assert_eq!(multi.ping_ms(ids[1]), Some(0));
+        assert!(matches!(multi.accept(ids[0]), Err(TransportError::UnknownConnection(_))));
+
+        // a client going away shows up under the multi's id for it
+        let [_, second] = clients;
+        drop(second);
+        assert_eq!(
+            events(&mut multi),
+            vec![TransportEvent::Disconnected { connection: ids[1], reason: REASON_TRANSPORT_DROPPED }]
+        );
+    }
+}
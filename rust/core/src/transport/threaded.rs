@@ -0,0 +1,375 @@
+use super::{ConnectionId, OnPacket, Transport, TransportError, TransportEvent};
+use crate::net_log;
+use crate::packet::Packet;
+use crossbeam_queue::SegQueue;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const BATCH_SIZE: usize = 256;
+/// Past this many undrained notices the thread stops receiving and lets
+/// the backend buffer them, so a stalled game thread can't grow the queue
+/// without bound.
+const MAX_QUEUED_NOTICES: usize = 16 * 1024;
+/// How long the thread sleeps when there was nothing to do. Sends wake it
+/// straight away.
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+const PING_INTERVAL: Duration = Duration::from_millis(250);
+
+enum Command {
+    Accept(ConnectionId),
+    Close { connection: ConnectionId, reason: u32, debug: String },
+    Send { connection: ConnectionId, reliable: bool, payload: Vec<u8> },
+    Broadcast { connections: Vec<ConnectionId>, reliable: bool, payload: Vec<u8> },
+}
+
+/// Everything the thread hands the game thread, in one queue so messages
+/// never overtake the events of the connection they came from.
+enum Notice {
+    Event(TransportEvent),
+    Ping(ConnectionId, Option<u32>),
+    Message(Received),
+}
+
+struct Received {
+    connection: ConnectionId,
+    reliable: bool,
+    payload: Vec<u8>,
+    packet: io::Result<Packet>,
+}
+
+#[derive(Default)]
+struct Shared {
+    commands: SegQueue<Command>,
+    notices: SegQueue<Notice>,
+    stop: AtomicBool,
+}
+
+/// Runs another transport on a thread of its own, which polls it
+/// continuously and decodes messages as they arrive. The game thread only
+/// drains lock-free queues, so polling under load no longer eats into its
+/// tick, and sends are handed over the same way.
+///
+/// Events and messages arrive in the order the backend produced them:
+/// `poll_events` stops at the first message and `poll_messages` at the first
+/// event, so a connection's messages never come before its `Connected` or
+/// after its `Disconnected`.
+///
+/// Accepting can't fail from the caller's point of view; the thread logs
+/// backend errors instead. Pings are sampled every `PING_INTERVAL`.
+pub struct ThreadedTransport {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+    is_server: bool,
+    server_connection: Option<ConnectionId>,
+    pings: HashMap<ConnectionId, u32>,
+    /// A notice popped by the poll it wasn't for.
+    held: Option<Notice>,
+}
+
+impl ThreadedTransport {
+    /// Moves `transport` to a new network thread. It is dropped there when
+    /// this is.
+    pub fn spawn(transport: Box<dyn Transport>) -> Result<Self, TransportError> {
+        let shared = Arc::new(Shared::default());
+        let is_server = transport.is_server();
+        let server_connection = transport.server_connection();
+        let thread = thread::Builder::new()
+            .name("network".to_string())
+            .spawn({
+                let shared = shared.clone();
+                move || run(transport, &shared)
+            })
+            .map_err(|e| TransportError::Backend(format!("failed to start network thread: {e}")))?;
+        Ok(Self { shared, thread: Some(thread), is_server, server_connection, pings: HashMap::new(), held: None })
+    }
+
+    fn command(&self, command: Command) {
+        self.shared.commands.push(command);
+        if let Some(thread) = self.thread.as_ref() {
+            thread.thread().unpark();
+        }
+    }
+
+    fn next_notice(&mut self) -> Option<Notice> {
+        self.held.take().or_else(|| self.shared.notices.pop())
+    }
+
+    fn note_ping(&mut self, connection: ConnectionId, ping_ms: Option<u32>) {
+        match ping_ms {
+            Some(ping_ms) => self.pings.insert(connection, ping_ms),
+            None => self.pings.remove(&connection),
+        };
+    }
+}
+
+impl Drop for ThreadedTransport {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                if self.is_server {
+                    net_log!(Error, Server, "Network thread panicked");
+                } else {
+                    net_log!(Error, Client, "Network thread panicked");
+                }
+            }
+        }
+    }
+}
+
+fn run(mut transport: Box<dyn Transport>, shared: &Shared) {
+    // connections worth sampling the ping of
+    let mut live: HashSet<ConnectionId> = transport.server_connection().into_iter().collect();
+    let mut last_ping = Instant::now();
+
+    while !shared.stop.load(Ordering::Acquire) {
+        let mut busy = false;
+
+        while let Some(command) = shared.commands.pop() {
+            busy = true;
+            match command {
+                Command::Accept(connection) => {
+                    if let Err(e) = transport.accept(connection) {
+                        net_log!(Warning, Server, "Failed to accept connection {}: {}", connection, e);
+                    }
+                }
+                Command::Close { connection, reason, debug } => {
+                    live.remove(&connection);
+                    transport.close(connection, reason, &debug);
+                }
+                Command::Send { connection, reliable, payload } => transport.send(connection, reliable, &payload),
+                Command::Broadcast { connections, reliable, payload } => {
+                    transport.broadcast(&connections, reliable, &payload);
+                }
+            }
+        }
+
+        transport.update();
+
+        let events = transport.poll_events(&mut |event| {
+            match event {
+                TransportEvent::Connected { connection, .. } => {
+                    live.insert(connection);
+                }
+                TransportEvent::Disconnected { connection, .. } => {
+                    live.remove(&connection);
+                }
+                TransportEvent::Connecting { .. } => {}
+            }
+            shared.notices.push(Notice::Event(event));
+        });
+        busy |= events > 0;
+
+        if shared.notices.len() < MAX_QUEUED_NOTICES {
+            let messages = transport.poll_messages(&mut |connection, reliable, payload| {
+                let packet = Packet::decode(payload);
+                let received = Received { connection, reliable, payload: payload.to_vec(), packet };
+                shared.notices.push(Notice::Message(received));
+            });
+            busy |= messages > 0;
+        }
+
+        let now = Instant::now();
+        if now.duration_since(last_ping) >= PING_INTERVAL {
+            last_ping = now;
+            for connection in &live {
+                shared.notices.push(Notice::Ping(*connection, transport.ping_ms(*connection)));
+            }
+        }
+
+        if !busy {
+            thread::park_timeout(IDLE_WAIT);
+        }
+    }
+}
+
+impl Transport for ThreadedTransport {
+    fn listen(_addr: SocketAddr) -> Result<Self, TransportError> {
+        Err(TransportError::Backend(
+            "build a ThreadedTransport from another transport with ThreadedTransport::spawn".to_string(),
+        ))
+    }
+
+    fn connect(_addr: SocketAddr) -> Result<Self, TransportError> {
+        Err(TransportError::Backend(
+            "build a ThreadedTransport from another transport with ThreadedTransport::spawn".to_string(),
+        ))
+    }
+
+    fn is_server(&self) -> bool {
+        self.is_server
+    }
+
+    fn server_connection(&self) -> Option<ConnectionId> {
+        self.server_connection
+    }
+
+    fn accept(&mut self, connection: ConnectionId) -> Result<(), TransportError> {
+        self.command(Command::Accept(connection));
+        Ok(())
+    }
+
+    fn close(&mut self, connection: ConnectionId, reason: u32, debug: &str) {
+        self.pings.remove(&connection);
+        self.command(Command::Close { connection, reason, debug: debug.to_string() });
+    }
+
+    fn send(&self, connection: ConnectionId, reliable: bool, payload: &[u8]) {
+        self.command(Command::Send { connection, reliable, payload: payload.to_vec() });
+    }
+
+    fn broadcast(&self, connections: &[ConnectionId], reliable: bool, payload: &[u8]) {
+        self.command(Command::Broadcast { connections: connections.to_vec(), reliable, payload: payload.to_vec() });
+    }
+
+    fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize {
+        let mut count = 0;
+        while count < BATCH_SIZE {
+            match self.next_notice() {
+                Some(Notice::Event(event)) => {
+                    if let TransportEvent::Disconnected { connection, .. } = event {
+                        self.pings.remove(&connection);
+                    }
+                    on_event(event);
+                    count += 1;
+                }
+                Some(Notice::Ping(connection, ping_ms)) => self.note_ping(connection, ping_ms),
+                Some(message @ Notice::Message(_)) => {
+                    self.held = Some(message);
+                    break;
+                }
+                None => break,
+            }
+        }
+        count
+    }
+
+    fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize {
+        self.poll_packets(&mut |connection, reliable, payload, _| on_message(connection, reliable, payload))
+    }
+
+    fn poll_packets(&mut self, on_packet: &mut OnPacket<'_>) -> usize {
+        let mut count = 0;
+        while count < BATCH_SIZE {
+            match self.next_notice() {
+                Some(Notice::Message(received)) => {
+                    on_packet(received.connection, received.reliable, &received.payload, received.packet);
+                    count += 1;
+                }
+                Some(Notice::Ping(connection, ping_ms)) => self.note_ping(connection, ping_ms),
+                Some(event @ Notice::Event(_)) => {
+                    self.held = Some(event);
+                    break;
+                }
+                None => break,
+            }
+        }
+        count
+    }
+
+    fn ping_ms(&self, connection: ConnectionId) -> Option<u32> {
+        self.pings.get(&connection).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        net::{IpAddr, Ipv4Addr},
+    };
+
+    /// Hands out one step's events and then its messages per thread loop.
+    struct Scripted {
+        steps: VecDeque<(Vec<TransportEvent>, Vec<Vec<u8>>)>,
+    }
+
+    impl Transport for Scripted {
+        fn listen(_addr: SocketAddr) -> Result<Self, TransportError> {
+            unreachable!()
+        }
+
+        fn connect(_addr: SocketAddr) -> Result<Self, TransportError> {
+            unreachable!()
+        }
+
+        fn is_server(&self) -> bool {
+            true
+        }
+
+        fn server_connection(&self) -> Option<ConnectionId> {
+            None
+        }
+
+        fn accept(&mut self, _connection: ConnectionId) -> Result<(), TransportError> {
+            Ok(())
+        }
+
+        fn close(&mut self, _connection: ConnectionId, _reason: u32, _debug: &str) {}
+
+        fn send(&self, _connection: ConnectionId, _reliable: bool, _payload: &[u8]) {}
+
+        fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize {
+            let events = self.steps.front_mut().map(|(events, _)| std::mem::take(events)).unwrap_or_default();
+            events.iter().cloned().for_each(&mut *on_event);
+            events.len()
+        }
+
+        fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize {
+            let (_, messages) = self.steps.pop_front().unwrap_or_default();
+            messages.iter().for_each(|payload| on_message(1, true, payload));
+            messages.len()
+        }
+
+        fn ping_ms(&self, _connection: ConnectionId) -> Option<u32> {
+            None
+        }
+    }
+
+    fn events(transport: &mut ThreadedTransport) -> Vec<TransportEvent> {
+        let mut events = Vec::new();
+        transport.poll_events(&mut |event| events.push(event));
+        events
+    }
+
+    fn messages(transport: &mut ThreadedTransport) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        transport.poll_messages(&mut |_, _, payload| messages.push(payload.to_vec()));
+        messages
+    }
+
+    #[test]
+    fn messages_stay_between_their_connection_events() {
+        let connected = TransportEvent::Connected { connection: 1, remote: IpAddr::V4(Ipv4Addr::LOCALHOST) };
+        let disconnected = TransportEvent::Disconnected { connection: 1, reason: 0 };
+        let steps = [
+            (vec![connected.clone()], vec![b"first".to_vec(), b"second".to_vec()]),
+            (vec![disconnected.clone()], vec![]),
+        ];
+        let mut transport = ThreadedTransport::spawn(Box::new(Scripted { steps: steps.into() })).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while transport.shared.notices.len() < 4 {
+            assert!(Instant::now() < deadline, "timed out waiting for the network thread");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // everything is queued, but nothing may be read ahead of its event
+        assert!(messages(&mut transport).is_empty());
+        assert_eq!(events(&mut transport), vec![connected]);
+        assert_eq!(messages(&mut transport), vec![b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(events(&mut transport), vec![disconnected]);
+        assert!(messages(&mut transport).is_empty());
+    }
+}
@@ -0,0 +1,773 @@
+//! Plain UDP backend with just enough on top to stand in for GNS: a
+//! connect/accept handshake, keepalives and timeouts, ordered reliable
+//! messages resent until acked, and RTT from acks.
+//!
+//! Every datagram carries at most one message, so payloads must fit in
+//! `MAX_PAYLOAD_LEN`; larger ones are dropped. Layout (little-endian):
+//!
+//! ```text
+//! header:     magic u16 | kind u8 | salt u64
+//! connect:    header
+//! accept:     header
+//! disconnect: header | reason u32
+//! data:       header | seq u16 | ack u16 | ack_bits u32 | flags u8 | [reliable_id u16] | payload
+//! ```
+//!
+//! `salt` is picked by the client per connection attempt so stray datagrams
+//! from an earlier session on the same address are ignored.
+
+use super::{
+    ConnectionId, REASON_CONNECT_TIMEOUT, REASON_TIMEOUT, REASON_TRANSPORT_DROPPED, Transport,
+    TransportError, TransportEvent,
+};
+use crate::math::sequence::seq_diff;
+use crate::net_log;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, VecDeque},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const MAX_PAYLOAD_LEN: usize = 1200;
+
+const MAGIC: u16 = 0xB52A;
+const KIND_CONNECT: u8 = 1;
+const KIND_ACCEPT: u8 = 2;
+const KIND_DISCONNECT: u8 = 3;
+const KIND_DATA: u8 = 4;
+
+const HEADER_LEN: usize = 2 + 1 + 8;
+const DATA_HEADER_LEN: usize = HEADER_LEN + 2 + 2 + 4 + 1;
+const FLAG_HAS_MESSAGE: u8 = 1 << 0;
+const FLAG_RELIABLE: u8 = 1 << 1;
+// set once we've received anything, until then `ack` is meaningless
+const FLAG_HAS_ACK: u8 = 1 << 2;
+
+const CONNECT_RESEND_INTERVAL: Duration = Duration::from_millis(250);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_RESEND_INTERVAL: Duration = Duration::from_millis(50);
+/// How many sent datagrams we remember for acks and RTT.
+const SENT_HISTORY: usize = 256;
+const RECV_BUFFER_LEN: usize = 2048;
+const BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Client waiting for an accept, or server waiting on `accept`.
+    Connecting,
+    Connected,
+}
+
+struct PendingReliable {
+    id: u16,
+    payload: Vec<u8>,
+    last_sent: Option<Instant>,
+}
+
+struct Connection {
+    addr: SocketAddr,
+    salt: u64,
+    state: State,
+    started: Instant,
+    last_received: Instant,
+    last_sent: Instant,
+
+    /* outgoing */
+    next_seq: u16,
+    // seq -> (sent at, reliable id carried)
+    sent: HashMap<u16, (Instant, Option<u16>)>,
+    next_reliable_id: u16,
+    reliable_out: VecDeque<PendingReliable>,
+
+    /* incoming */
+    remote_seq: Option<u16>,
+    ack_bits: u32,
+    ack_pending: bool,
+    next_reliable_in: u16,
+    // reliable messages that arrived ahead of `next_reliable_in`
+    reliable_in: BTreeMap<u16, Vec<u8>>,
+
+    rtt_ms: Option<f64>,
+}
+
+impl Connection {
+    fn new(addr: SocketAddr, salt: u64, now: Instant) -> Self {
+        Self {
+            addr,
+            salt,
+            state: State::Connecting,
+            started: now,
+            last_received: now,
+            last_sent: now,
+            next_seq: 0,
+            sent: HashMap::new(),
+            next_reliable_id: 0,
+            reliable_out: VecDeque::new(),
+            remote_seq: None,
+            ack_bits: 0,
+            ack_pending: false,
+            next_reliable_in: 0,
+            reliable_in: BTreeMap::new(),
+            rtt_ms: None,
+        }
+    }
+
+    fn resend_interval(&self) -> Duration {
+        let rtt = Duration::from_secs_f64(self.rtt_ms.unwrap_or(100.0) * 1.5 / 1000.0);
+        rtt.max(MIN_RESEND_INTERVAL)
+    }
+
+    /// Marks `seq` received. Returns false for duplicates and datagrams too
+    /// old to track.
+    fn note_received(&mut self, seq: u16) -> bool {
+        let Some(remote_seq) = self.remote_seq else {
+            self.remote_seq = Some(seq);
+            self.ack_bits = 0;
+            return true;
+        };
+
+        let diff = seq_diff(seq, remote_seq);
+        if diff > 0 {
+            // the previous newest moves into the bitfield, `diff` behind
+            self.ack_bits = if diff > 32 {
+                0
+            } else {
+                self.ack_bits.checked_shl(diff as u32).unwrap_or(0) | (1 << (diff - 1))
+            };
+            self.remote_seq = Some(seq);
+            true
+        } else if diff == 0 || diff < -32 {
+            false
+        } else {
+            let bit = 1u32 << (-diff - 1);
+            let fresh = self.ack_bits & bit == 0;
+            self.ack_bits |= bit;
+            fresh
+        }
+    }
+
+    /// Processes the ack fields of an incoming datagram.
+    fn handle_acks(&mut self, ack: u16, ack_bits: u32, now: Instant) {
+        let mut acked = vec![ack];
+        for i in 0..32u16 {
+            if ack_bits & (1 << i) != 0 {
+                acked.push(ack.wrapping_sub(i + 1));
+            }
+        }
+
+        for seq in acked {
+            let Some((sent_at, reliable_id)) = self.sent.remove(&seq) else {
+                continue;
+            };
+            let sample = now.duration_since(sent_at).as_secs_f64() * 1000.0;
+            self.rtt_ms = Some(match self.rtt_ms {
+                Some(rtt) => rtt * 0.875 + sample * 0.125,
+                None => sample,
+            });
+            if let Some(id) = reliable_id {
+                self.reliable_out.retain(|pending| pending.id != id);
+            }
+        }
+    }
+
+    /// Queues a received reliable message and returns every one now
+    /// deliverable in order.
+    fn receive_reliable(&mut self, id: u16, payload: Vec<u8>) -> Vec<Vec<u8>> {
+        if seq_diff(id, self.next_reliable_in) < 0 {
+            return Vec::new();
+        }
+        self.reliable_in.insert(id, payload);
+
+        let mut ready = Vec::new();
+        while let Some(payload) = self.reliable_in.remove(&self.next_reliable_in) {
+            ready.push(payload);
+            self.next_reliable_in = self.next_reliable_in.wrapping_add(1);
+        }
+        ready
+    }
+}
+
+fn write_header(buffer: &mut Vec<u8>, kind: u8, salt: u64) {
+    buffer.extend_from_slice(&MAGIC.to_le_bytes());
+    buffer.push(kind);
+    buffer.extend_from_slice(&salt.to_le_bytes());
+}
+
+fn random_salt() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    crate::math::rng::Rng::new(nanos ^ u64::from(std::process::id())).next_u64()
+}
+
+/// Mutable state behind a `RefCell`, since `Transport::send` takes `&self`.
+struct Inner {
+    connections: HashMap<ConnectionId, Connection>,
+    by_addr: HashMap<SocketAddr, ConnectionId>,
+    next_id: ConnectionId,
+    events: VecDeque<TransportEvent>,
+    messages: VecDeque<(ConnectionId, bool, Vec<u8>)>,
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    is_server: bool,
+    inner: RefCell<Inner>,
+}
+
+impl UdpTransport {
+    fn new(socket: UdpSocket, is_server: bool) -> Result<Self, TransportError> {
+        socket
+            .set_nonblocking(true)
+            .map_err(|e| TransportError::Backend(format!("failed to make socket non-blocking: {e}")))?;
+        Ok(Self {
+            socket,
+            is_server,
+            inner: RefCell::new(Inner {
+                connections: HashMap::new(),
+                by_addr: HashMap::new(),
+                next_id: 0,
+                events: VecDeque::new(),
+                messages: VecDeque::new(),
+            }),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn send_control(&self, addr: SocketAddr, kind: u8, salt: u64, reason: Option<u32>) {
+        let mut buffer = Vec::with_capacity(HEADER_LEN + 4);
+        write_header(&mut buffer, kind, salt);
+        if let Some(reason) = reason {
+            buffer.extend_from_slice(&reason.to_le_bytes());
+        }
+        let _ = self.socket.send_to(&buffer, addr);
+    }
+
+    /// Sends one data datagram, optionally carrying a message.
+    fn send_data(&self, connection: &mut Connection, message: Option<(Option<u16>, &[u8])>, now: Instant) {
+        let seq = connection.next_seq;
+        connection.next_seq = connection.next_seq.wrapping_add(1);
+
+        let mut buffer = Vec::with_capacity(DATA_HEADER_LEN + 2 + message.map_or(0, |(_, payload)| payload.len()));
+        write_header(&mut buffer, KIND_DATA, connection.salt);
+        buffer.extend_from_slice(&seq.to_le_bytes());
+        buffer.extend_from_slice(&connection.remote_seq.unwrap_or(0).to_le_bytes());
+        buffer.extend_from_slice(&connection.ack_bits.to_le_bytes());
+
+        let ack_flag = if connection.remote_seq.is_some() { FLAG_HAS_ACK } else { 0 };
+        let mut reliable_id = None;
+        match message {
+            Some((id, payload)) => {
+                let mut flags = FLAG_HAS_MESSAGE | ack_flag;
+                if id.is_some() {
+                    flags |= FLAG_RELIABLE;
+                }
+                buffer.push(flags);
+                if let Some(id) = id {
+                    buffer.extend_from_slice(&id.to_le_bytes());
+                    reliable_id = Some(id);
+                }
+                buffer.extend_from_slice(payload);
+            }
+            None => buffer.push(ack_flag),
+        }
+
+        if connection.sent.len() >= SENT_HISTORY {
+            // forget the oldest so a silent peer can't grow this forever
+            let oldest = connection.sent.keys().copied().min_by_key(|sent| seq_diff(*sent, seq));
+            if let Some(oldest) = oldest {
+                connection.sent.remove(&oldest);
+            }
+        }
+        connection.sent.insert(seq, (now, reliable_id));
+        connection.last_sent = now;
+        connection.ack_pending = false;
+        let _ = self.socket.send_to(&buffer, connection.addr);
+    }
+
+    /// Reads every pending datagram and runs resends, keepalives and timeouts.
+    fn pump(&self) {
+        let now = Instant::now();
+        let mut buffer = [0u8; RECV_BUFFER_LEN];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, addr)) => self.handle_datagram(&buffer[..len], addr, now),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // ICMP errors (e.g. port unreachable) surface here on some platforms
+                Err(_) => continue,
+            }
+        }
+        self.service(now);
+    }
+
+    fn handle_datagram(&self, datagram: &[u8], addr: SocketAddr, now: Instant) {
+        if datagram.len() < HEADER_LEN || u16::from_le_bytes([datagram[0], datagram[1]]) != MAGIC {
+            return;
+        }
+        let kind = datagram[2];
+        let salt = u64::from_le_bytes(datagram[3..11].try_into().expect("slice is 8 bytes"));
+        let body = &datagram[HEADER_LEN..];
+
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+
+        if kind == KIND_CONNECT && self.is_server {
+            let existing = inner
+                .by_addr
+                .get(&addr)
+                .and_then(|id| inner.connections.get(id).map(|connection| (*id, connection.salt, connection.state)));
+            match existing {
+                // a retry of the connect we already know about
+                Some((_, existing_salt, state)) if existing_salt == salt => {
+                    if state == State::Connected {
+                        self.send_control(addr, KIND_ACCEPT, salt, None);
+                    }
+                }
+                // same address, new session: the old one is gone
+                Some((id, _, _)) => {
+                    inner.connections.remove(&id);
+                    inner.events.push_back(TransportEvent::Disconnected { connection: id, reason: REASON_TIMEOUT });
+                    Self::register(inner, addr, salt, now);
+                }
+                None => Self::register(inner, addr, salt, now),
+            }
+            return;
+        }
+
+        let Some(id) = inner.by_addr.get(&addr).copied() else {
+            return;
+        };
+        let Some(connection) = inner.connections.get_mut(&id).filter(|connection| connection.salt == salt) else {
+            return;
+        };
+        connection.last_received = now;
+
+        match kind {
+            KIND_ACCEPT if !self.is_server && connection.state == State::Connecting => {
+                connection.state = State::Connected;
+                inner.events.push_back(TransportEvent::Connected { connection: id, remote: addr.ip() });
+            }
+            KIND_DISCONNECT => {
+                let reason = body.get(0..4).map_or(0, |reason| u32::from_le_bytes(reason.try_into().expect("slice is 4 bytes")));
+                inner.connections.remove(&id);
+                inner.by_addr.remove(&addr);
+                inner.events.push_back(TransportEvent::Disconnected { connection: id, reason });
+            }
+            KIND_DATA if connection.state == State::Connected => {
+                if body.len() < DATA_HEADER_LEN - HEADER_LEN {
+                    return;
+                }
+                let seq = u16::from_le_bytes([body[0], body[1]]);
+                let ack = u16::from_le_bytes([body[2], body[3]]);
+                let ack_bits = u32::from_le_bytes(body[4..8].try_into().expect("slice is 4 bytes"));
+                let flags = body[8];
+                let mut payload = &body[9..];
+
+                if flags & FLAG_HAS_ACK != 0 {
+                    connection.handle_acks(ack, ack_bits, now);
+                }
+                let fresh = connection.note_received(seq);
+                if flags & FLAG_HAS_MESSAGE == 0 || !fresh {
+                    return;
+                }
+
+                if flags & FLAG_RELIABLE != 0 {
+                    if payload.len() < 2 {
+                        return;
+                    }
+                    let reliable_id = u16::from_le_bytes([payload[0], payload[1]]);
+                    payload = &payload[2..];
+                    connection.ack_pending = true;
+                    for ready in connection.receive_reliable(reliable_id, payload.to_vec()) {
+                        inner.messages.push_back((id, true, ready));
+                    }
+                } else {
+                    inner.messages.push_back((id, false, payload.to_vec()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn register(inner: &mut Inner, addr: SocketAddr, salt: u64, now: Instant) {
+        let id = inner.next_id;
+        inner.next_id = inner.next_id.wrapping_add(1);
+        inner.connections.insert(id, Connection::new(addr, salt, now));
+        inner.by_addr.insert(addr, id);
+        inner.events.push_back(TransportEvent::Connecting { connection: id, remote: addr.ip() });
+    }
+
+    fn service(&self, now: Instant) {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+
+        let mut timed_out = Vec::new();
+        for (id, connection) in inner.connections.iter_mut() {
+            match connection.state {
+                State::Connecting => {
+                    if now.duration_since(connection.started) >= CONNECT_TIMEOUT {
+                        timed_out.push((*id, REASON_CONNECT_TIMEOUT));
+                    } else if !self.is_server && now.duration_since(connection.last_sent) >= CONNECT_RESEND_INTERVAL {
+                        self.send_control(connection.addr, KIND_CONNECT, connection.salt, None);
+                        connection.last_sent = now;
+                    }
+                }
+                State::Connected => {
+                    if now.duration_since(connection.last_received) >= IDLE_TIMEOUT {
+                        timed_out.push((*id, REASON_TIMEOUT));
+                        continue;
+                    }
+
+                    let resend_interval = connection.resend_interval();
+                    let due = connection
+                        .reliable_out
+                        .iter()
+                        .filter(|pending| pending.last_sent.is_none_or(|sent| now.duration_since(sent) >= resend_interval))
+                        .map(|pending| (pending.id, pending.payload.clone()))
+                        .collect::<Vec<_>>();
+                    for (reliable_id, payload) in due {
+                        self.send_data(connection, Some((Some(reliable_id), &payload)), now);
+                        if let Some(pending) = connection.reliable_out.iter_mut().find(|pending| pending.id == reliable_id) {
+                            pending.last_sent = Some(now);
+                        }
+                    }
+
+                    if connection.ack_pending || now.duration_since(connection.last_sent) >= KEEPALIVE_INTERVAL {
+                        self.send_data(connection, None, now);
+                    }
+                }
+            }
+        }
+
+        for (id, reason) in timed_out {
+            if let Some(connection) = inner.connections.remove(&id) {
+                inner.by_addr.remove(&connection.addr);
+                inner.events.push_back(TransportEvent::Disconnected { connection: id, reason });
+            }
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn listen(addr: SocketAddr) -> Result<Self, TransportError> {
+        let socket = UdpSocket::bind(addr).map_err(|e| match e.kind() {
+            io::ErrorKind::AddrInUse => TransportError::AddressInUse(addr),
+            _ => TransportError::Backend(format!("failed to bind {addr}: {e}")),
+        })?;
+        Self::new(socket, true)
+    }
+
+    fn connect(addr: SocketAddr) -> Result<Self, TransportError> {
+        let local = match addr.ip() {
+            IpAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            IpAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+        let socket = UdpSocket::bind(local).map_err(|e| TransportError::Backend(format!("failed to bind {local}: {e}")))?;
+        let transport = Self::new(socket, false)?;
+
+        let salt = random_salt();
+        let now = Instant::now();
+        {
+            let mut inner = transport.inner.borrow_mut();
+            inner.connections.insert(0, Connection::new(addr, salt, now));
+            inner.by_addr.insert(addr, 0);
+            inner.next_id = 1;
+            inner.events.push_back(TransportEvent::Connecting { connection: 0, remote: addr.ip() });
+        }
+        transport.send_control(addr, KIND_CONNECT, salt, None);
+        Ok(transport)
+    }
+
+    fn is_server(&self) -> bool {
+        self.is_server
+    }
+
+    fn server_connection(&self) -> Option<ConnectionId> {
+        if self.is_server {
+            return None;
+        }
+        self.inner.borrow().connections.keys().next().copied()
+    }
+
+    fn update(&mut self) {
+        self.pump();
+    }
+
+    fn accept(&mut self, connection: ConnectionId) -> Result<(), TransportError> {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        let target = inner
+            .connections
+            .get_mut(&connection)
+            .filter(|target| target.state == State::Connecting)
+            .ok_or(TransportError::UnknownConnection(connection))?;
+
+        target.state = State::Connected;
+        target.last_received = Instant::now();
+        let (addr, salt) = (target.addr, target.salt);
+        self.send_control(addr, KIND_ACCEPT, salt, None);
+        inner.events.push_back(TransportEvent::Connected { connection, remote: addr.ip() });
+        Ok(())
+    }
+
+    fn close(&mut self, connection: ConnectionId, reason: u32, _debug: &str) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(closed) = inner.connections.remove(&connection) {
+            inner.by_addr.remove(&closed.addr);
+            self.send_control(closed.addr, KIND_DISCONNECT, closed.salt, Some(reason));
+        }
+    }
+
+    fn send(&self, connection: ConnectionId, reliable: bool, payload: &[u8]) {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            net_log!(Error, Packet, "Dropping {} byte UDP message, the limit is {MAX_PAYLOAD_LEN}", payload.len());
+            return;
+        }
+
+        let mut inner = self.inner.borrow_mut();
+        let Some(target) = inner.connections.get_mut(&connection).filter(|target| target.state == State::Connected) else {
+            return;
+        };
+
+        let now = Instant::now();
+        if reliable {
+            let id = target.next_reliable_id;
+            target.next_reliable_id = target.next_reliable_id.wrapping_add(1);
+            target.reliable_out.push_back(PendingReliable { id, payload: payload.to_vec(), last_sent: Some(now) });
+            self.send_data(target, Some((Some(id), payload)), now);
+        } else {
+            self.send_data(target, Some((None, payload)), now);
+        }
+    }
+
+    fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize {
+        self.pump();
+        let events = {
+            let mut inner = self.inner.borrow_mut();
+            let count = inner.events.len().min(BATCH_SIZE);
+            inner.events.drain(..count).collect::<Vec<_>>()
+        };
+        for event in &events {
+            on_event(event.clone());
+        }
+        events.len()
+    }
+
+    fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize {
+        self.pump();
+        let messages = {
+            let mut inner = self.inner.borrow_mut();
+            let count = inner.messages.len().min(BATCH_SIZE);
+            inner.messages.drain(..count).collect::<Vec<_>>()
+        };
+        for (connection, reliable, payload) in &messages {
+            on_message(*connection, *reliable, payload);
+        }
+        messages.len()
+    }
+
+    fn ping_ms(&self, connection: ConnectionId) -> Option<u32> {
+        let inner = self.inner.borrow();
+        let connection = inner.connections.get(&connection)?;
+        Some(connection.rtt_ms.unwrap_or(0.0).round() as u32)
+    }
+}
+
+impl Drop for UdpTransport {
+    fn drop(&mut self) {
+        let inner = self.inner.borrow();
+        for connection in inner.connections.values() {
+            self.send_control(connection.addr, KIND_DISCONNECT, connection.salt, Some(REASON_TRANSPORT_DROPPED));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const WAIT: Duration = Duration::from_secs(2);
+
+    fn localhost() -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)
+    }
+
+    fn connection() -> Connection {
+        Connection::new(localhost(), 1, Instant::now())
+    }
+
+    /// Polls `transport` until `done` says it has seen enough, or fails the test.
+    fn poll_until(transport: &mut UdpTransport, mut done: impl FnMut(&mut UdpTransport) -> bool) {
+        let deadline = Instant::now() + WAIT;
+        while !done(transport) {
+            assert!(Instant::now() < deadline, "timed out polling");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn events(transport: &mut UdpTransport) -> Vec<TransportEvent> {
+        let mut events = Vec::new();
+        transport.poll_events(&mut |event| events.push(event));
+        events
+    }
+
+    fn receive(transport: &mut UdpTransport, count: usize) -> Vec<(bool, Vec<u8>)> {
+        let mut received = Vec::new();
+        poll_until(transport, |transport| {
+            transport.poll_messages(&mut |_, reliable, payload| received.push((reliable, payload.to_vec())));
+            received.len() >= count
+        });
+        received
+    }
+
+    /// A server and a client connected to it, with the server's id for the client.
+    fn connected_pair() -> (UdpTransport, UdpTransport, ConnectionId) {
+        let mut server = UdpTransport::listen(localhost()).unwrap();
+        let mut client = UdpTransport::connect(server.local_addr().unwrap()).unwrap();
+
+        let mut connection = None;
+        poll_until(&mut server, |server| {
+            for event in events(server) {
+                if let TransportEvent::Connecting { connection: id, .. } = event {
+                    connection = Some(id);
+                }
+            }
+            connection.is_some()
+        });
+        let connection = connection.unwrap();
+        server.accept(connection).unwrap();
+        poll_until(&mut client, |client| {
+            events(client).iter().any(|event| matches!(event, TransportEvent::Connected { .. }))
+        });
+        (server, client, connection)
+    }
+
+    #[test]
+    fn messages_arrive_and_reliable_ones_are_acked() {
+        let (mut server, mut client, _) = connected_pair();
+        let to_server = client.server_connection().unwrap();
+        for i in 0..20u8 {
+            client.send(to_server, i % 2 == 0, &[i]);
+        }
+
+        let received = receive(&mut server, 20);
+        let reliable: Vec<u8> =
+            received.iter().filter(|(reliable, _)| *reliable).map(|(_, payload)| payload[0]).collect();
+        assert_eq!(reliable, (0..20).step_by(2).collect::<Vec<_>>());
+        assert_eq!(received.len(), 20);
+
+        poll_until(&mut client, |client| {
+            server.update();
+            client.update();
+            client.inner.borrow().connections[&to_server].reliable_out.is_empty()
+        });
+        assert!(client.ping_ms(to_server).is_some());
+    }
+
+    #[test]
+    fn lost_reliable_message_is_resent() {
+        let (mut server, client, _) = connected_pair();
+        let to_server = client.server_connection().unwrap();
+        client.send(to_server, true, b"hello");
+
+        // lose it: read the datagram off the server's socket behind its back
+        let mut buffer = [0u8; RECV_BUFFER_LEN];
+        let deadline = Instant::now() + WAIT;
+        while server.socket.recv_from(&mut buffer).is_err() {
+            assert!(Instant::now() < deadline, "datagram never arrived");
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut received = Vec::new();
+        server.poll_messages(&mut |_, _, payload| received.push(payload.to_vec()));
+        assert!(received.is_empty());
+
+        let resend_at = Instant::now() + client.inner.borrow().connections[&to_server].resend_interval();
+        client.service(resend_at);
+        assert_eq!(receive(&mut server, 1), vec![(true, b"hello".to_vec())]);
+    }
+
+    #[test]
+    fn reordered_reliable_messages_wait_for_the_gap() {
+        let mut connection = connection();
+        assert!(connection.receive_reliable(2, vec![2]).is_empty());
+        assert!(connection.receive_reliable(1, vec![1]).is_empty());
+        assert_eq!(connection.receive_reliable(0, vec![0]), vec![vec![0], vec![1], vec![2]]);
+        // a resend of something already delivered
+        assert!(connection.receive_reliable(1, vec![1]).is_empty());
+        assert_eq!(connection.receive_reliable(3, vec![3]), vec![vec![3]]);
+    }
+
+    #[test]
+    fn duplicate_and_stale_datagrams_are_dropped() {
+        let mut connection = connection();
+        assert!(connection.note_received(100));
+        assert!(connection.note_received(102));
+        assert!(!connection.note_received(102));
+        assert!(connection.note_received(101));
+        assert!(!connection.note_received(101));
+        assert_eq!(connection.ack_bits, 0b11);
+
+        assert!(connection.note_received(140));
+        assert!(!connection.note_received(107));
+        assert!(connection.note_received(108));
+    }
+
+    #[test]
+    fn sequences_wrap_around() {
+        let mut connection = connection();
+        assert!(connection.note_received(65534));
+        assert!(connection.note_received(1));
+        assert!(connection.note_received(65535));
+        assert_eq!(connection.remote_seq, Some(1));
+        assert_eq!(connection.ack_bits, 0b110);
+
+        connection.next_reliable_in = 65535;
+        assert!(connection.receive_reliable(0, vec![0]).is_empty());
+        assert_eq!(connection.receive_reliable(65535, vec![255]), vec![vec![255], vec![0]]);
+        assert_eq!(connection.next_reliable_in, 1);
+
+        let now = Instant::now();
+        connection.sent.insert(65535, (now, Some(65535)));
+        connection.sent.insert(0, (now, None));
+        connection.sent.insert(1, (now, Some(0)));
+        for id in [65535, 0] {
+            connection.reliable_out.push_back(PendingReliable { id, payload: Vec::new(), last_sent: None });
+        }
+        connection.handle_acks(1, 0b10, now);
+        assert_eq!(connection.sent.keys().collect::<Vec<_>>(), vec![&0]);
+        assert!(connection.reliable_out.is_empty());
+        assert!(connection.rtt_ms.is_some());
+    }
+
+    #[test]
+    fn silent_peer_times_out() {
+        let (_server, mut client, _) = connected_pair();
+        let to_server = client.server_connection().unwrap();
+        client.service(Instant::now() + IDLE_TIMEOUT);
+        assert_eq!(
+            events(&mut client),
+            vec![TransportEvent::Disconnected { connection: to_server, reason: REASON_TIMEOUT }]
+        );
+        assert!(client.server_connection().is_none());
+    }
+
+    #[test]
+    fn unanswered_connect_times_out() {
+        // bound but never polled, so nothing answers
+        let silent = UdpSocket::bind(localhost()).unwrap();
+        let mut client = UdpTransport::connect(silent.local_addr().unwrap()).unwrap();
+        assert!(matches!(events(&mut client)[..], [TransportEvent::Connecting { .. }]));
+
+        client.service(Instant::now() + CONNECT_TIMEOUT);
+        assert!(matches!(
+            events(&mut client)[..],
+            [TransportEvent::Disconnected { reason: REASON_CONNECT_TIMEOUT, .. }]
+        ));
+    }
+}
@@ -0,0 +1,659 @@
+//! WebSocket backend, so web exports and browser tools can join a regular
+//! server. Every message is one binary WebSocket message holding exactly the
+//! bytes handed to `send`, usually a `Packet::encode`.
+//!
+//! The stream is ordered and lossless, so reliable messages simply go out in
+//! order. Unreliable ones are droppable: one still stuck behind a backed up
+//! socket after a full poll is discarded in favour of newer ones instead of
+//! adding to the backlog.
+//!
+//! Closing keeps a connection around, flushed by later polls, until its
+//! queued reliable messages and close frame are out and the peer has
+//! answered, or `CLOSE_TIMEOUT` passes.
+//!
+//! Control messages are text, so they never collide with packets:
+//!
+//! ```text
+//! server -> client: "accept"           connection accepted, packets may flow
+//! close frame:      reason as decimal  code is the reason when it is 4000-4999, else 1000
+//! ```
+//!
+//! Messages received on a WebSocket are always reported as reliable.
+
+use super::{
+    ConnectionId, REASON_CONNECT_TIMEOUT, REASON_TIMEOUT, REASON_TRANSPORT_DROPPED, Transport, TransportError,
+    TransportEvent,
+};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+use tungstenite::{
+    Bytes, ClientHandshake, HandshakeError, Message, ServerHandshake, WebSocket,
+    handshake::{MidHandshake, server::NoCallback},
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
+
+const ACCEPT: &str = "accept";
+const CLIENT_CONNECTION: ConnectionId = 0;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a closed connection keeps flushing its queue and close frame
+/// and waiting for the peer's reply before the socket is dropped anyway.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+const BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Client waiting for "accept", or server waiting on `accept`.
+    Connecting,
+    Connected,
+}
+
+enum Stage {
+    Server(MidHandshake<ServerHandshake<TcpStream, NoCallback>>),
+    Client(MidHandshake<ClientHandshake<TcpStream>>),
+}
+
+/// A TCP connection still doing the HTTP upgrade.
+struct Handshake {
+    remote: SocketAddr,
+    started: Instant,
+    stage: Stage,
+}
+
+enum Progress {
+    Done(Box<WebSocket<TcpStream>>),
+    Pending(Box<Handshake>),
+    Failed,
+}
+
+impl Handshake {
+    fn advance(self: Box<Self>) -> Progress {
+        let Handshake { remote, started, stage } = *self;
+        match stage {
+            Stage::Server(mid) => match mid.handshake() {
+                Ok(socket) => Progress::Done(Box::new(socket)),
+                Err(HandshakeError::Interrupted(mid)) => {
+                    Progress::Pending(Box::new(Handshake { remote, started, stage: Stage::Server(mid) }))
+                }
+                Err(HandshakeError::Failure(_)) => Progress::Failed,
+            },
+            Stage::Client(mid) => match mid.handshake() {
+                Ok((socket, _)) => Progress::Done(Box::new(socket)),
+                Err(HandshakeError::Interrupted(mid)) => {
+                    Progress::Pending(Box::new(Handshake { remote, started, stage: Stage::Client(mid) }))
+                }
+                Err(HandshakeError::Failure(_)) => Progress::Failed,
+            },
+        }
+    }
+}
+
+struct Outgoing {
+    reliable: bool,
+    payload: Vec<u8>,
+    // unreliable and already waited through one poll
+    stale: bool,
+}
+
+struct Connection {
+    socket: Box<WebSocket<TcpStream>>,
+    remote: SocketAddr,
+    state: State,
+    started: Instant,
+    last_received: Instant,
+    last_ping: Instant,
+    // not yet handed to the socket
+    outgoing: VecDeque<Outgoing>,
+    rtt_ms: Option<f64>,
+}
+
+impl Connection {
+    fn new(socket: Box<WebSocket<TcpStream>>, remote: SocketAddr, now: Instant) -> Self {
+        Self {
+            socket,
+            remote,
+            state: State::Connecting,
+            started: now,
+            last_received: now,
+            last_ping: now,
+            outgoing: VecDeque::new(),
+            rtt_ms: None,
+        }
+    }
+
+    /// Hands queued messages to the socket one at a time, stopping as soon as
+    /// it would block so nothing piles up inside tungstenite. Errors mean the
+    /// connection is gone.
+    fn flush(&mut self) -> tungstenite::Result<()> {
+        loop {
+            match self.socket.flush() {
+                Ok(()) => {}
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+
+            let Some(next) = self.outgoing.pop_front() else {
+                return Ok(());
+            };
+            match self.socket.write(Message::binary(next.payload)) {
+                Ok(()) => {}
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn queue(&mut self, reliable: bool, payload: &[u8]) {
+        self.outgoing.push_back(Outgoing { reliable, payload: payload.to_vec(), stale: false });
+    }
+
+    /// Drops unreliable messages still queued from the previous poll and
+    /// marks the rest to go next time.
+    fn drop_stale_unreliable(&mut self) {
+        self.outgoing.retain_mut(|message| message.reliable || !std::mem::replace(&mut message.stale, true));
+    }
+
+    fn send_control(&mut self, message: Message) -> tungstenite::Result<()> {
+        match self.socket.write(message) {
+            Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// A connection that is going away, still sending what it had queued and
+/// then its close frame or its reply to the peer's.
+struct Closing {
+    connection: Connection,
+    // sent once the queue has drained
+    frame: Option<CloseFrame>,
+    deadline: Instant,
+}
+
+impl Closing {
+    /// Closes with `reason`, unless a close frame was already sent or
+    /// received and only needs flushing.
+    fn new(mut connection: Connection, reason: u32, now: Instant) -> Self {
+        connection.outgoing.retain(|message| message.reliable);
+        let frame = connection.socket.can_write().then(|| close_frame(reason));
+        Self { connection, frame, deadline: now + CLOSE_TIMEOUT }
+    }
+
+    /// Pushes the close along. Returns true once the handshake is complete,
+    /// the socket failed or the deadline passed.
+    fn advance(&mut self, now: Instant) -> bool {
+        if now >= self.deadline || self.connection.flush().is_err() {
+            return true;
+        }
+        if !self.connection.outgoing.is_empty() {
+            return false;
+        }
+        if let Some(frame) = self.frame.take() {
+            match self.connection.socket.close(Some(frame)) {
+                Ok(()) => {}
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => return true,
+            }
+        }
+        // reading flushes the close frame and sees the reply, anything else
+        // the peer still sends is dropped
+        loop {
+            match self.connection.socket.read() {
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => return false,
+                Err(_) => return true,
+            }
+        }
+    }
+}
+
+fn close_frame(reason: u32) -> CloseFrame {
+    let code = match u16::try_from(reason) {
+        Ok(code @ 4000..=4999) => CloseCode::from(code),
+        _ => CloseCode::Normal,
+    };
+    CloseFrame { code, reason: reason.to_string().into() }
+}
+
+fn close_reason(frame: Option<&CloseFrame>) -> u32 {
+    let Some(frame) = frame else {
+        return REASON_TRANSPORT_DROPPED;
+    };
+    frame.reason.parse().unwrap_or_else(|_| u32::from(u16::from(frame.code)))
+}
+
+/// Mutable state behind a `RefCell`, since `Transport::send` takes `&self`.
+struct Inner {
+    handshakes: Vec<(Option<ConnectionId>, Box<Handshake>)>,
+    connections: HashMap<ConnectionId, Connection>,
+    closing: Vec<Closing>,
+    next_id: ConnectionId,
+    events: VecDeque<TransportEvent>,
+    messages: VecDeque<(ConnectionId, bool, Vec<u8>)>,
+}
+
+pub struct WebSocketTransport {
+    listener: Option<TcpListener>,
+    epoch: Instant,
+    inner: RefCell<Inner>,
+}
+
+impl WebSocketTransport {
+    fn new(listener: Option<TcpListener>) -> Self {
+        Self {
+            listener,
+            epoch: Instant::now(),
+            inner: RefCell::new(Inner {
+                handshakes: Vec::new(),
+                connections: HashMap::new(),
+                closing: Vec::new(),
+                next_id: 0,
+                events: VecDeque::new(),
+                messages: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.listener {
+            Some(listener) => listener.local_addr(),
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "clients have no listening address")),
+        }
+    }
+
+    /// Accepts new sockets, advances handshakes, reads what arrived, flushes
+    /// what is queued and moves closing connections along.
+    fn pump(&self) {
+        let now = Instant::now();
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+
+        if let Some(listener) = &self.listener {
+            loop {
+                match listener.accept() {
+                    Ok((stream, remote)) => {
+                        if stream.set_nonblocking(true).is_err() || stream.set_nodelay(true).is_err() {
+                            continue;
+                        }
+                        let handshake = match tungstenite::accept(stream) {
+                            Ok(socket) => Progress::Done(Box::new(socket)),
+                            Err(HandshakeError::Interrupted(mid)) => Progress::Pending(Box::new(Handshake {
+                                remote,
+                                started: now,
+                                stage: Stage::Server(mid),
+                            })),
+                            Err(HandshakeError::Failure(_)) => Progress::Failed,
+                        };
+                        Self::finish_handshake(inner, None, remote, handshake, now);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(_) => break,
+                }
+            }
+        }
+
+        for (id, handshake) in std::mem::take(&mut inner.handshakes) {
+            let remote = handshake.remote;
+            if now.duration_since(handshake.started) >= CONNECT_TIMEOUT {
+                if let Some(id) = id {
+                    inner
+                        .events
+                        .push_back(TransportEvent::Disconnected { connection: id, reason: REASON_CONNECT_TIMEOUT });
+                }
+                continue;
+            }
+            Self::finish_handshake(inner, id, remote, handshake.advance(), now);
+        }
+
+        let mut closed = Vec::new();
+        for (id, connection) in inner.connections.iter_mut() {
+            if let Some(reason) = self.service(*id, connection, &mut inner.events, &mut inner.messages, now) {
+                closed.push((*id, reason));
+            }
+        }
+        for (id, reason) in closed {
+            if let Some(connection) = inner.connections.remove(&id) {
+                inner.closing.push(Closing::new(connection, reason, now));
+            }
+            inner.events.push_back(TransportEvent::Disconnected { connection: id, reason });
+        }
+
+        inner.closing.retain_mut(|closing| !closing.advance(now));
+    }
+
+    fn finish_handshake(
+        inner: &mut Inner,
+        id: Option<ConnectionId>,
+        remote: SocketAddr,
+        progress: Progress,
+        now: Instant,
+    ) {
+        match progress {
+            Progress::Done(socket) => {
+                // clients announced their connection when they started connecting
+                let id = id.unwrap_or_else(|| {
+                    let id = inner.next_id;
+                    inner.next_id = inner.next_id.wrapping_add(1);
+                    inner.events.push_back(TransportEvent::Connecting { connection: id, remote: remote.ip() });
+                    id
+                });
+                inner.connections.insert(id, Connection::new(socket, remote, now));
+            }
+            Progress::Pending(handshake) => inner.handshakes.push((id, handshake)),
+            Progress::Failed => {
+                if let Some(id) = id {
+                    inner
+                        .events
+                        .push_back(TransportEvent::Disconnected { connection: id, reason: REASON_TRANSPORT_DROPPED });
+                }
+            }
+        }
+    }
+
+    /// Reads, pings and flushes one connection. Returns the disconnect
+    /// reason if it is gone.
+    fn service(
+        &self,
+        id: ConnectionId,
+        connection: &mut Connection,
+        events: &mut VecDeque<TransportEvent>,
+        messages: &mut VecDeque<(ConnectionId, bool, Vec<u8>)>,
+        now: Instant,
+    ) -> Option<u32> {
+        loop {
+            let message = match connection.socket.read() {
+                Ok(message) => message,
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => return Some(REASON_TRANSPORT_DROPPED),
+            };
+            connection.last_received = now;
+            match message {
+                Message::Binary(payload) if connection.state == State::Connected => {
+                    messages.push_back((id, true, payload.to_vec()));
+                }
+                Message::Text(text)
+                    if self.listener.is_none() && connection.state == State::Connecting && text.as_str() == ACCEPT =>
+                {
+                    connection.state = State::Connected;
+                    events.push_back(TransportEvent::Connected { connection: id, remote: connection.remote.ip() });
+                }
+                Message::Pong(payload) => {
+                    if let Ok(sent_us) = <[u8; 8]>::try_from(payload.as_ref()) {
+                        let sample = (self.now_us().saturating_sub(u64::from_le_bytes(sent_us))) as f64 / 1000.0;
+                        connection.rtt_ms = Some(match connection.rtt_ms {
+                            Some(rtt) => rtt * 0.875 + sample * 0.125,
+                            None => sample,
+                        });
+                    }
+                }
+                Message::Close(frame) => return Some(close_reason(frame.as_ref())),
+                _ => {}
+            }
+        }
+
+        match connection.state {
+            State::Connecting if now.duration_since(connection.started) >= CONNECT_TIMEOUT => {
+                return Some(REASON_CONNECT_TIMEOUT);
+            }
+            State::Connected if now.duration_since(connection.last_received) >= IDLE_TIMEOUT => {
+                return Some(REASON_TIMEOUT);
+            }
+            _ => {}
+        }
+
+        if now.duration_since(connection.last_ping) >= PING_INTERVAL {
+            connection.last_ping = now;
+            let ping = Message::Ping(Bytes::copy_from_slice(&self.now_us().to_le_bytes()));
+            if connection.send_control(ping).is_err() {
+                return Some(REASON_TRANSPORT_DROPPED);
+            }
+        }
+
+        let result = connection.flush();
+        connection.drop_stale_unreliable();
+        result.err().map(|_| REASON_TRANSPORT_DROPPED)
+    }
+
+    fn now_us(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn listen(addr: SocketAddr) -> Result<Self, TransportError> {
+        let listener = TcpListener::bind(addr).map_err(|e| match e.kind() {
+            io::ErrorKind::AddrInUse => TransportError::AddressInUse(addr),
+            _ => TransportError::Backend(format!("failed to bind {addr}: {e}")),
+        })?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| TransportError::Backend(format!("failed to make listener non-blocking: {e}")))?;
+        Ok(Self::new(Some(listener)))
+    }
+
+    /// Opens the TCP connection up front, blocking for at most a few seconds,
+    /// and finishes the upgrade in later polls.
+    fn connect(addr: SocketAddr) -> Result<Self, TransportError> {
+        let stream =
+            TcpStream::connect_timeout(&addr, Duration::from_secs(3)).map_err(|_| TransportError::Unreachable(addr))?;
+        stream
+            .set_nonblocking(true)
+            .and_then(|()| stream.set_nodelay(true))
+            .map_err(|e| TransportError::Backend(format!("failed to configure socket: {e}")))?;
+
+        let transport = Self::new(None);
+        let now = Instant::now();
+        let progress = match tungstenite::client(format!("ws://{addr}/"), stream) {
+            Ok((socket, _)) => Progress::Done(Box::new(socket)),
+            Err(HandshakeError::Interrupted(mid)) => {
+                Progress::Pending(Box::new(Handshake { remote: addr, started: now, stage: Stage::Client(mid) }))
+            }
+            Err(HandshakeError::Failure(e)) => {
+                return Err(TransportError::Backend(format!("websocket handshake failed: {e}")));
+            }
+        };
+        {
+            let mut inner = transport.inner.borrow_mut();
+            inner.next_id = CLIENT_CONNECTION + 1;
+            inner.events.push_back(TransportEvent::Connecting { connection: CLIENT_CONNECTION, remote: addr.ip() });
+            Self::finish_handshake(&mut inner, Some(CLIENT_CONNECTION), addr, progress, now);
+        }
+        Ok(transport)
+    }
+
+    fn is_server(&self) -> bool {
+        self.listener.is_some()
+    }
+
+    fn server_connection(&self) -> Option<ConnectionId> {
+        if self.listener.is_some() {
+            return None;
+        }
+        let inner = self.inner.borrow();
+        let known = inner.connections.contains_key(&CLIENT_CONNECTION) || !inner.handshakes.is_empty();
+        known.then_some(CLIENT_CONNECTION)
+    }
+
+    fn update(&mut self) {
+        self.pump();
+    }
+
+    fn accept(&mut self, connection: ConnectionId) -> Result<(), TransportError> {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        let target = inner
+            .connections
+            .get_mut(&connection)
+            .filter(|target| self.listener.is_some() && target.state == State::Connecting)
+            .ok_or(TransportError::UnknownConnection(connection))?;
+
+        target.state = State::Connected;
+        target.last_received = Instant::now();
+        target
+            .send_control(Message::text(ACCEPT))
+            .and_then(|()| target.flush())
+            .map_err(|e| TransportError::Backend(format!("failed to accept: {e}")))?;
+        let remote = target.remote.ip();
+        inner.events.push_back(TransportEvent::Connected { connection, remote });
+        Ok(())
+    }
+
+    fn close(&mut self, connection: ConnectionId, reason: u32, _debug: &str) {
+        let mut inner = self.inner.borrow_mut();
+        inner.handshakes.retain(|(id, _)| *id != Some(connection));
+        if let Some(closed) = inner.connections.remove(&connection) {
+            let now = Instant::now();
+            let mut closing = Closing::new(closed, reason, now);
+            if !closing.advance(now) {
+                inner.closing.push(closing);
+            }
+        }
+    }
+
+    fn send(&self, connection: ConnectionId, reliable: bool, payload: &[u8]) {
+        let mut inner = self.inner.borrow_mut();
+        let Some(target) = inner.connections.get_mut(&connection).filter(|target| target.state == State::Connected)
+        else {
+            return;
+        };
+        target.queue(reliable, payload);
+        // a dead socket is reported as a disconnect by the next poll
+        let _ = target.flush();
+    }
+
+    fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize {
+        self.pump();
+        let events = {
+            let mut inner = self.inner.borrow_mut();
+            let count = inner.events.len().min(BATCH_SIZE);
+            inner.events.drain(..count).collect::<Vec<_>>()
+        };
+        for event in &events {
+            on_event(event.clone());
+        }
+        events.len()
+    }
+
+    fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize {
+        self.pump();
+        let messages = {
+            let mut inner = self.inner.borrow_mut();
+            let count = inner.messages.len().min(BATCH_SIZE);
+            inner.messages.drain(..count).collect::<Vec<_>>()
+        };
+        for (connection, reliable, payload) in &messages {
+            on_message(*connection, *reliable, payload);
+        }
+        messages.len()
+    }
+
+    fn ping_ms(&self, connection: ConnectionId) -> Option<u32> {
+        let inner = self.inner.borrow();
+        let connection = inner.connections.get(&connection)?;
+        Some(connection.rtt_ms.unwrap_or(0.0).round() as u32)
+    }
+}
+
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        let mut inner = self.inner.borrow_mut();
+        for connection in inner.connections.values_mut() {
+            let _ = connection.socket.close(Some(close_frame(REASON_TRANSPORT_DROPPED)));
+            let _ = connection.socket.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        thread,
+    };
+
+    const WAIT: Duration = Duration::from_secs(2);
+
+    fn poll_until(transport: &mut WebSocketTransport, mut done: impl FnMut(&mut WebSocketTransport) -> bool) {
+        let deadline = Instant::now() + WAIT;
+        while !done(transport) {
+            assert!(Instant::now() < deadline, "timed out polling");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// A server and a client connected to it, with the server's id for the client.
+    fn connected_pair() -> (WebSocketTransport, WebSocketTransport, ConnectionId) {
+        let mut server = WebSocketTransport::listen(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+        let mut client = WebSocketTransport::connect(server.local_addr().unwrap()).unwrap();
+
+        let mut connection = None;
+        poll_until(&mut server, |server| {
+            client.update();
+            server.poll_events(&mut |event| {
+                if let TransportEvent::Connecting { connection: id, .. } = event {
+                    connection = Some(id);
+                }
+            });
+            connection.is_some()
+        });
+        let connection = connection.unwrap();
+        server.accept(connection).unwrap();
+        let mut connected = false;
+        poll_until(&mut client, |client| {
+            client.poll_events(&mut |event| connected |= matches!(event, TransportEvent::Connected { .. }));
+            connected
+        });
+        (server, client, connection)
+    }
+
+    #[test]
+    fn close_delivers_queued_messages_and_reason() {
+        let (mut server, mut client, connection) = connected_pair();
+        server.send(connection, true, b"bye");
+        server.close(connection, 4321, "kicked");
+
+        let mut received = Vec::new();
+        let mut reason = None;
+        poll_until(&mut client, |client| {
+            server.update();
+            client.poll_messages(&mut |_, _, payload| received.push(payload.to_vec()));
+            client.poll_events(&mut |event| {
+                if let TransportEvent::Disconnected { reason: closed, .. } = event {
+                    reason = Some(closed);
+                }
+            });
+            reason.is_some()
+        });
+        assert_eq!(received, vec![b"bye".to_vec()]);
+        assert_eq!(reason, Some(4321));
+
+        poll_until(&mut server, |server| {
+            server.update();
+            client.update();
+            server.inner.borrow().closing.is_empty() && client.inner.borrow().closing.is_empty()
+        });
+    }
+
+    #[test]
+    fn closing_gives_up_after_the_deadline() {
+        let (mut server, _client, connection) = connected_pair();
+        server.close(connection, 4321, "kicked");
+        // the client never polls, so never answers
+        server.update();
+        assert_eq!(server.inner.borrow().closing.len(), 1);
+
+        let later = Instant::now() + CLOSE_TIMEOUT;
+        server.inner.borrow_mut().closing.retain_mut(|closing| !closing.advance(later));
+        assert!(server.inner.borrow().closing.is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+//! Master server for the internet server list. Dedicated servers configured
+//! with `master_server` heartbeat to it and `ServerBrowser` queries it. Small
+//! enough to run next to a local server for tests.
+//!
+//!     br-master --bind 0.0.0.0:45880 --ttl 35
+
+use br_core::registry::{DEFAULT_MASTER_PORT, DEFAULT_SERVER_TTL, MasterServer, RegistryEvent};
+use std::{
+    net::SocketAddr,
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "usage: br-master [--bind <ip:port>] [--ttl <s>]";
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Args {
+    bind: SocketAddr,
+    ttl: Duration,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args { bind: SocketAddr::from(([0, 0, 0, 0], DEFAULT_MASTER_PORT)), ttl: DEFAULT_SERVER_TTL };
+
+        let mut args = args.peekable();
+        while let Some(flag) = args.next() {
+            let (flag, inline_value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (flag, None),
+            };
+            if flag == "--help" || flag == "-h" {
+                return Err(USAGE.to_string());
+            }
+            let value = inline_value
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {flag}"))?;
+            let invalid = |e: &dyn std::fmt::Display| format!("invalid value '{value}' for {flag}: {e}");
+
+            match flag.as_str() {
+                "--bind" => parsed.bind = value.parse().map_err(|e| invalid(&e))?,
+                "--ttl" => parsed.ttl = Duration::from_secs_f64(value.parse().map_err(|e| invalid(&e))?),
+                _ => return Err(format!("unknown flag {flag}\n{USAGE}")),
+            }
+        }
+
+        if parsed.ttl.is_zero() {
+            return Err("--ttl must be positive".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut master = match MasterServer::bind(args.bind, args.ttl) {
+        Ok(master) => master,
+        Err(e) => {
+            eprintln!("ERROR: Failed to bind {}: {e}", args.bind);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("Master server listening on {}", args.bind);
+
+    loop {
+        for event in master.poll(Instant::now()) {
+            let (what, addr) = match event {
+                RegistryEvent::Registered(addr) => ("registered", addr),
+                RegistryEvent::Unregistered(addr) => ("unregistered", addr),
+                RegistryEvent::Expired(addr) => ("expired", addr),
+            };
+            println!("{addr} {what}, {} listed", master.registry().len());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,17 @@
+use br_core::data_structures::buffer_stats::BufferStats;
+use godot::prelude::*;
+
+/// `stats` as the dictionary the buffers' `stats()` return, keyed like the
+/// `BufferStats` fields plus `average_occupancy`.
+pub(super) fn stats_to_dictionary(stats: &BufferStats) -> VarDictionary {
+    vdict! {
+        "late_arrivals": stats.late_arrivals as i64,
+        "duplicates": stats.duplicates as i64,
+        "out_of_window": stats.out_of_window as i64,
+        "skipped": stats.skipped as i64,
+        "underruns": stats.underruns as i64,
+        "overflows": stats.overflows as i64,
+        "average_occupancy": stats.average_occupancy(),
+        "peak_occupancy": stats.peak_occupancy as i64,
+    }
+}
@@ -1,10 +1,13 @@
-use godot::{classes::Engine, prelude::*};
-use crate::math::sequence::{seq_is_newer, seq_diff};
+use super::buffer_stats::stats_to_dictionary;
+use crate::packet::prelude::PlayerInputPacket;
+use br_core::data_structures::jitter_buffer::{
+    GapFill, JitterBuffer as CoreJitterBuffer, SyntheticInput, TimestampedFrame,
+};
+use godot::{
+    classes::Engine,
+    prelude::*,
+};
 use num_traits::FromPrimitive;
-use std::collections::HashMap;
-
-const MAX_FRAMES_PER_TICK: usize = 4;
-const PACKET_LOSS_TOLERANCE: i32 = 4;
 
 #[derive(GodotClass)]
 #[class(no_init, base=RefCounted)]
@@ -15,17 +18,52 @@ struct TimestampedPacket {
     #[var]
     timestamp_us: i64,
     #[var]
+    sequence_id: i64,
+    /// Made up by gap filling rather than received.
+    #[var]
+    synthetic: bool,
+    #[var]
     packet: Variant,
 }
 
+impl TimestampedPacket {
+    fn from_frame(frame: TimestampedFrame<BufferedInput>) -> Gd<Self> {
+        Gd::from_init_fn(|base| Self {
+            base,
+            delta: frame.delta,
+            timestamp_us: i64::from(frame.timestamp_us),
+            sequence_id: i64::from(frame.sequence_id),
+            synthetic: frame.synthetic,
+            packet: frame.packet.0,
+        })
+    }
+}
+
+/// Gap filling makes up `PlayerInputPacket`s; anything else is repeated as is.
+#[derive(Clone)]
+struct BufferedInput(Variant);
+
+impl SyntheticInput for BufferedInput {
+    fn decayed(&self, weight: f64) -> Self {
+        match self.0.try_to::<Gd<PlayerInputPacket>>() {
+            Ok(packet) => Self(packet.bind().decayed(weight).to_variant()),
+            Err(_) => self.clone(),
+        }
+    }
+
+    fn movement_only(&self) -> Self {
+        match self.0.try_to::<Gd<PlayerInputPacket>>() {
+            Ok(packet) => Self(packet.bind().movement_only().to_variant()),
+            Err(_) => self.clone(),
+        }
+    }
+}
+
 #[derive(GodotClass)]
 #[class(base=RefCounted)]
 struct JitterBuffer {
     base: Base<RefCounted>,
-    packets: HashMap<u16, Gd<TimestampedPacket>>,
-    next_sequence_id: u16,
-    last_received_timestamp_us: u32,
-    last_sequence_id: u16,
+    inner: CoreJitterBuffer<BufferedInput>,
 }
 
 #[godot_api]
@@ -33,98 +71,124 @@ impl IRefCounted for JitterBuffer {
     fn init(base: Base<RefCounted>) -> Self {
         Self {
             base,
-            packets: HashMap::with_capacity(64),
-            next_sequence_id: 0,
-            last_received_timestamp_us: 0,
-            last_sequence_id: 65535,
+            inner: CoreJitterBuffer::new(),
         }
     }
 }
 
 #[godot_api]
 impl JitterBuffer {
+    /// `received_us` is when the input arrived, normally the packet's
+    /// `received_us` as stamped by the driver when it polled the message.
     #[func]
-    fn enqueue(&mut self, sequence_id: i64, timestamp_us: i64, packet: Variant) {
+    fn enqueue(&mut self, sequence_id: i64, timestamp_us: i64, received_us: i64, packet: Variant) {
         let Some(sequence_id) = u16::from_i64(sequence_id) else {
             godot_warn!("Invalid sequence id: {sequence_id}");
             return;
         };
 
-        if !seq_is_newer(sequence_id, self.last_sequence_id) {
-            return;
-        }
-
-        self.last_sequence_id = sequence_id;
-
         let Some(rs_timestamp_us) = u32::from_i64(timestamp_us) else {
             godot_warn!("Invalid timestamp: {timestamp_us}");
             return;
         };
 
-
-        let delta = if self.last_received_timestamp_us > 0 {
-            (rs_timestamp_us - self.last_received_timestamp_us) as f64 / 1_000_000.0
-        } else {
-            1.0 / Engine::singleton().get_physics_ticks_per_second() as f64
+        let Some(arrival_us) = u64::from_i64(received_us) else {
+            godot_warn!("Invalid receive time: {received_us}");
+            return;
         };
-        self.last_received_timestamp_us = rs_timestamp_us;
-
-        let ts_packet = Gd::from_init_fn(|base| TimestampedPacket {
-            base,
-            delta,
-            timestamp_us,
-            packet,
-        });
 
-        self.packets.insert(sequence_id, ts_packet);
+        let fallback_delta = 1.0 / Engine::singleton().get_physics_ticks_per_second() as f64;
+        self.inner.enqueue(sequence_id, rs_timestamp_us, arrival_us, fallback_delta, BufferedInput(packet));
     }
 
     #[func]
     fn consume(&mut self) -> Array<Gd<TimestampedPacket>> {
-        for _ in 0..MAX_FRAMES_PER_TICK {
-            // packet available, stop skipping
-            if self.packets.contains_key(&self.next_sequence_id) {
-                break;
-            }
-
-            let diff = seq_diff(self.last_sequence_id, self.next_sequence_id);
-            if diff < PACKET_LOSS_TOLERANCE {
-                return Array::new();
-            }
-
-            self.next_sequence_id = self.next_sequence_id.wrapping_add(1);
-        }
+        self.inner.consume().into_iter().map(TimestampedPacket::from_frame).collect()
+    }
 
-        let mut consumed = Array::new();
-        for _ in 0..MAX_FRAMES_PER_TICK {
-            if let Some(packet) = self.packets.remove(&self.next_sequence_id) {
-                consumed.push(&packet);
-                self.next_sequence_id = self.next_sequence_id.wrapping_add(1);
-            } else {
-                break;
-            }
-        }
+    /* gap filling policies, see set_gap_fill */
+    #[constant]
+    const GAP_FILL_SKIP: i64 = GapFill::Skip as i64;
+    #[constant]
+    const GAP_FILL_REPEAT_DECAY: i64 = GapFill::RepeatDecay as i64;
+    #[constant]
+    const GAP_FILL_HOLD_MOVEMENT: i64 = GapFill::HoldMovement as i64;
+    #[constant]
+    const GAP_FILL_MARK_SYNTHETIC: i64 = GapFill::MarkSynthetic as i64;
+
+    /// What `consume` releases while the next input is late: nothing
+    /// (`GAP_FILL_SKIP`, the default), the last input with decaying movement,
+    /// only its movement, or the last input as is. Made up frames have
+    /// `synthetic` set.
+    #[func]
+    fn set_gap_fill(&mut self, policy: i64) -> bool {
+        let Some(gap_fill) = GapFill::from_i64(policy) else {
+            godot_warn!("Invalid gap fill policy: {policy}");
+            return false;
+        };
+        self.inner.set_gap_fill(gap_fill);
+        true
+    }
+
+    #[func]
+    fn gap_fill(&self) -> i64 {
+        self.inner.gap_fill() as i64
+    }
 
-        consumed
+    /// Inputs that arrived after `consume` had made up a frame in their
+    /// place, matched by `sequence_id`. Drain it every tick to reconcile.
+    #[func]
+    fn take_late_frames(&mut self) -> Array<Gd<TimestampedPacket>> {
+        self.inner.take_late_frames().into_iter().map(TimestampedPacket::from_frame).collect()
     }
 
+    /// Frames currently buffered, to compare against `target_depth`.
     #[func]
     fn size(&self) -> i64 {
-        self.packets.len() as i64
+        self.inner.size() as i64
+    }
+
+    #[func]
+    fn target_depth(&self) -> i64 {
+        self.inner.target_depth() as i64
+    }
+
+    #[func]
+    fn jitter_us(&self) -> f64 {
+        self.inner.jitter_us()
+    }
+
+    #[func]
+    fn starvation_count(&self) -> i64 {
+        self.inner.stats().underruns as i64
     }
 
     #[func]
     fn last_sequence_id(&self) -> i64 {
-        self.last_sequence_id as i64
+        self.inner.last_sequence_id() as i64
     }
 
     #[func]
     fn last_received_timestamp_us(&self) -> i64 {
-        self.last_received_timestamp_us as i64
+        self.inner.last_received_timestamp_us() as i64
     }
 
     #[func]
     fn next_sequence_id(&self) -> i64 {
-        self.next_sequence_id as i64
+        self.inner.next_sequence_id() as i64
+    }
+
+    /// Diagnostics since creation or `reset_stats`: `late_arrivals`,
+    /// `duplicates`, `out_of_window`, `skipped`, `underruns`, `overflows`,
+    /// `average_occupancy` and `peak_occupancy`. Underruns are ticks with no
+    /// input to release, the same as `starvation_count`.
+    #[func]
+    fn stats(&self) -> VarDictionary {
+        stats_to_dictionary(self.inner.stats())
+    }
+
+    #[func]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats();
     }
-}
\ No newline at end of file
+}
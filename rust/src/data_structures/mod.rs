@@ -1,2 +1,3 @@
+mod buffer_stats;
 mod jitter_buffer;
-mod seq_ring_buffer;
\ No newline at end of file
+mod seq_ring_buffer;
@@ -0,0 +1,172 @@
+use crate::packet::prelude::*;
+use br_core::demo::{Demo, SPECTATOR_ID};
+use br_core::net_log;
+use godot::classes::{INode, Node, ProjectSettings};
+use godot::prelude::*;
+
+/// Plays back a demo written by `NetworkDriver.start_demo`, emitting the same
+/// packet objects as `NetworkDriver.on_client_packet` so client code can be
+/// pointed at a replay instead of a live server.
+///
+/// Playback starts with a keyframe assigning the viewer `spectator_id()`, so
+/// every recorded player shows up as a remote one. Listeners should clear
+/// their world on `on_seek`, since a keyframe follows it.
+#[derive(GodotClass)]
+#[class(base=Node)]
+struct DemoPlayer {
+    base: Base<Node>,
+    demo: Option<Demo>,
+    /// Index of the next frame to emit.
+    cursor: usize,
+    /// Fractional tick the playhead is at.
+    playhead: f64,
+    #[var]
+    playing: bool,
+    /// Playback rate, 1.0 is real time. Negative values are treated as 0.
+    #[var]
+    speed: f64,
+}
+
+#[godot_api]
+impl INode for DemoPlayer {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            base,
+            demo: None,
+            cursor: 0,
+            playhead: 0.0,
+            playing: false,
+            speed: 1.0,
+        }
+    }
+
+    fn physics_process(&mut self, delta: f64) {
+        if !self.playing {
+            return;
+        }
+        let Some(demo) = self.demo.as_ref() else {
+            return;
+        };
+
+        self.playhead += delta * self.speed.max(0.0) * demo.tick_rate as f64;
+        let (packets, cursor) = Self::collect_deltas(demo, self.cursor, self.playhead);
+        let finished = cursor >= demo.frames.len();
+        self.cursor = cursor;
+
+        for packet in packets {
+            self.signals().on_client_packet().emit(&packet);
+        }
+
+        if finished {
+            self.playing = false;
+            self.signals().on_finished().emit();
+        }
+    }
+}
+
+#[godot_api]
+impl DemoPlayer {
+    #[signal]
+    fn on_client_packet(packet: Gd<Object>);
+    #[signal]
+    fn on_seek(tick: i64);
+    #[signal]
+    fn on_finished();
+
+    /// Loads a demo and seeks to its first tick. Returns false if the file
+    /// can't be read or has no complete keyframe.
+    #[func]
+    fn load(&mut self, path: String) -> bool {
+        let path = ProjectSettings::singleton().globalize_path(&path).to_string();
+        match Demo::open(&path) {
+            Ok(demo) => {
+                let first_tick = demo.first_tick();
+                self.demo = Some(demo);
+                self.playing = false;
+                self.seek(i64::from(first_tick));
+                true
+            }
+            Err(e) => {
+                net_log!(Error, Packet, "Failed to load demo {}: {}", path, e);
+                false
+            }
+        }
+    }
+
+    #[func]
+    fn play(&mut self) {
+        self.playing = self.demo.is_some();
+    }
+
+    #[func]
+    fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Jumps to `tick` by replaying the nearest keyframe before it and the
+    /// deltas in between. Keeps playing if playback was running.
+    #[func]
+    fn seek(&mut self, tick: i64) {
+        let Some(demo) = self.demo.as_ref() else {
+            return;
+        };
+
+        let tick = tick.clamp(i64::from(demo.first_tick()), i64::from(demo.last_tick())) as u32;
+        let Some(start) = demo.keyframe_at_or_before(tick) else {
+            return;
+        };
+        let mut packets: Vec<Gd<Object>> = demo.frames[start].packets.iter().map(|packet| packet.as_gd()).collect();
+        let (deltas, cursor) = Self::collect_deltas(demo, start + 1, f64::from(tick));
+        packets.extend(deltas);
+
+        self.cursor = cursor;
+        self.playhead = f64::from(tick);
+
+        self.signals().on_seek().emit(i64::from(tick));
+        for packet in packets {
+            self.signals().on_client_packet().emit(&packet);
+        }
+    }
+
+    #[func]
+    fn current_tick(&self) -> i64 {
+        self.playhead as i64
+    }
+
+    #[func]
+    fn first_tick(&self) -> i64 {
+        self.demo.as_ref().map_or(0, |demo| i64::from(demo.first_tick()))
+    }
+
+    #[func]
+    fn last_tick(&self) -> i64 {
+        self.demo.as_ref().map_or(0, |demo| i64::from(demo.last_tick()))
+    }
+
+    #[func]
+    fn tick_rate(&self) -> i64 {
+        self.demo.as_ref().map_or(0, |demo| i64::from(demo.tick_rate))
+    }
+
+    /// Local id the viewer is assigned by keyframes.
+    #[func]
+    fn spectator_id(&self) -> i64 {
+        i64::from(SPECTATOR_ID)
+    }
+
+    /// Packets of every delta frame from `cursor` up to and including
+    /// `up_to_tick`, skipping keyframes, and the index to resume from.
+    fn collect_deltas(demo: &Demo, mut cursor: usize, up_to_tick: f64) -> (Vec<Gd<Object>>, usize) {
+        let mut packets = Vec::new();
+        while let Some(frame) = demo.frames.get(cursor) {
+            if f64::from(frame.tick) > up_to_tick {
+                break;
+            }
+            if !frame.keyframe {
+                packets.extend(frame.packets.iter().map(|packet| packet.as_gd()));
+            }
+            cursor += 1;
+        }
+        (packets, cursor)
+    }
+}
@@ -0,0 +1,219 @@
+use br_core::transport::{ConnectionId, Transport, TransportError, TransportEvent};
+use gns::sys::{
+    ESteamNetworkingConnectionState, k_nSteamNetworkingSend_Reliable, k_nSteamNetworkingSend_Unreliable,
+};
+use gns::{GnsConnection, GnsConnectionEvent, GnsGlobal, GnsSocket, IsClient, IsServer};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+const MAX_MESSAGES_PER_POLL: usize = 256;
+const MAX_EVENTS_PER_POLL: usize = 128;
+
+enum Socket {
+    Server(GnsSocket<IsServer>),
+    Client(GnsSocket<IsClient>),
+}
+
+/// Maps GNS connection handles to transport ids both ways, since
+/// `GnsConnection` can't be built from a plain integer.
+#[derive(Default)]
+struct Connections {
+    ids: HashMap<GnsConnection, ConnectionId>,
+    handles: HashMap<ConnectionId, GnsConnection>,
+    next_id: ConnectionId,
+    // disconnected this poll cycle, known until their last messages are read
+    closing: Vec<(ConnectionId, GnsConnection)>,
+}
+
+impl Connections {
+    fn id_for(&mut self, handle: GnsConnection) -> ConnectionId {
+        if let Some(id) = self.ids.get(&handle) {
+            return *id;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.ids.insert(handle, id);
+        self.handles.insert(id, handle);
+        id
+    }
+
+    fn get(&self, handle: GnsConnection) -> Option<ConnectionId> {
+        self.ids
+            .get(&handle)
+            .copied()
+            .or_else(|| self.closing.iter().find(|(_, closing)| *closing == handle).map(|(id, _)| *id))
+    }
+
+    /// Forgets a connection that went away, keeping it in `closing` until it
+    /// is closed.
+    fn disconnect(&mut self, handle: GnsConnection) -> Option<ConnectionId> {
+        let id = self.ids.remove(&handle)?;
+        self.handles.remove(&id);
+        self.closing.push((id, handle));
+        Some(id)
+    }
+
+    fn remove(&mut self, id: ConnectionId) -> Option<GnsConnection> {
+        if let Some(index) = self.closing.iter().position(|(closing, _)| *closing == id) {
+            return Some(self.closing.swap_remove(index).1);
+        }
+        let handle = self.handles.remove(&id)?;
+        self.ids.remove(&handle);
+        Some(handle)
+    }
+}
+
+/// `Transport` over GameNetworkingSockets, the backend used for real play.
+pub(crate) struct GnsTransport {
+    global: Arc<GnsGlobal>,
+    socket: Socket,
+    connections: Connections,
+}
+
+fn global() -> Result<Arc<GnsGlobal>, TransportError> {
+    GnsGlobal::get().map_err(|e| TransportError::Backend(format!("failed to get GnsGlobal: {e}")))
+}
+
+/// Only connections GNS announced get an id, and they lose it on disconnect.
+fn map_event(connections: &mut Connections, event: GnsConnectionEvent) -> Option<TransportEvent> {
+    match (event.old_state(), event.info().state()) {
+        (
+            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_None,
+            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connecting,
+        ) => {
+            let connection = connections.id_for(event.connection());
+            Some(TransportEvent::Connecting { connection, remote: event.info().remote_address() })
+        }
+        (
+            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connecting,
+            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connected,
+        ) => {
+            let connection = connections.get(event.connection())?;
+            Some(TransportEvent::Connected { connection, remote: event.info().remote_address() })
+        }
+        (
+            _,
+            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_ClosedByPeer
+            | ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_ProblemDetectedLocally,
+        ) => {
+            let connection = connections.disconnect(event.connection())?;
+            Some(TransportEvent::Disconnected { connection, reason: event.info().end_reason() })
+        }
+        // intermediate states only show up in the GNS debug output
+        _ => None,
+    }
+}
+
+impl Transport for GnsTransport {
+    fn listen(addr: SocketAddr) -> Result<Self, TransportError> {
+        let global = global()?;
+        let server = GnsSocket::new(global.clone())
+            .listen(addr.ip(), addr.port())
+            .map_err(|_| TransportError::AddressInUse(addr))?;
+        Ok(Self { global, socket: Socket::Server(server), connections: Connections::default() })
+    }
+
+    fn connect(addr: SocketAddr) -> Result<Self, TransportError> {
+        let global = global()?;
+        let client = GnsSocket::new(global.clone())
+            .connect(addr.ip(), addr.port())
+            .map_err(|_| TransportError::Unreachable(addr))?;
+        let mut connections = Connections::default();
+        connections.id_for(client.connection());
+        Ok(Self { global, socket: Socket::Client(client), connections })
+    }
+
+    fn is_server(&self) -> bool {
+        matches!(self.socket, Socket::Server(_))
+    }
+
+    fn server_connection(&self) -> Option<ConnectionId> {
+        match &self.socket {
+            Socket::Server(_) => None,
+            Socket::Client(client) => self.connections.ids.get(&client.connection()).copied(),
+        }
+    }
+
+    fn update(&mut self) {
+        // disconnects from the last cycle that nobody closed
+        for (_, handle) in std::mem::take(&mut self.connections.closing) {
+            match &self.socket {
+                Socket::Server(server) => server.close_connection(handle, 0, "", false),
+                Socket::Client(client) => client.close_connection(handle, 0, "", false),
+            };
+        }
+        self.global.poll_callbacks();
+    }
+
+    fn accept(&mut self, connection: ConnectionId) -> Result<(), TransportError> {
+        let Socket::Server(server) = &self.socket else {
+            return Err(TransportError::Backend("only servers accept connections".to_string()));
+        };
+        let handle = self.connections.handles.get(&connection).ok_or(TransportError::UnknownConnection(connection))?;
+        server.accept(*handle).map_err(|e| TransportError::Backend(format!("accept failed: {e:?}")))
+    }
+
+    fn close(&mut self, connection: ConnectionId, reason: u32, debug: &str) {
+        let Some(handle) = self.connections.remove(connection) else {
+            return;
+        };
+        match &self.socket {
+            Socket::Server(server) => server.close_connection(handle, reason, debug, false),
+            Socket::Client(client) => client.close_connection(handle, reason, debug, false),
+        };
+    }
+
+    fn send(&self, connection: ConnectionId, reliable: bool, payload: &[u8]) {
+        self.broadcast(&[connection], reliable, payload);
+    }
+
+    fn broadcast(&self, connections: &[ConnectionId], reliable: bool, payload: &[u8]) {
+        let flags = if reliable { k_nSteamNetworkingSend_Reliable } else { k_nSteamNetworkingSend_Unreliable };
+        let messages = connections
+            .iter()
+            .filter_map(|connection| self.connections.handles.get(connection))
+            .map(|handle| self.global.utils().allocate_message(*handle, flags, payload))
+            .collect::<Vec<_>>();
+
+        match &self.socket {
+            Socket::Server(server) => server.send_messages(messages),
+            Socket::Client(client) => client.send_messages(messages),
+        };
+    }
+
+    fn poll_events(&mut self, on_event: &mut dyn FnMut(TransportEvent)) -> usize {
+        let connections = &mut self.connections;
+        let mut handle = |event| {
+            if let Some(event) = map_event(connections, event) {
+                on_event(event);
+            }
+        };
+        match &self.socket {
+            Socket::Server(server) => server.poll_event::<MAX_EVENTS_PER_POLL>(&mut handle),
+            Socket::Client(client) => client.poll_event::<MAX_EVENTS_PER_POLL>(&mut handle),
+        }
+    }
+
+    fn poll_messages(&mut self, on_message: &mut dyn FnMut(ConnectionId, bool, &[u8])) -> usize {
+        let connections = &self.connections;
+        let mut handle = |message: &gns::GnsNetworkMessage<gns::ToReceive>| {
+            let Some(connection) = connections.get(message.connection()) else {
+                return;
+            };
+            on_message(connection, message.flags() & k_nSteamNetworkingSend_Reliable != 0, message.payload());
+        };
+        let processed = match &self.socket {
+            Socket::Server(server) => server.poll_messages::<MAX_MESSAGES_PER_POLL>(&mut handle),
+            Socket::Client(client) => client.poll_messages::<MAX_MESSAGES_PER_POLL>(&mut handle),
+        };
+        processed.unwrap_or(0)
+    }
+
+    fn ping_ms(&self, connection: ConnectionId) -> Option<u32> {
+        let handle = self.connections.handles.get(&connection)?;
+        let status = match &self.socket {
+            Socket::Server(server) => server.get_connection_real_time_status(*handle, 0),
+            Socket::Client(client) => client.get_connection_real_time_status(*handle, 0),
+        };
+        status.ok().map(|(status, _)| status.ping())
+    }
+}
@@ -0,0 +1,168 @@
+use crate::server_info::ServerInfo;
+use br_core::discovery::{
+    Beacon, BeaconBroadcaster, DEFAULT_BEACON_INTERVAL, DEFAULT_EXPIRY, DISCOVERY_PORT, DiscoveryEvent,
+    DiscoveryListener,
+};
+use br_core::net_log;
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use std::time::Instant;
+
+/// Advertises a server on the LAN and/or browses for others. A dedicated or
+/// listen server calls `start_advertising`, a main menu `start_browsing`.
+#[derive(GodotClass)]
+#[class(base=Node)]
+struct LanDiscovery {
+    base: Base<Node>,
+    broadcaster: Option<BeaconBroadcaster>,
+    listener: Option<DiscoveryListener>,
+    /// UDP port beacons go to; only change it before starting.
+    #[var]
+    discovery_port: i64,
+}
+
+#[godot_api]
+impl INode for LanDiscovery {
+    fn init(base: Base<Node>) -> Self {
+        Self { base, broadcaster: None, listener: None, discovery_port: i64::from(DISCOVERY_PORT) }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        let now = Instant::now();
+
+        if let Some(broadcaster) = self.broadcaster.as_mut()
+            && let Err(e) = broadcaster.update(now)
+        {
+            net_log!(Error, Server, "Stopped advertising on the LAN: {}", e);
+            self.broadcaster = None;
+        }
+
+        let Some(listener) = self.listener.as_mut() else {
+            return;
+        };
+        for event in listener.update(now) {
+            match event {
+                DiscoveryEvent::Discovered(server) => {
+                    let info = ServerInfo::from_beacon(server.addr, &server.beacon);
+                    self.signals().on_server_discovered().emit(&info);
+                }
+                DiscoveryEvent::Updated(server) => {
+                    let info = ServerInfo::from_beacon(server.addr, &server.beacon);
+                    self.signals().on_server_updated().emit(&info);
+                }
+                DiscoveryEvent::Lost(server) => {
+                    let info = ServerInfo::from_beacon(server.addr, &server.beacon);
+                    self.signals().on_server_lost().emit(&info);
+                }
+            }
+        }
+    }
+}
+
+#[godot_api]
+impl LanDiscovery {
+    #[signal]
+    fn on_server_discovered(info: Gd<ServerInfo>);
+    /// A known server's player count, map or name changed.
+    #[signal]
+    fn on_server_updated(info: Gd<ServerInfo>);
+    /// No beacon from the server for a few seconds.
+    #[signal]
+    fn on_server_lost(info: Gd<ServerInfo>);
+
+    /// Starts broadcasting this machine's server, replacing any previous
+    /// advertisement. `port` is the game port clients should connect to.
+    #[func]
+    fn start_advertising(&mut self, name: String, map: String, port: i64, capacity: i64) -> bool {
+        let (Some(discovery_port), Ok(port)) = (self.port(), u16::try_from(port)) else {
+            net_log!(Error, Server, "Invalid discovery port {} or game port {}", self.discovery_port, port);
+            return false;
+        };
+        let capacity = u16::try_from(capacity).unwrap_or(u16::MAX);
+        let beacon = Beacon::new(name, map, 0, capacity, port);
+        match BeaconBroadcaster::new(beacon, discovery_port, DEFAULT_BEACON_INTERVAL) {
+            Ok(broadcaster) => {
+                self.broadcaster = Some(broadcaster);
+                true
+            }
+            Err(e) => {
+                net_log!(Error, Server, "Failed to advertise on the LAN: {}", e);
+                false
+            }
+        }
+    }
+
+    #[func]
+    fn stop_advertising(&mut self) {
+        self.broadcaster = None;
+    }
+
+    #[func]
+    fn is_advertising(&self) -> bool {
+        self.broadcaster.is_some()
+    }
+
+    #[func]
+    fn set_player_count(&mut self, player_count: i64) {
+        let player_count = u16::try_from(player_count).unwrap_or(u16::MAX);
+        self.update_beacon(|beacon| beacon.player_count = player_count);
+    }
+
+    #[func]
+    fn set_map(&mut self, map: String) {
+        self.update_beacon(|beacon| beacon.map = map);
+    }
+
+    /// Starts listening for beacons. Servers already known are forgotten.
+    #[func]
+    fn start_browsing(&mut self) -> bool {
+        let Some(discovery_port) = self.port() else {
+            net_log!(Error, Client, "Invalid discovery port {}", self.discovery_port);
+            return false;
+        };
+        match DiscoveryListener::new(discovery_port, DEFAULT_EXPIRY) {
+            Ok(listener) => {
+                self.listener = Some(listener);
+                true
+            }
+            Err(e) => {
+                net_log!(Error, Client, "Failed to listen for LAN servers: {}", e);
+                false
+            }
+        }
+    }
+
+    #[func]
+    fn stop_browsing(&mut self) {
+        self.listener = None;
+    }
+
+    #[func]
+    fn is_browsing(&self) -> bool {
+        self.listener.is_some()
+    }
+
+    /// Every server currently known.
+    #[func]
+    fn servers(&self) -> Array<Gd<ServerInfo>> {
+        self.listener
+            .as_ref()
+            .map(|listener| {
+                listener.servers().map(|server| ServerInfo::from_beacon(server.addr, &server.beacon)).collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn port(&self) -> Option<u16> {
+        u16::try_from(self.discovery_port).ok().filter(|port| *port != 0)
+    }
+
+    fn update_beacon(&mut self, change: impl FnOnce(&mut Beacon)) {
+        let Some(broadcaster) = self.broadcaster.as_mut() else {
+            return;
+        };
+        let mut beacon = broadcaster.beacon().clone();
+        change(&mut beacon);
+        broadcaster.set_beacon(beacon);
+    }
+}
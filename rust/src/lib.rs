@@ -1,7 +1,15 @@
 use godot::prelude::*;
 
+mod demo_player;
+mod gns_transport;
+mod lan_discovery;
 mod network_driver;
+mod network_error;
 mod packet;
+mod server_browser;
+mod server_info;
+mod server_query;
+mod transport;
 mod data_structures;
 mod math;
 
@@ -1,24 +1,7 @@
+use br_core::math::sequence::{SEQUENCE_MODULO, seq_diff, seq_is_newer};
 use godot::prelude::*;
 use num_traits::FromPrimitive;
 
-pub const SEQUENCE_MODULO: i32 = 65536;
-pub const SEQUENCE_HALF_RANGE: i32 = SEQUENCE_MODULO / 2;
-
-pub fn seq_is_newer(a: u16, b: u16) -> bool {
-    seq_diff(a, b) > 0
-}
-
-pub fn seq_diff(a: u16, b: u16) -> i32 {
-    let mut diff = (a as i32) - (b as i32);
-
-    if diff > SEQUENCE_HALF_RANGE {
-        diff -= SEQUENCE_MODULO;
-    } else if diff < -SEQUENCE_HALF_RANGE {
-        diff += SEQUENCE_MODULO;
-    }
-    diff
-}
-
 #[derive(GodotClass)]
 #[class(base=RefCounted)]
 struct PacketSequence {
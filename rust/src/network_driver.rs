@@ -1,60 +1,91 @@
 use crate::packet::prelude::*;
-use gns::sys::{
-    ESteamNetworkingConfigValue, ESteamNetworkingConnectionState,
-    ESteamNetworkingSocketsDebugOutputType, k_nSteamNetworkingSend_Reliable,
-};
-use gns::{
-    GnsConfig, GnsConnection, GnsGlobal, GnsSocket, IsClient, IsServer,
-    sys::k_nSteamNetworkingSend_Unreliable,
+use br_core::capture::{CaptureRole, CaptureWriter, Direction};
+use br_core::config::{LinkSimulationConfig, ServerConfig};
+use br_core::demo::DemoWriter;
+use br_core::discovery::Beacon;
+use br_core::link_conditioner::LinkProfile;
+use br_core::lobby::{Lobby, LobbyPhase, LobbySettings};
+use br_core::metrics::{MetricsEndpoint, ServerMetrics};
+use br_core::log::{LogCategory, LogLevel, RotatingLogFile, logger};
+use br_core::net_log;
+use br_core::registry::Heartbeater;
+use br_core::server_query::{QueryPlayer, QueryResponder, ServerStatus};
+use br_core::data_structures::peer_slots::PeerSlots;
+use br_core::transport::{
+    ConditionedTransport, ConnectionId, ThreadedTransport, Transport, TransportError, TransportEvent,
 };
+use crate::network_error::{NetworkError, parse_ip, parse_port};
+use crate::server_browser::resolve_master;
+use crate::transport::TransportKind;
+use gns::sys::{ESteamNetworkingConfigValue, ESteamNetworkingSocketsDebugOutputType};
+use gns::{GnsConfig, GnsGlobal};
 use godot::classes::INode;
 use godot::classes::Node;
+use godot::classes::{Engine, Os, ProjectSettings, Time};
 use godot::prelude::*;
-use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::Arc;
 use std::{
-    collections::{HashMap, VecDeque},
-    net::{IpAddr, Ipv4Addr},
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::BufWriter,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
     time::{Duration, Instant},
 };
 
 const DEFAULT_IP_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
 const DEFAULT_PORT: i64 = 45876;
-const PLAYER_COUNT: u8 = 100;
-const MAX_MESSAGES_PER_POLL: usize = 256;
-const MAX_EVENTS_PER_POLL: usize = 128;
+const DEFAULT_CAPACITY: usize = 100;
+const DEFAULT_RESERVED_SLOTS: usize = 0;
 const POLL_TIME_BUDGET_MS: u64 = 2;
-
-/* TODO: unwrap must be banned */
-
-// Global debug message queue accessible from GNS callbacks
-static DEBUG_QUEUE: OnceLock<Arc<Mutex<VecDeque<String>>>> = OnceLock::new();
-
-// Helper function to queue debug messages from GNS callbacks
-fn queue_debug_message(prefix: &str, ty: ESteamNetworkingSocketsDebugOutputType, message: String) {
-    if let Some(queue) = DEBUG_QUEUE.get() {
-        if let Ok(mut q) = queue.lock() {
-            q.push_back(format!("[{}] {ty:#?}: {message}", prefix));
-            // Limit queue size to prevent memory issues
-            if q.len() > 1000 {
-                q.pop_front();
-            }
+const DEFAULT_MESSAGE_BUDGET: usize = 1024;
+const DEFAULT_DEMO_KEYFRAME_SECONDS: u32 = 5;
+const PING_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Batched packets waiting for `drain_packets` beyond this drop the oldest.
+const MAX_PENDING_PACKETS: usize = 16 * 1024;
+
+// GNS calls this from its own thread, the logger is thread-safe
+fn gns_debug_callback(ty: ESteamNetworkingSocketsDebugOutputType, message: String) {
+    use ESteamNetworkingSocketsDebugOutputType::*;
+    let level = match ty {
+        k_ESteamNetworkingSocketsDebugOutputType_Bug | k_ESteamNetworkingSocketsDebugOutputType_Error => LogLevel::Error,
+        k_ESteamNetworkingSocketsDebugOutputType_Important | k_ESteamNetworkingSocketsDebugOutputType_Warning => {
+            LogLevel::Warning
         }
-    }
+        k_ESteamNetworkingSocketsDebugOutputType_Msg => LogLevel::Info,
+        k_ESteamNetworkingSocketsDebugOutputType_Verbose => LogLevel::Verbose,
+        k_ESteamNetworkingSocketsDebugOutputType_Debug => LogLevel::Debug,
+        _ => LogLevel::Everything,
+    };
+    logger().log_with(level, LogCategory::Gns, None, || message);
 }
 
-// Function pointer compatible callbacks for GNS
-fn server_debug_callback(ty: ESteamNetworkingSocketsDebugOutputType, message: String) {
-    queue_debug_message("GNS Server", ty, message);
+/// Godot's monotonic clock, which received packets are stamped with.
+fn ticks_usec() -> i64 {
+    i64::try_from(Time::singleton().get_ticks_usec()).unwrap_or(i64::MAX)
 }
 
-fn client_debug_callback(ty: ESteamNetworkingSocketsDebugOutputType, message: String) {
-    queue_debug_message("GNS Client", ty, message);
+fn lobby_phase_id(phase: Option<LobbyPhase>) -> i64 {
+    phase.map_or(-1, |phase| phase as i64)
 }
 
-fn i64_to_u32(value: i64) -> u32 {
-    value.try_into().map_err(|e| {
-        godot_print!("ERROR: Failed to convert {value} to u32: {:#?}", e);
-    }).unwrap_or(0)
+fn gns_debug_level(level: LogLevel) -> ESteamNetworkingSocketsDebugOutputType {
+    match level {
+        LogLevel::Off => ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_None,
+        LogLevel::Error => ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_Error,
+        LogLevel::Warning => ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_Warning,
+        LogLevel::Info => ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_Msg,
+        LogLevel::Verbose => ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_Verbose,
+        LogLevel::Debug => ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_Debug,
+        LogLevel::Everything => ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_Everything,
+    }
+}
+
+/// An open traffic capture; timestamps are relative to `started`.
+struct Recording {
+    writer: CaptureWriter<BufWriter<File>>,
+    started: Instant,
 }
 
 #[derive(GodotClass)]
@@ -63,62 +94,212 @@ struct NetworkDriver {
     base: Base<Node>,
 
     /* server-side vars */
-    server: Option<GnsSocket<IsServer>>,
-    available_peer_ids: Vec<u8>,
-    connected_clients: HashMap<GnsConnection, u8>,
+    peer_slots: PeerSlots,
+    reserved_addresses: HashSet<IpAddr>,
+    connected_clients: HashMap<ConnectionId, PeerId>,
+    /// The host's own player in listen-server mode.
+    local_peer: Option<PeerId>,
+    local_peer_joined: bool,
+    // host player -> server and server -> host player, delivered next poll
+    local_inbox: RefCell<VecDeque<Packet>>,
+    local_outbox: RefCell<VecDeque<Packet>>,
+    server_port: u16,
+    // keeps this server on the master server's list
+    heartbeater: Option<Heartbeater>,
+    // servers answer status queries here when non-zero
+    query_port: u16,
+    query_responder: Option<QueryResponder>,
+    server_name: String,
+    map_name: String,
+    match_phase: String,
+    player_names: HashMap<PeerId, String>,
+    lobby: Option<Lobby>,
+    // gathered while serving, scraped through metrics_endpoint
+    metrics: RefCell<ServerMetrics>,
+    metrics_endpoint: Option<MetricsEndpoint>,
+    last_ping_sample: Instant,
 
     /* client-side vars */
-    client: Option<GnsSocket<IsClient>>,
     #[var]
     client_ping: i64,
+    // last phase the server replicated
+    client_lobby_phase: Option<LobbyPhase>,
 
     /* common vars */
+    transport: Option<Box<dyn Transport>>,
+    transport_kind: TransportKind,
+    // servers also take WebSocket clients here when non-zero
+    websocket_port: u16,
+    // simulated link for the next server on a backend other than GNS
+    link_simulation: Option<LinkSimulationConfig>,
     #[var]
     is_connected: bool,
     #[var]
     is_server: bool,
-    gns_global: Arc<GnsGlobal>,
+    /// Snapshots per second gameplay code should broadcast, set from the server config.
+    #[var]
+    send_rate: i64,
+    // only the GNS transport and its fake link settings need it
+    gns_global: Option<Arc<GnsGlobal>>,
+    max_messages_per_poll: usize,
+    poll_time_budget: Duration,
+    /// Run the next server or client's transport on its own thread, which
+    /// polls and decodes continuously; the poll budgets then only bound
+    /// handing packets to Godot each tick.
+    #[var]
+    network_thread: bool,
+    /// Queue received packets for `drain_packets` instead of emitting
+    /// `on_server_packet`, `on_client_packet` and their typed signals for
+    /// each. Drain it every tick; past `MAX_PENDING_PACKETS` the oldest are
+    /// dropped and reported on the next drain.
+    #[var]
+    batch_packets: bool,
+    // (peer id, received_us, packet) since the last drain, peer id -1 for
+    // packets from the server
+    pending_packets: VecDeque<(i64, i64, Packet)>,
+    pending_packets_dropped: u64,
+    // refilled by every drain so its packed arrays keep their storage
+    packet_batch: Option<Gd<PacketBatch>>,
+    packet_handlers: PacketHandlers,
+    // reused by every dispatch so calling handlers doesn't allocate
+    handler_scratch: Vec<Callable>,
+    // send and poll paths only borrow self immutably
+    recording: RefCell<Option<Recording>>,
+    demo: RefCell<Option<DemoWriter<BufWriter<File>>>>,
+    demo_tick: u32,
     // last_update: Instant,
-
-    /* thread-safe debug message queue */
-    debug_messages: Arc<Mutex<VecDeque<String>>>,
+    /// Also print every log record to the Godot output, not just `on_log`.
+    #[var]
+    print_logs: bool,
 }
 
 #[godot_api]
 impl INode for NetworkDriver {
     fn init(base: Base<Node>) -> Self {
-        let gns_global = GnsGlobal::get().unwrap_or_else(|_| {
-            godot_print!("ERROR: Failed to get GnsGlobal instance");
-            panic!("GnsGlobal initialization failed");
-        });
-
-        // Initialize global debug queue
-        let debug_queue = Arc::new(Mutex::new(VecDeque::new()));
-        let _ = DEBUG_QUEUE.set(debug_queue.clone());
+        let gns_global = GnsGlobal::get()
+            .map_err(|e| net_log!(Error, Server, "GameNetworkingSockets is unavailable: {}", e))
+            .ok();
 
         Self {
             base,
             is_connected: false,
             is_server: false,
+            send_rate: i64::from(Engine::singleton().get_physics_ticks_per_second()),
             gns_global,
-            server: None,
+            max_messages_per_poll: DEFAULT_MESSAGE_BUDGET,
+            poll_time_budget: Duration::from_millis(POLL_TIME_BUDGET_MS),
+            network_thread: false,
+            batch_packets: false,
+            pending_packets: VecDeque::new(),
+            pending_packets_dropped: 0,
+            packet_batch: None,
+            packet_handlers: PacketHandlers::default(),
+            handler_scratch: Vec::new(),
+            recording: RefCell::new(None),
+            demo: RefCell::new(None),
+            demo_tick: 0,
+            transport: None,
+            transport_kind: TransportKind::Gns,
+            websocket_port: 0,
+            link_simulation: None,
             // last_update: Instant::now(),
-            available_peer_ids: (0..PLAYER_COUNT).rev().collect(),
+            peer_slots: PeerSlots::new(DEFAULT_CAPACITY, DEFAULT_RESERVED_SLOTS)
+                .expect("default capacity is within the peer id range"),
+            reserved_addresses: HashSet::new(),
             connected_clients: HashMap::new(),
-            client: None,
+            local_peer: None,
+            local_peer_joined: false,
+            local_inbox: RefCell::new(VecDeque::new()),
+            local_outbox: RefCell::new(VecDeque::new()),
+            server_port: 0,
+            heartbeater: None,
+            query_port: 0,
+            query_responder: None,
+            server_name: String::new(),
+            map_name: String::new(),
+            match_phase: String::new(),
+            player_names: HashMap::new(),
+            lobby: None,
+            metrics: RefCell::new(ServerMetrics::default()),
+            metrics_endpoint: None,
+            last_ping_sample: Instant::now(),
             client_ping: 0,
-            debug_messages: debug_queue,
+            client_lobby_phase: None,
+            print_logs: true,
         }
     }
 
     fn physics_process(&mut self, _delta: f64) {
+        let tick_started = Instant::now();
+        self.end_demo_tick();
         self.handle_events();
-        self.process_debug_messages();
+        self.update_lobby();
+        self.update_heartbeat();
+        self.answer_queries();
+        self.process_log_records();
+        self.update_metrics(tick_started);
     }
 }
 
-#[godot_api]
-impl NetworkDriver {
+// Signals can only be declared in the primary `#[godot_api]` block, so it is
+// assembled here: the hand-written signals plus an `on_server_*` and
+// `on_client_*` pair per registered packet type, e.g. `on_server_chat` and
+// `on_client_chat`, along with the functions that emit them.
+macro_rules! packet_signals {
+    ({ $($signals:tt)* } $($variant:ident: $class:ident),+ $(,)?) => {
+        paste::paste! {
+            #[godot_api]
+            impl NetworkDriver {
+                $($signals)*
+
+                /* typed packet signals, emitted right after the untyped ones */
+                $(
+                    #[signal]
+                    fn [<on_server_ $variant:snake>](peer_id: i64, packet: Gd<$class>);
+                    #[signal]
+                    fn [<on_client_ $variant:snake>](packet: Gd<$class>);
+                )+
+            }
+
+            impl NetworkDriver {
+                /// Emits `on_server_packet` and the packet's typed server signal.
+                /// Returns the packet object they were given.
+                fn emit_server_packet(&mut self, peer_id: PeerId, packet: &Packet, received_us: i64) -> Gd<Object> {
+                    let peer_id = i64::from(peer_id);
+                    match packet {
+                        $(
+                            Packet::$variant(packet) => {
+                                let mut packet = $class::from_wire(packet);
+                                packet.bind_mut().received_us = received_us;
+                                self.signals().on_server_packet().emit(peer_id, &packet.clone().upcast::<Object>());
+                                self.signals().[<on_server_ $variant:snake>]().emit(peer_id, &packet);
+                                packet.upcast()
+                            }
+                        )+
+                    }
+                }
+
+                /// Emits `on_client_packet` and the packet's typed client signal.
+                /// Returns the packet object they were given.
+                fn emit_client_packet(&mut self, packet: &Packet, received_us: i64) -> Gd<Object> {
+                    match packet {
+                        $(
+                            Packet::$variant(packet) => {
+                                let mut packet = $class::from_wire(packet);
+                                packet.bind_mut().received_us = received_us;
+                                self.signals().on_client_packet().emit(&packet.clone().upcast::<Object>());
+                                self.signals().[<on_client_ $variant:snake>]().emit(&packet);
+                                packet.upcast()
+                            }
+                        )+
+                    }
+                }
+            }
+        }
+    };
+}
+
+for_each_packet!(packet_signals! {
     /* server-side signals */
     #[signal]
     fn on_peer_connect(peer_id: i64);
@@ -135,179 +316,995 @@ impl NetworkDriver {
     #[signal]
     fn on_client_packet(packet: Gd<Object>);
 
-    fn _start_server(&mut self, ip_address: IpAddr, port: i64) {
-        self.is_server = true;
+    /* lobby signals */
+    /// On the server when its lobby changes phase, on clients when the
+    /// replicated phase does. `phase` is 0 waiting, 1 countdown, 2 in progress.
+    #[signal]
+    fn on_lobby_phase_changed(phase: i64, countdown_ms: i64);
 
-        // Setup debugging to log everything.
-        // Use function pointer to safely queue messages from GNS thread
-        self.gns_global.utils().enable_debug_output(
-            ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_None,
-            server_debug_callback,
-        );
-
-        self.server = GnsSocket::new(self.gns_global.clone())
-            .listen(ip_address, port.try_into().unwrap())
-            .map_err(|e| {
-                godot_print!("ERROR: Failed to start server: {:#?}", e);
-                panic!("Failed to start server");
-            })
-            .ok();
+    /* diagnostics */
+    /// Every log record that passes its category's level. `level` is 1 error,
+    /// 2 warning, 3 info, 4 verbose, 5 debug; `peer_id` is -1 if none.
+    #[signal]
+    fn on_log(level: i64, category: GString, peer_id: i64, message: GString);
+    /// A call failed; `code` is one of the `ERR_*` constants it also returned.
+    #[signal]
+    fn on_network_error(code: i64, message: GString);
+});
+
+#[godot_api(secondary)]
+impl NetworkDriver {
+    /* error codes returned by calls that can fail */
+    #[constant]
+    const ERR_OK: i64 = NetworkError::OK;
+    #[constant]
+    const ERR_PARSE: i64 = NetworkError::PARSE;
+    #[constant]
+    const ERR_BIND: i64 = NetworkError::BIND;
+    #[constant]
+    const ERR_CONNECT: i64 = NetworkError::CONNECT;
+    #[constant]
+    const ERR_NOT_INITIALIZED: i64 = NetworkError::NOT_INITIALIZED;
+    #[constant]
+    const ERR_SEND: i64 = NetworkError::SEND;
+    #[constant]
+    const ERR_INVALID: i64 = NetworkError::INVALID;
+    #[constant]
+    const ERR_IO: i64 = NetworkError::IO;
+
+    /// Logs a failed call and emits `on_network_error` for it. Returns the
+    /// code handed back to GDScript, `ERR_OK` on success.
+    fn report(&mut self, result: Result<(), NetworkError>) -> i64 {
+        let Err(error) = result else {
+            return NetworkError::OK;
+        };
+        if self.is_server {
+            net_log!(Error, Server, "{}", error);
+        } else {
+            net_log!(Error, Client, "{}", error);
+        }
+        self.signals().on_network_error().emit(error.code(), error.to_string().as_str());
+        error.code()
+    }
+
+    fn _start_server(
+        &mut self,
+        ip_address: IpAddr,
+        port: i64,
+        capacity: usize,
+        reserved_slots: usize,
+    ) -> Result<(), NetworkError> {
+        let port = parse_port(port, "port")?;
+        let peer_slots = PeerSlots::new(capacity, reserved_slots)
+            .map_err(|e| NetworkError::Invalid(format!("invalid server capacity: {e}")))?;
+
+        if self.transport_kind == TransportKind::Gns {
+            self.apply_gns_log_level();
+        }
+
+        // bind first so a failure leaves whatever was running before untouched
+        let addr = SocketAddr::new(ip_address, port);
+        let websocket_addr = SocketAddr::new(ip_address, self.websocket_port);
+        let transport = self
+            .transport_kind
+            .listen_with_websocket(addr, websocket_addr)
+            .map(|transport| Self::maybe_conditioned(transport, self.link_simulation.clone(), u64::from(port)))
+            .and_then(|transport| self.maybe_threaded(transport))
+            .map_err(|e| NetworkError::bind(addr, e))?;
+
+        self.peer_slots = peer_slots;
+        self.connected_clients.clear();
+        self.local_peer = None;
+        self.local_inbox.get_mut().clear();
+        self.local_outbox.get_mut().clear();
+        self.pending_packets.clear();
+        self.player_names.clear();
+        self.lobby = None;
+        *self.metrics.get_mut() = ServerMetrics::default();
+        self.is_server = true;
+        self.server_port = port;
+        self.link_simulation = None;
+        self.transport = Some(transport);
+
+        // the game is up either way, so only log this one
+        if self.query_port != 0 {
+            match QueryResponder::bind(SocketAddr::new(ip_address, self.query_port)) {
+                Ok(responder) => self.query_responder = Some(responder),
+                Err(e) => net_log!(Error, Server, "Failed to open query port {}: {}", self.query_port, e),
+            }
+        }
 
         self.is_connected = true;
+        Ok(())
+    }
+
+    /// Returns `ERR_OK`, or the `ERR_*` code also sent with `on_network_error`.
+    #[func]
+    fn start_server(&mut self, ip_address: String, port: i64) -> i64 {
+        let result = parse_ip(&ip_address)
+            .and_then(|addr| self._start_server(addr, port, DEFAULT_CAPACITY, DEFAULT_RESERVED_SLOTS));
+        self.report(result)
     }
 
+    /// Starts a server accepting `capacity` regular players plus `reserved_slots`
+    /// extra peers that only addresses added with `allow_reserved_slot` may take.
     #[func]
-    fn start_server(&mut self, ip_address: String, port: i64) {
-        let addr = ip_address.parse().unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to parse IP address '{}': {}", ip_address, e);
-            panic!("Invalid IP address");
+    fn start_server_with_capacity(&mut self, ip_address: String, port: i64, capacity: i64, reserved_slots: i64) -> i64 {
+        let result = parse_ip(&ip_address).and_then(|addr| {
+            let (Ok(capacity), Ok(reserved_slots)) = (usize::try_from(capacity), usize::try_from(reserved_slots))
+            else {
+                return Err(NetworkError::Invalid(format!(
+                    "invalid server capacity {capacity} with {reserved_slots} reserved slots"
+                )));
+            };
+            self._start_server(addr, port, capacity, reserved_slots)
         });
-        self._start_server(addr, port);
+        self.report(result)
     }
 
+    /// Starts a server whose host also plays. The local player takes a peer
+    /// id like any remote one, and both the server and client signals fire
+    /// for it with packets routed in memory instead of over the transport.
     #[func]
-    fn start_server_with_port(&mut self, port: i64) {
-        self._start_server(DEFAULT_IP_ADDRESS, port);
+    fn start_listen_server(&mut self, ip_address: String, port: i64) -> i64 {
+        let result = parse_ip(&ip_address).and_then(|addr| self._start_listen_server(addr, port));
+        self.report(result)
     }
 
     #[func]
-    fn start_server_default(&mut self) {
-        self._start_server(DEFAULT_IP_ADDRESS, DEFAULT_PORT);
+    fn start_listen_server_with_port(&mut self, port: i64) -> i64 {
+        let result = self._start_listen_server(DEFAULT_IP_ADDRESS, port);
+        self.report(result)
     }
 
-    fn _start_client(&mut self, ip_address: IpAddr, port: i64) {
-        self.is_server = false;
+    fn _start_listen_server(&mut self, ip_address: IpAddr, port: i64) -> Result<(), NetworkError> {
+        self._start_server(ip_address, port, DEFAULT_CAPACITY, DEFAULT_RESERVED_SLOTS)?;
+
+        // the host always gets in, reserved slot or not
+        let peer_id = self
+            .peer_slots
+            .acquire(true)
+            .ok_or_else(|| NetworkError::Invalid("no peer id left for the local player".to_string()))?;
+        self.local_peer = Some(peer_id);
+        self.local_peer_joined = false;
+        Ok(())
+    }
 
-        // Setup debugging using function pointer to safely queue messages from GNS thread
-        self.gns_global.utils().enable_debug_output(
-            ESteamNetworkingSocketsDebugOutputType::k_ESteamNetworkingSocketsDebugOutputType_None,
-            client_debug_callback,
-        );
+    /// Lists the running server with the master server at `address`
+    /// (`host[:port]`) until it is destroyed. Player count is kept up to date
+    /// automatically.
+    #[func]
+    fn register_with_master(&mut self, address: String, name: String, map: String) -> i64 {
+        let result = self._register_with_master(&address, name, map);
+        self.report(result)
+    }
 
-        self.client = GnsSocket::new(self.gns_global.clone())
-            .connect(ip_address, port.try_into().unwrap())
-            .ok();
+    fn _register_with_master(&mut self, address: &str, name: String, map: String) -> Result<(), NetworkError> {
+        if !self.is_server || self.transport.is_none() {
+            return Err(NetworkError::NotInitialized("server"));
+        }
+        let master =
+            resolve_master(address).ok_or_else(|| NetworkError::Parse(format!("master server address '{address}'")))?;
+
+        self.server_name = name.clone();
+        self.map_name = map.clone();
+        let capacity = u16::try_from(self.peer_slots.capacity()).unwrap_or(u16::MAX);
+        let beacon = Beacon::new(name, map, 0, capacity, self.server_port);
+        let heartbeater = Heartbeater::new(master, beacon).map_err(|e| NetworkError::Connect {
+            addr: master,
+            reason: e.to_string(),
+        })?;
+        net_log!(Info, Server, "Registering with master server {}", master);
+        self.heartbeater = Some(heartbeater);
+        Ok(())
+    }
 
-        self.is_connected = true;
+    #[func]
+    fn unregister_from_master(&mut self) {
+        self.heartbeater = None;
     }
 
+    fn update_heartbeat(&mut self) {
+        let player_count = u16::try_from(self.peer_slots.in_use()).unwrap_or(u16::MAX);
+        let Some(heartbeater) = self.heartbeater.as_mut() else {
+            return;
+        };
+
+        let mut beacon = heartbeater.beacon().clone();
+        beacon.player_count = player_count;
+        heartbeater.set_beacon(beacon);
+        if let Err(e) = heartbeater.update(Instant::now()) {
+            net_log!(Error, Server, "Failed to send master server heartbeat: {}", e);
+        }
+    }
+
+    /// UDP port the next server answers status queries on (see `ServerQuery`).
+    /// 0 turns it off.
+    #[func]
+    fn set_query_port(&mut self, port: i64) -> i64 {
+        let result = parse_port(port, "query port").map(|port| self.query_port = port);
+        self.report(result)
+    }
+
+    /// Name and map reported to status queries and the master server.
     #[func]
-    fn start_client(&mut self, ip_address: String, port: i64) {
-        godot_print!("Starting client to {}:{}", ip_address, port);
-        let addr = ip_address.parse().unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to parse IP address '{}': {}", ip_address, e);
-            panic!("Invalid IP address");
+    fn set_server_info(&mut self, name: String, map: String) {
+        if let Some(heartbeater) = self.heartbeater.as_mut() {
+            let mut beacon = heartbeater.beacon().clone();
+            beacon.name = name.clone();
+            beacon.map = map.clone();
+            heartbeater.set_beacon(beacon);
+        }
+        self.server_name = name;
+        self.map_name = map;
+    }
+
+    /// Free-form match phase reported to status queries, e.g. "lobby".
+    #[func]
+    fn set_match_phase(&mut self, phase: String) {
+        self.match_phase = phase;
+    }
+
+    /// Name status queries list for `peer_id`; forgotten when it disconnects.
+    #[func]
+    fn set_player_name(&mut self, peer_id: i64, name: String) -> i64 {
+        let result = match PeerId::try_from(peer_id) {
+            Ok(peer_id) => {
+                self.player_names.insert(peer_id, name);
+                Ok(())
+            }
+            Err(_) => Err(NetworkError::Parse(format!("peer id {peer_id}"))),
+        };
+        self.report(result)
+    }
+
+    fn answer_queries(&mut self) {
+        let Some(mut responder) = self.query_responder.take() else {
+            return;
+        };
+        responder.poll(Instant::now(), || {
+            let players = self
+                .connected_clients
+                .values()
+                .chain(self.local_peer.as_ref())
+                .map(|&id| QueryPlayer { id, name: self.player_names.get(&id).cloned().unwrap_or_default() })
+                .collect();
+            let capacity = u16::try_from(self.peer_slots.capacity()).unwrap_or(u16::MAX);
+            ServerStatus::new(
+                self.server_name.clone(),
+                self.map_name.clone(),
+                self.match_phase.clone(),
+                capacity,
+                players,
+            )
         });
-        self._start_client(addr, port);
+        self.query_responder = Some(responder);
+    }
+
+    /// Holds the running server in a pre-match lobby: the countdown starts
+    /// once `min_players` peers are ready and the match starts when it runs
+    /// out. Everyone already connected joins it, up to `max_players`.
+    #[func]
+    fn enable_lobby(&mut self, min_players: i64, max_players: i64, countdown_secs: f64) -> i64 {
+        let result = self._enable_lobby(min_players, max_players, countdown_secs);
+        self.report(result)
+    }
+
+    fn _enable_lobby(&mut self, min_players: i64, max_players: i64, countdown_secs: f64) -> Result<(), NetworkError> {
+        if !self.is_server || self.transport.is_none() {
+            return Err(NetworkError::NotInitialized("server"));
+        }
+        let (Ok(min_players), Ok(max_players)) = (u16::try_from(min_players), u16::try_from(max_players)) else {
+            return Err(NetworkError::Invalid(format!("invalid lobby player range {min_players}..={max_players}")));
+        };
+        let countdown = Duration::try_from_secs_f64(countdown_secs)
+            .map_err(|_| NetworkError::Invalid(format!("invalid lobby countdown {countdown_secs}")))?;
+
+        let mut lobby = Lobby::new(LobbySettings { min_players, max_players, countdown })
+            .map_err(|e| NetworkError::Invalid(format!("invalid lobby settings: {e}")))?;
+        let joined = self.local_peer.filter(|_| self.local_peer_joined);
+        for &peer_id in self.connected_clients.values().chain(joined.as_ref()) {
+            lobby.join(peer_id);
+        }
+        self.match_phase = lobby.phase().name().to_string();
+        self.lobby = Some(lobby);
+        Ok(())
+    }
+
+    /// Drops the lobby; clients keep the last phase they were sent.
+    #[func]
+    fn disable_lobby(&mut self) {
+        self.lobby = None;
     }
 
+    /// Marks `peer_id` ready or not on the server, e.g. for bots or an admin
+    /// forcing a start.
     #[func]
-    fn start_client_with_port(&mut self, port: i64) {
-        self._start_client(DEFAULT_IP_ADDRESS, port);
+    fn set_peer_ready(&mut self, peer_id: i64, ready: bool) {
+        if let (Some(lobby), Ok(peer_id)) = (self.lobby.as_mut(), PeerId::try_from(peer_id)) {
+            lobby.set_ready(peer_id, ready);
+        }
     }
 
+    /// Tells the server this client is ready, or no longer ready.
     #[func]
-    fn start_client_default(&mut self) {
-        self._start_client(DEFAULT_IP_ADDRESS, DEFAULT_PORT);
+    fn set_ready(&mut self, ready: bool) -> i64 {
+        let result = self._send_packet(&Packet::LobbyReady(LobbyReadyPacketWire { ready }));
+        self.report(result)
+    }
+
+    /// Current lobby phase (0 waiting, 1 countdown, 2 in progress), or -1
+    /// without a lobby.
+    #[func]
+    fn lobby_phase(&self) -> i64 {
+        if self.is_server {
+            lobby_phase_id(self.lobby.as_ref().map(Lobby::phase))
+        } else {
+            lobby_phase_id(self.client_lobby_phase)
+        }
+    }
+
+    fn update_lobby(&mut self) {
+        let now = Instant::now();
+        let Some(lobby) = self.lobby.as_mut() else {
+            return;
+        };
+        let phase_change = lobby.update(now);
+        if !lobby.take_changed() {
+            return;
+        }
+        let state = lobby.state(now);
+        let countdown_ms = i64::from(state.countdown_ms);
+
+        if self.transport.is_some() {
+            let result = self._broadcast_packet(&Packet::LobbyState(state));
+            self.report(result);
+        }
+        if let Some(phase) = phase_change {
+            net_log!(Info, Server, "Lobby is now {}", phase.name());
+            self.match_phase = phase.name().to_string();
+            self.signals().on_lobby_phase_changed().emit(lobby_phase_id(Some(phase)), countdown_ms);
+        }
+    }
+
+    /// Serves Prometheus metrics at `http://127.0.0.1:<port>/metrics` until
+    /// the server is destroyed.
+    #[func]
+    fn start_metrics(&mut self, port: i64) -> i64 {
+        let result = parse_port(port, "metrics port").and_then(|port| {
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+            let endpoint = MetricsEndpoint::bind(addr).map_err(|e| NetworkError::bind(addr, e))?;
+            net_log!(Info, Server, "Serving metrics on http://{}/metrics", addr);
+            self.metrics_endpoint = Some(endpoint);
+            Ok(())
+        });
+        self.report(result)
+    }
+
+    #[func]
+    fn stop_metrics(&mut self) {
+        self.metrics_endpoint = None;
+    }
+
+    fn update_metrics(&mut self, tick_started: Instant) {
+        if !self.is_server {
+            return;
+        }
+        let now = Instant::now();
+        let metrics = self.metrics.get_mut();
+        metrics.observe_tick(now.duration_since(tick_started));
+        metrics.set_connected_peers(self.connected_clients.len() + usize::from(self.local_peer_joined));
+
+        if now.duration_since(self.last_ping_sample) >= PING_SAMPLE_INTERVAL {
+            self.last_ping_sample = now;
+            if let Some(transport) = self.transport.as_ref() {
+                for (connection, peer_id) in &self.connected_clients {
+                    if let Some(ping_ms) = transport.ping_ms(*connection) {
+                        metrics.observe_ping(*peer_id, ping_ms);
+                    }
+                }
+            }
+        }
+
+        if let Some(endpoint) = self.metrics_endpoint.as_mut() {
+            endpoint.poll(now, || metrics.render());
+        }
+    }
+
+    /// Peer id of the host's own player, or -1 when not a listen server.
+    #[func]
+    fn local_peer_id(&self) -> i64 {
+        self.local_peer.map_or(-1, i64::from)
+    }
+
+    #[func]
+    fn is_listen_server(&self) -> bool {
+        self.local_peer.is_some()
+    }
+
+    #[func]
+    fn start_server_with_port(&mut self, port: i64) -> i64 {
+        let result = self._start_server(DEFAULT_IP_ADDRESS, port, DEFAULT_CAPACITY, DEFAULT_RESERVED_SLOTS);
+        self.report(result)
+    }
+
+    #[func]
+    fn start_server_default(&mut self) -> i64 {
+        let result = self._start_server(DEFAULT_IP_ADDRESS, DEFAULT_PORT, DEFAULT_CAPACITY, DEFAULT_RESERVED_SLOTS);
+        self.report(result)
+    }
+
+    /// Loads the dedicated server config and starts the server with it.
+    ///
+    /// `path` may be empty, in which case `--config=<path>` from the user command
+    /// line is used if present. Environment and command-line overrides are
+    /// always applied on top. Returns `ERR_INVALID` if the config is invalid,
+    /// or the code `start_server` would for a server that fails to start.
+    #[func]
+    fn start_server_from_config(&mut self, path: String) -> i64 {
+        let args: Vec<String> = Os::singleton()
+            .get_cmdline_user_args()
+            .as_slice()
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let path = if path.is_empty() {
+            ServerConfig::config_path_from_args(&args)
+        } else {
+            Some(path)
+        };
+        let path = path.map(|path| ProjectSettings::singleton().globalize_path(&path).to_string());
+
+        let result = ServerConfig::load(path.as_deref().map(Path::new), std::env::vars(), &args)
+            .map_err(|e| NetworkError::Invalid(format!("invalid server config: {e}")))
+            .and_then(|config| self.apply_config(&config));
+        self.report(result)
+    }
+
+    /// Stops at the first setting that can't be applied. Metrics, the lobby
+    /// and master server registration come after the server started; they
+    /// are reported but don't fail the whole config.
+    fn apply_config(&mut self, config: &ServerConfig) -> Result<(), NetworkError> {
+        self.apply_log_config(config);
+        net_log!(Info, Server, "Starting server from config: {:#?}", config);
+
+        self.max_messages_per_poll = config.max_messages_per_poll;
+        self.poll_time_budget = Duration::from_millis(config.poll_time_budget_ms);
+        self.network_thread = config.network_thread;
+        self.send_rate = i64::from(config.send_rate);
+        Engine::singleton().set_physics_ticks_per_second(config.tick_rate as i32);
+        self._set_transport(&config.transport)?;
+        self.websocket_port = config.websocket_port;
+        self.query_port = config.query_port;
+        self.set_server_info(config.server_name.clone(), config.map.clone());
+
+        // GNS simulates the link itself, other backends are wrapped in a ConditionedTransport
+        let simulate_link = config.link != LinkSimulationConfig::default();
+        if simulate_link && self.transport_kind != TransportKind::Gns {
+            self.link_simulation = Some(config.link.clone());
+        }
+
+        self._start_server(config.bind_address, i64::from(config.port), config.capacity, config.reserved_slots)?;
+
+        if simulate_link && self.transport_kind == TransportKind::Gns {
+            let link = &config.link;
+            for (key, value) in [
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLag_Send, link.lag_send_ms),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLag_Recv, link.lag_recv_ms),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLoss_Send, link.loss_send_pct),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLoss_Recv, link.loss_recv_pct),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketJitter_Send_Avg, link.jitter_send_ms),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketJitter_Recv_Avg, link.jitter_recv_ms),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_Send, link.dup_send_pct),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_Recv, link.dup_recv_pct),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_TimeMax, link.dup_ms_max),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Send, link.reorder_send_pct),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Recv, link.reorder_recv_pct),
+                (ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Time, link.reorder_ms),
+            ] {
+                self._set_gns_config(key, i64::from(value))?;
+            }
+        }
+
+        if config.metrics_port != 0 {
+            self.start_metrics(i64::from(config.metrics_port));
+        }
+
+        if config.lobby_min_players != 0 {
+            let max_players = i64::try_from(config.capacity).unwrap_or(i64::MAX);
+            self.enable_lobby(
+                i64::from(config.lobby_min_players),
+                max_players,
+                f64::from(config.lobby_countdown_secs),
+            );
+        }
+
+        if !config.master_server.is_empty() {
+            self.register_with_master(config.master_server.clone(), config.server_name.clone(), config.map.clone());
+        }
+        Ok(())
+    }
+
+    /// Lets connections from `ip_address` use the reserved admin/spectator slots.
+    #[func]
+    fn allow_reserved_slot(&mut self, ip_address: String) -> i64 {
+        let result = parse_ip(&ip_address).map(|addr| {
+            self.reserved_addresses.insert(addr);
+        });
+        self.report(result)
+    }
+
+    #[func]
+    fn capacity(&self) -> i64 {
+        self.peer_slots.capacity() as i64
+    }
+
+    #[func]
+    fn reserved_slots(&self) -> i64 {
+        self.peer_slots.reserved() as i64
+    }
+
+    #[func]
+    fn peer_count(&self) -> i64 {
+        self.peer_slots.in_use() as i64
+    }
+
+    /// Picks the backend the next `start_server*`/`start_client*` call uses:
+    /// "gns" (default), "udp", "websocket" or "loopback" for an in-process
+    /// server and client.
+    #[func]
+    fn set_transport(&mut self, name: String) -> i64 {
+        let result = self._set_transport(&name);
+        self.report(result)
+    }
+
+    fn _set_transport(&mut self, name: &str) -> Result<(), NetworkError> {
+        if self.transport.is_some() {
+            return Err(NetworkError::Invalid("cannot change transport while connected".to_string()));
+        }
+        self.transport_kind = name.parse().map_err(NetworkError::Parse)?;
+        Ok(())
+    }
+
+    #[func]
+    fn transport_name(&self) -> String {
+        self.transport_kind.name().to_string()
+    }
+
+    /// TCP port the next server also accepts WebSocket clients on, alongside
+    /// its main transport, so web builds can join. 0 turns it off.
+    #[func]
+    fn set_websocket_port(&mut self, port: i64) -> i64 {
+        let result = parse_port(port, "WebSocket port").map(|port| self.websocket_port = port);
+        self.report(result)
+    }
+
+    fn _start_client(&mut self, ip_address: IpAddr, port: i64) -> Result<(), NetworkError> {
+        let port = parse_port(port, "port")?;
+        self.is_server = false;
+        self.client_lobby_phase = None;
+        self.pending_packets.clear();
+
+        if self.transport_kind == TransportKind::Gns {
+            self.apply_gns_log_level();
+        }
+
+        let addr = SocketAddr::new(ip_address, port);
+        let transport = self
+            .transport_kind
+            .connect(addr)
+            .and_then(|transport| self.maybe_threaded(transport))
+            .map_err(|e| NetworkError::connect(addr, e))?;
+        self.transport = Some(transport);
+        self.is_connected = true;
+        Ok(())
+    }
+
+    /// Puts a simulated `link` in front of `transport`, seeded so the same
+    /// config and traffic impair the same messages.
+    fn maybe_conditioned(
+        transport: Box<dyn Transport>,
+        link: Option<LinkSimulationConfig>,
+        seed: u64,
+    ) -> Box<dyn Transport> {
+        let Some(link) = link else {
+            return transport;
+        };
+        net_log!(Info, Server, "Simulating link: {:?}", link);
+        Box::new(ConditionedTransport::new(transport, LinkProfile::send(&link), LinkProfile::recv(&link), seed))
+    }
+
+    /// Hands `transport` to a network thread if `network_thread` is set.
+    fn maybe_threaded(&self, transport: Box<dyn Transport>) -> Result<Box<dyn Transport>, TransportError> {
+        if !self.network_thread {
+            return Ok(transport);
+        }
+        Ok(Box::new(ThreadedTransport::spawn(transport)?))
+    }
+
+    /// Returns `ERR_OK`, or the `ERR_*` code also sent with `on_network_error`.
+    #[func]
+    fn start_client(&mut self, ip_address: String, port: i64) -> i64 {
+        net_log!(Info, Client, "Starting client to {}:{}", ip_address, port);
+        let result = parse_ip(&ip_address).and_then(|addr| self._start_client(addr, port));
+        self.report(result)
+    }
+
+    #[func]
+    fn start_client_with_port(&mut self, port: i64) -> i64 {
+        let result = self._start_client(DEFAULT_IP_ADDRESS, port);
+        self.report(result)
+    }
+
+    #[func]
+    fn start_client_default(&mut self) -> i64 {
+        let result = self._start_client(DEFAULT_IP_ADDRESS, DEFAULT_PORT);
+        self.report(result)
+    }
+
+    fn _register_handler(&mut self, packet_class_name: &str, callable: Callable, priority: i64) -> Result<(), NetworkError> {
+        let id = packet_id_of_class(packet_class_name)
+            .ok_or_else(|| NetworkError::Invalid(format!("unknown packet class '{packet_class_name}'")))?;
+        if !callable.is_valid() {
+            return Err(NetworkError::Invalid(format!("invalid handler for {packet_class_name}")));
+        }
+        self.packet_handlers.register(id, callable, priority);
+        Ok(())
+    }
+
+    /// Routes every received `packet_class_name` packet, e.g. "ChatPacket",
+    /// to `callable(peer_id, packet)` once its signals have been emitted.
+    /// `peer_id` is -1 for packets from the server. Handlers run highest
+    /// priority first; one that returns true stops the rest from seeing the
+    /// packet. Registering a handler again only changes its priority.
+    #[func]
+    fn register_handler_with_priority(&mut self, packet_class_name: String, callable: Callable, priority: i64) -> i64 {
+        let result = self._register_handler(&packet_class_name, callable, priority);
+        self.report(result)
+    }
+
+    #[func]
+    fn register_handler(&mut self, packet_class_name: String, callable: Callable) -> i64 {
+        let result = self._register_handler(&packet_class_name, callable, 0);
+        self.report(result)
+    }
+
+    #[func]
+    fn unregister_handler(&mut self, packet_class_name: String, callable: Callable) -> i64 {
+        let result = packet_id_of_class(&packet_class_name)
+            .ok_or_else(|| NetworkError::Invalid(format!("unknown packet class '{packet_class_name}'")))
+            .and_then(|id| {
+                if self.packet_handlers.unregister(id, &callable) {
+                    Ok(())
+                } else {
+                    Err(NetworkError::Invalid(format!("handler is not registered for {packet_class_name}")))
+                }
+            });
+        self.report(result)
+    }
+
+    /// `packet_class_name` packets received with no handler while others
+    /// had one, or -1 for an unknown class.
+    #[func]
+    fn unhandled_packet_count(&self, packet_class_name: String) -> i64 {
+        packet_id_of_class(&packet_class_name)
+            .map_or(-1, |id| i64::try_from(self.packet_handlers.unhandled_count(id)).unwrap_or(i64::MAX))
+    }
+
+    /// Packets received since the last call while `batch_packets` is set,
+    /// grouped by type. `peer_id` columns hold the sender on the server and
+    /// -1 for packets from the server, so a listen-server host gets both in
+    /// one batch. The same batch object comes back every call, overwritten.
+    #[func]
+    fn drain_packets(&mut self) -> Gd<PacketBatch> {
+        let batch = self.packet_batch.get_or_insert_with(PacketBatch::empty);
+        batch.bind_mut().fill(self.pending_packets.make_contiguous());
+        self.pending_packets.clear();
+        let dropped = std::mem::take(&mut self.pending_packets_dropped);
+        if dropped > 0 {
+            net_log!(Warning, Packet, "Dropped {} batched packets that were not drained in time", dropped);
+        }
+        batch.clone()
     }
 
     #[func]
     fn disconnect_client(&mut self) {
-        self.client = None;
+        self.transport = None;
         self.is_connected = false;
         self.signals().on_disconnect_from_server().emit(1000);
     }
 
+    /// Stops the server, emitting `on_peer_disconnect` for every peer that
+    /// had joined, the local one included, as if each had left.
     #[func]
     fn destroy_server(&mut self) {
-        self.server = None;
+        self.stop_demo();
+        self.heartbeater = None;
+        self.query_responder = None;
+        self.lobby = None;
+        self.metrics_endpoint = None;
+        // dropping the transport closes every connection
+        self.transport = None;
         self.is_connected = false;
+        self.pending_packets.clear();
+
+        let mut departed: Vec<PeerId> = self.connected_clients.drain().map(|(_, peer_id)| peer_id).collect();
+        let local_joined = std::mem::take(&mut self.local_peer_joined);
+        if let Some(peer_id) = self.local_peer.take() {
+            self.local_inbox.get_mut().clear();
+            self.local_outbox.get_mut().clear();
+            if local_joined {
+                departed.push(peer_id);
+            } else {
+                self.peer_slots.release(peer_id);
+            }
+        }
+
+        for peer_id in departed {
+            self.signals().on_peer_disconnect().emit(i64::from(peer_id));
+            self.peer_slots.release(peer_id);
+            self.player_names.remove(&peer_id);
+            self.metrics.get_mut().forget_peer(peer_id);
+        }
+        if local_joined {
+            self.signals().on_disconnect_from_server().emit(1000);
+        }
     }
 
-    fn _send_packet(&self, packet: &Packet) {
+    fn _send_packet(&self, packet: &Packet) -> Result<(), NetworkError> {
         if self.is_server {
-            return;
+            if self.local_peer.is_some() {
+                self.local_inbox.borrow_mut().push_back(packet.clone());
+            }
+            return Ok(());
+        }
+
+        let client = self.transport.as_ref().ok_or(NetworkError::NotInitialized("client"))?;
+        let server_connection =
+            client.server_connection().ok_or_else(|| NetworkError::Send("not connected to a server".to_string()))?;
+
+        let payload = packet.encode();
+        self.record(Direction::Sent, 0, packet.is_reliable(), &payload);
+        client.send(server_connection, packet.is_reliable(), &payload);
+        Ok(())
+    }
+
+    #[func]
+    fn send_packet(&mut self, packet: Gd<GdPacket>) -> i64 {
+        let result = self._send_packet(&packet.bind().packet);
+        self.report(result)
+    }
+
+    fn _broadcast_packet(&self, packet: &Packet) -> Result<(), NetworkError> {
+        if !self.is_server {
+            return Err(NetworkError::Send("only the server broadcasts".to_string()));
+        }
+        let server = self.transport.as_ref().ok_or(NetworkError::NotInitialized("server"))?;
+
+        if let Some(demo) = self.demo.borrow_mut().as_mut() {
+            demo.record(packet);
+        }
+        if self.local_peer.is_some() {
+            self.local_outbox.borrow_mut().push_back(packet.clone());
         }
 
-        let client = self.client.as_ref().unwrap_or_else(|| {
-            godot_print!("ERROR: Client not initialized");
-            panic!("Client socket not initialized");
+        let payload = packet.encode();
+        for peer_id in self.connected_clients.values() {
+            self.record(Direction::Sent, *peer_id, packet.is_reliable(), &payload);
+        }
+        let connections = self.connected_clients.keys().copied().collect::<Vec<_>>();
+        server.broadcast(&connections, packet.is_reliable(), &payload);
+        Ok(())
+    }
+
+    #[func]
+    fn broadcast_packet(&mut self, packet: Gd<GdPacket>) -> i64 {
+        let result = self._broadcast_packet(&packet.bind().packet);
+        self.report(result)
+    }
+
+    /// Starts capturing every message sent and received to `path`, replacing
+    /// any capture already in progress. Read captures back with
+    /// `br_core::capture::CaptureReader`.
+    #[func]
+    fn start_recording(&mut self, path: String) -> i64 {
+        let path = ProjectSettings::singleton().globalize_path(&path).to_string();
+        let role = if self.is_server { CaptureRole::Server } else { CaptureRole::Client };
+        let result = CaptureWriter::create(&path, role).map_err(|e| NetworkError::io(&path, e)).map(|writer| {
+            self.stop_recording();
+            *self.recording.borrow_mut() = Some(Recording { writer, started: Instant::now() });
+            net_log!(Info, Packet, "Recording network traffic to {}", path);
         });
+        self.report(result)
+    }
 
-        client.send_messages(vec![self.gns_global.utils().allocate_message(
-            client.connection(),
-            if packet.is_reliable() {
-                k_nSteamNetworkingSend_Reliable
-            } else {
-                k_nSteamNetworkingSend_Unreliable
-            },
-            packet.encode().as_slice(),
-        )]);
+    #[func]
+    fn stop_recording(&mut self) {
+        let Some(mut recording) = self.recording.borrow_mut().take() else {
+            return;
+        };
+        if let Err(e) = recording.writer.flush() {
+            net_log!(Error, Packet, "Failed to flush traffic capture: {}", e);
+        }
+    }
+
+    #[func]
+    fn is_recording(&self) -> bool {
+        self.recording.borrow().is_some()
     }
 
+    /// Starts writing a match demo of everything broadcast from now on, with
+    /// a keyframe every `keyframe_interval` ticks (0 picks a few seconds'
+    /// worth). Server only; play it back with `DemoPlayer`.
     #[func]
-    fn send_packet(&self, packet: Gd<GdPacket>) {
-        self._send_packet(&packet.bind().packet);
+    fn start_demo(&mut self, path: String, keyframe_interval: i64) -> i64 {
+        let result = self._start_demo(path, keyframe_interval);
+        self.report(result)
     }
 
-    fn _broadcast_packet(&self, packet: &Packet) {
+    fn _start_demo(&mut self, path: String, keyframe_interval: i64) -> Result<(), NetworkError> {
         if !self.is_server {
+            return Err(NetworkError::NotInitialized("server"));
+        }
+
+        let tick_rate = Engine::singleton().get_physics_ticks_per_second() as u32;
+        let keyframe_interval = match u32::try_from(keyframe_interval) {
+            Ok(0) => tick_rate * DEFAULT_DEMO_KEYFRAME_SECONDS,
+            Ok(interval) => interval,
+            Err(_) => {
+                return Err(NetworkError::Invalid(format!("invalid demo keyframe interval {keyframe_interval}")));
+            }
+        };
+
+        let path = ProjectSettings::singleton().globalize_path(&path).to_string();
+        let writer = DemoWriter::create(&path, tick_rate, keyframe_interval).map_err(|e| NetworkError::io(&path, e))?;
+        self.stop_demo();
+        *self.demo.get_mut() = Some(writer);
+        self.demo_tick = 0;
+        net_log!(Info, Packet, "Recording demo to {}", path);
+        Ok(())
+    }
+
+    #[func]
+    fn stop_demo(&mut self) {
+        let Some(mut demo) = self.demo.get_mut().take() else {
             return;
+        };
+        // close out broadcasts made since the last physics frame
+        let result = demo.end_tick(self.demo_tick).and_then(|_| demo.flush());
+        if let Err(e) = result {
+            net_log!(Error, Packet, "Failed to finish demo: {}", e);
         }
+    }
 
-        let server = self.server.as_ref().unwrap_or_else(|| {
-            godot_print!("ERROR: Server not initialized");
-            panic!("Server socket not initialized");
-        });
+    #[func]
+    fn is_recording_demo(&self) -> bool {
+        self.demo.borrow().is_some()
+    }
 
-        let messages = self
-            .connected_clients
-            .keys()
-            .clone()
-            .into_iter()
-            .map(|client| {
-                self.gns_global.utils().allocate_message(
-                    *client,
-                    if packet.is_reliable() {
-                        k_nSteamNetworkingSend_Reliable
-                    } else {
-                        k_nSteamNetworkingSend_Unreliable
-                    },
-                    packet.encode().as_slice(),
-                )
-            })
-            .collect::<Vec<_>>();
-
-        server.send_messages(messages);
-    }
-
-    #[func]
-    fn broadcast_packet(&self, packet: Gd<GdPacket>) {
-        self._broadcast_packet(&packet.bind().packet);
-    }
-
-    fn process_debug_messages(&mut self) {
-        if let Ok(mut queue) = self.debug_messages.lock() {
-            // Process up to 10 messages per frame to avoid blocking
-            for _ in 0..10 {
-                if let Some(message) = queue.pop_front() {
-                    godot_print!("{}", message);
-                } else {
-                    break;
-                }
+    /// Gameplay broadcasts from its own physics frame, after ours has run, so
+    /// each tick's packets are written at the start of the next one.
+    fn end_demo_tick(&mut self) {
+        let Some(demo) = self.demo.get_mut().as_mut() else {
+            return;
+        };
+
+        if let Err(e) = demo.end_tick(self.demo_tick) {
+            net_log!(Error, Packet, "Stopped recording demo: {}", e);
+            *self.demo.get_mut() = None;
+            return;
+        }
+        self.demo_tick = self.demo_tick.wrapping_add(1);
+    }
+
+    /// Counts a message for the metrics and writes it to the traffic capture,
+    /// if one is running.
+    fn record(&self, direction: Direction, peer_id: PeerId, reliable: bool, payload: &[u8]) {
+        self.metrics.borrow_mut().count_message(direction, payload);
+
+        let mut recording = self.recording.borrow_mut();
+        let Some(active) = recording.as_mut() else {
+            return;
+        };
+
+        let timestamp_us = active.started.elapsed().as_micros() as u64;
+        if let Err(e) = active.writer.write(timestamp_us, direction, peer_id, reliable, payload) {
+            // a failing disk shouldn't take the session down with it
+            net_log!(Error, Packet, "Stopped recording network traffic: {}", e);
+            *recording = None;
+        }
+    }
+
+    /// Hands every queued log record to Godot.
+    fn process_log_records(&mut self) {
+        let (records, dropped) = logger().drain();
+        if dropped > 0 {
+            let message = format!("Dropped {dropped} log records that were not drained in time");
+            godot_print!("[{}] WARNING: {}", LogCategory::Server.name(), message);
+            self.signals().on_log().emit(LogLevel::Warning as i64, LogCategory::Server.name(), -1, message.as_str());
+        }
+        for record in records {
+            if self.print_logs {
+                godot_print!("{}", record);
             }
+            self.signals().on_log().emit(
+                record.level as i64,
+                record.category.name(),
+                record.peer_id.map_or(-1, i64::from),
+                record.message.as_str(),
+            );
         }
     }
 
-    fn queue_debug(&self, message: String) {
-        if let Ok(mut queue) = self.debug_messages.lock() {
-            queue.push_back(message);
-            if queue.len() > 1000 {
-                queue.pop_front();
+    /// Sets the level of one log category ("gns", "server", "client",
+    /// "packet") or of all of them ("all"). Levels are "off", "error",
+    /// "warning", "info", "verbose", "debug" and "everything".
+    #[func]
+    fn set_log_level(&mut self, category: String, level: String) -> i64 {
+        let parsed = level.parse::<LogLevel>().and_then(|level| {
+            let categories = if category.eq_ignore_ascii_case("all") {
+                LogCategory::ALL.to_vec()
+            } else {
+                vec![category.parse::<LogCategory>()?]
+            };
+            Ok((level, categories))
+        });
+        let result = parsed.map_err(NetworkError::Parse).map(|(level, categories)| {
+            for category in categories {
+                logger().set_level(category, level);
             }
+            self.apply_gns_log_level();
+        });
+        self.report(result)
+    }
+
+    #[func]
+    fn log_level(&self, category: String) -> String {
+        category.parse::<LogCategory>().map_or_else(|_| String::new(), |category| logger().level(category).name().to_string())
+    }
+
+    /// Also writes every log record to `path`, keeping `keep` older files
+    /// once it grows past `max_kb`. Replaces any log file already open.
+    #[func]
+    fn open_log_file(&mut self, path: String, max_kb: i64, keep: i64) -> i64 {
+        let path = ProjectSettings::singleton().globalize_path(&path).to_string();
+        let result = match (u64::try_from(max_kb), u32::try_from(keep)) {
+            (Ok(max_kb), Ok(keep)) => RotatingLogFile::open(&path, max_kb.saturating_mul(1024), keep)
+                .map(|file| {
+                    logger().set_file(Some(file));
+                    net_log!(Info, Server, "Logging to {}", path);
+                })
+                .map_err(|e| NetworkError::io(&path, e)),
+            _ => Err(NetworkError::Invalid(format!("invalid log file size {max_kb} KiB or count {keep}"))),
+        };
+        self.report(result)
+    }
+
+    #[func]
+    fn close_log_file(&mut self) {
+        logger().set_file(None);
+    }
+
+    fn apply_log_config(&mut self, config: &ServerConfig) {
+        for category in LogCategory::ALL {
+            logger().set_level(category, config.log.level(category, config.log_level));
+        }
+        if !config.log.file.is_empty() {
+            let keep = i64::from(config.log.file_keep);
+            let max_kb = i64::try_from(config.log.file_max_kb).unwrap_or(i64::MAX);
+            self.open_log_file(config.log.file.clone(), max_kb, keep);
         }
     }
 
+    /// GNS filters its own debug output, so it has to be told the level.
+    fn apply_gns_log_level(&self) {
+        let Some(gns_global) = self.gns_global.as_ref() else {
+            return;
+        };
+        let level = gns_debug_level(logger().level(LogCategory::Gns));
+        gns_global.utils().enable_debug_output(level, gns_debug_callback);
+    }
+
     fn handle_events(&mut self) {
         if !self.is_connected {
             return;
@@ -321,94 +1318,105 @@ impl NetworkDriver {
     }
 
     fn handle_client_events(&mut self) {
-        self.gns_global.poll_callbacks();
+        let Some(mut client) = self.transport.take() else {
+            let result = Err(NetworkError::NotInitialized("client"));
+            self.is_connected = false;
+            self.report(result);
+            return;
+        };
 
-        let client = self.client.as_ref().unwrap_or_else(|| {
-            godot_print!("ERROR: Client not initialized");
-            panic!("Client socket not initialized");
-        });
+        client.update();
 
-        self.client_ping = client.get_connection_real_time_status(client.connection(), 0).and_then(|(status, _)| Ok(status.ping())).unwrap_or(0) as i64;
+        self.client_ping = client
+            .server_connection()
+            .and_then(|connection| client.ping_ms(connection))
+            .unwrap_or(0) as i64;
 
         let mut packets_to_emit = Vec::new();
+        let mut lobby_states = Vec::new();
 
-        let poll_deadline = Instant::now() + Duration::from_millis(POLL_TIME_BUDGET_MS);
+        let poll_deadline = Instant::now() + self.poll_time_budget;
+        let mut messages_processed = 0;
+        let mut emit_disconnect = -1;
+        let mut emit_connect = false;
+        // messages and events are polled in turn, so either is seen in the order it arrived
         loop {
-            let processed = client.poll_messages::<MAX_MESSAGES_PER_POLL>(|message| {
-                let packet = Packet::decode(message.payload());
+            let processed_count = client.poll_packets(&mut |_, reliable, payload, packet| {
+                self.record(Direction::Received, 0, reliable, payload);
+                let received_us = ticks_usec();
 
                 match packet {
                     Ok(packet) => {
-                        packets_to_emit.push(packet.as_gd());
+                        if let Packet::LobbyState(state) = &packet {
+                            lobby_states.push((state.phase, state.countdown_ms));
+                        }
+                        packets_to_emit.push((received_us, packet));
                     }
                     Err(e) => {
-                        self.queue_debug(format!(
-                            "ERROR: Failed to decode packet: {}, raw data {:x?}",
-                            e,
-                            &message.payload()
-                        ));
+                        self.metrics.borrow_mut().count_decode_failure();
+                        net_log!(Warning, Packet, "Failed to decode packet: {}, raw data {:x?}", e, payload);
                     }
                 }
             });
-            let processed_count = processed.unwrap_or(0);
+            messages_processed += processed_count;
 
-            if processed_count == 0 || Instant::now() >= poll_deadline {
-                break;
-            }
-        }
+            let events_processed = client.poll_events(&mut |event| match event {
+                TransportEvent::Connecting { .. } => {
+                    net_log!(Verbose, Client, "Connecting to server");
+                }
+                TransportEvent::Connected { .. } => {
+                    net_log!(Info, Client, "Connected to server");
+                    emit_connect = true;
+                }
+                TransportEvent::Disconnected { reason, .. } => {
+                    // We got disconnected or lost the connection.
+                    net_log!(Info, Client, "Disconnected from server with reason {}", reason);
+                    emit_disconnect = i64::from(reason);
+                }
+            });
 
-        let mut emit_disconnect = -1;
-        let mut emit_connect = false;
-        loop {
-            let processed = client.poll_event::<MAX_EVENTS_PER_POLL>(|event| match (event.old_state(), event.info().state()) {
-            (
-                ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_None,
-                ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connecting,
-            ) => {
-                self.queue_debug("GnsSocket<Client>: connecting to server.".to_string());
-            }
-            (
-                ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connecting,
-                ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connected,
-            ) => {
-                self.queue_debug("GnsSocket<Client>: connected to server.".to_string());
-                emit_connect = true;
-            }
-            (_, ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_ClosedByPeer | ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_ProblemDetectedLocally) => {
-                // We got disconnected or lost the connection.
-                self.queue_debug("GnsSocket<Client>: ET phone home.".to_string());
-                emit_disconnect = event.info().end_reason() as i64;
-            }
-            (previous, current) => {
-                self.queue_debug(format!("GnsSocket<Client>: {:#?} => {:#?}.", previous, current));
+            if processed_count == 0 && events_processed == 0 {
+                break;
             }
-        });
-
-            if processed == 0 || Instant::now() >= poll_deadline {
+            if messages_processed >= self.max_messages_per_poll || Instant::now() >= poll_deadline {
+                self.metrics.get_mut().count_poll_budget_overrun();
                 break;
             }
         }
 
-        if emit_disconnect != -1 {
-            self.is_connected = false;
-            self.signals().on_disconnect_from_server().emit(emit_disconnect);
-            self.client = None;
-        }
+        self.transport = Some(client);
 
         if emit_connect {
             self.signals().on_connect_to_server().emit();
         }
 
-        for packet in packets_to_emit {
-            self.signals().on_client_packet().emit(&packet);
+        for (phase, countdown_ms) in lobby_states {
+            let phase = LobbyPhase::from_u8(phase);
+            if phase.is_some() && phase != self.client_lobby_phase {
+                self.client_lobby_phase = phase;
+                self.signals().on_lobby_phase_changed().emit(lobby_phase_id(phase), i64::from(countdown_ms));
+            }
+        }
+
+        for (received_us, packet) in packets_to_emit {
+            self.deliver_client_packet(packet, received_us);
+        }
+
+        // the server's last packets come before the disconnect
+        if emit_disconnect != -1 {
+            self.is_connected = false;
+            self.signals().on_disconnect_from_server().emit(emit_disconnect);
+            self.transport = None;
         }
     }
 
     fn handle_server_events(&mut self) {
-        let server = self.server.take().unwrap_or_else(|| {
-            godot_print!("ERROR: Server not initialized");
-            panic!("Server socket not initialized");
-        });
+        let Some(mut server) = self.transport.take() else {
+            let result = Err(NetworkError::NotInitialized("server"));
+            self.is_connected = false;
+            self.report(result);
+            return;
+        };
 
         // let now = Instant::now();
         // let elapsed = now - self.last_update;
@@ -440,297 +1448,306 @@ impl NetworkDriver {
         // }
 
         // Poll internal callbacks
-        self.gns_global.poll_callbacks();
+        server.update();
+
+        let mut packets_to_emit: Vec<(PeerId, i64, Packet)> = Vec::new();
+        let mut peer_connects_to_emit: Vec<PeerId> = Vec::new();
+        let mut peer_disconnects_to_emit: Vec<PeerId> = Vec::new();
+        let mut ready_changes: Vec<(PeerId, bool)> = Vec::new();
+        let poll_deadline = Instant::now() + self.poll_time_budget;
+        let mut messages_processed = 0;
+
+        // Events and messages are polled in turn so they are handled in the order they
+        // arrived. A disconnect keeps its connection known until the messages
+        // polled alongside it are in, and only then is it forgotten and closed.
+        let mut disconnected = Vec::new();
+        loop {
+            // accepting and closing needs the transport back
+            let mut events = Vec::new();
+            let events_processed = server.poll_events(&mut |event| events.push(event));
+            for event in events {
+                self.handle_server_event(server.as_mut(), event, &mut peer_connects_to_emit, &mut disconnected);
+            }
 
-        let mut packets_to_emit: Vec<(i64, Gd<Object>)> = Vec::new();
-        let mut peer_connects_to_emit: Vec<u8> = Vec::new();
-        let mut peer_disconnects_to_emit: Vec<u8> = Vec::new();
-        let poll_deadline = Instant::now() + Duration::from_millis(POLL_TIME_BUDGET_MS);
+            let processed_count = server.poll_packets(&mut |connection, reliable, payload, packet| {
+                let peer_id = match self.connected_clients.get(&connection) {
+                    Some(peer_id) => {
+                        self.record(Direction::Received, *peer_id, reliable, payload);
+                        *peer_id
+                    }
+                    None => {
+                        net_log!(Error, Server, "Message from unknown connection {:?}", connection);
+                        return;
+                    }
+                };
+                let received_us = ticks_usec();
 
-        // Process connections events.
-        loop {
-            let processed = server
-                .poll_event::<MAX_EVENTS_PER_POLL>(|event| {
-                    match (event.old_state(), event.info().state()) {
-                        (
-                            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_None,
-                            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connecting,
-                        ) => {
-                            if self.available_peer_ids.is_empty() {
-                                self.queue_debug(format!("GnsSocket<Server>: no available peer ids"));
-                                server.close_connection(event.connection(), 1001, "Server is full", false);
-                            } else {
-                                let result = server.accept(event.connection());
-                                self.queue_debug(format!("GnsSocket<Server>: accepted new client: {:#?}.", result));
-                                self.queue_debug(format!(
-                                    "GnsSocket<Server>: number of clients: {:#?}.",
-                                    self.connected_clients.len()
-                                ));
-                            }
-                        }
-                        (
-                            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connecting,
-                            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_Connected,
-                        ) => {
-                            match self.available_peer_ids.pop() {
-                                Some(peer_id) => {
-                                    self.connected_clients.insert(event.connection(), peer_id.try_into().unwrap());
-                                    self.queue_debug(format!(
-                                        "GnsSocket<Server>: new client connected with peer id: {:#?}.",
-                                        peer_id
-                                    ));
-                                    peer_connects_to_emit.push(peer_id);
-                                }
-                                None => {
-                                    self.queue_debug(format!(
-                                        "GnsSocket<Server>: no available peer ids, this should not happen"
-                                    ));
-                                    server.close_connection(event.connection(), 2000, "Server is full", false);
-                                }
-                            }
-                        }
-                        (
-                            _,
-                            ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_ClosedByPeer
-                                | ESteamNetworkingConnectionState::k_ESteamNetworkingConnectionState_ProblemDetectedLocally,
-                        ) => {
-                            let conn = event.connection();
-                            match self.connected_clients.remove(&conn) {
-                                Some(peer_id) => {
-                                    self.queue_debug(format!(
-                                        "GnsSocket<Server>: {:#?} disconnected with peer id: {:#?}.",
-                                        conn,
-                                        peer_id
-                                    ));
-                                    peer_disconnects_to_emit.push(peer_id);
-                                }
-                                None => {
-                                    self.queue_debug(format!(
-                                        "GnsSocket<Server>: {:#?} disconnected, but not found in connected clients",
-                                        conn
-                                    ));
-                                }
-                            }
-                            self.queue_debug(format!("GnsSocket<Server>: closing connection for {:#?}.", conn));
-                            server.close_connection(conn, 1002, "Closing connection ended by client", false);
-                        }
-                        (previous, current) => {
-                            self.queue_debug(format!("GnsSocket<Server>: {:#?} => {:#?}.", previous, current));
-                        }
+                match packet {
+                    Ok(Packet::LobbyReady(ready)) if self.lobby.is_some() => {
+                        ready_changes.push((peer_id, ready.ready));
+                    }
+                    Ok(packet) => {
+                        packets_to_emit.push((peer_id, received_us, packet));
+                    }
+                    Err(e) => {
+                        self.metrics.borrow_mut().count_decode_failure();
+                        net_log!(Warning, Packet, peer = peer_id, "Failed to decode packet: {}, raw data {:x?}", e, payload);
                     }
-                });
+                }
+            });
+            messages_processed += processed_count;
 
-            if processed == 0 || Instant::now() >= poll_deadline {
+            if events_processed == 0 && processed_count == 0 {
+                break;
+            }
+            if messages_processed >= self.max_messages_per_poll || Instant::now() >= poll_deadline {
+                self.metrics.get_mut().count_poll_budget_overrun();
                 break;
             }
         }
 
-        // Process messages with bounded batches and time budget.
-        loop {
-            let processed = server
-                .poll_messages::<MAX_MESSAGES_PER_POLL>(|message| {
-                    let packet = Packet::decode(message.payload());
-
-                    let peer_id: i64 = match self.connected_clients.get(&message.connection()) {
-                        Some(peer_id) => (*peer_id).try_into().unwrap(),
-                        None => {
-                            self.queue_debug(format!(
-                                "ERROR: Failed to get peer id for connection: {:#?}",
-                                message.connection()
-                            ));
-                            return;
-                        }
-                    };
+        for connection in disconnected {
+            match self.connected_clients.remove(&connection) {
+                Some(peer_id) => {
+                    net_log!(Info, Server, peer = peer_id, "Disconnected ({:?})", connection);
+                    peer_disconnects_to_emit.push(peer_id);
+                }
+                None => {
+                    net_log!(Warning, Server, "Unknown connection {:?} disconnected", connection);
+                }
+            }
+            net_log!(Debug, Server, "Closing connection {:?}", connection);
+            server.close(connection, 1002, "Closing connection ended by client");
+        }
 
-                    match packet {
-                        Ok(packet) => {
-                            packets_to_emit.push((peer_id, packet.as_gd()));
-                        }
-                        Err(e) => {
-                            self.queue_debug(format!(
-                                "ERROR: Failed to decode packet: {}, raw data {:x?}",
-                                e,
-                                &message.payload()
-                            ));
-                        }
-                    }
-                });
-            let processed_count = processed.unwrap_or(0);
+        self.transport = Some(server);
 
-            if processed_count == 0 || Instant::now() >= poll_deadline {
-                break;
+        if let Some(lobby) = self.lobby.as_mut() {
+            for &peer_id in &peer_connects_to_emit {
+                lobby.join(peer_id);
+                lobby.mark_changed();
+            }
+            for (peer_id, ready) in ready_changes {
+                lobby.set_ready(peer_id, ready);
+            }
+            for &peer_id in &peer_disconnects_to_emit {
+                lobby.leave(peer_id);
             }
         }
 
-        self.server = Some(server);
-
         for peer_id in peer_connects_to_emit {
-            self.signals().on_peer_connect().emit(peer_id as i64);
+            self.signals().on_peer_connect().emit(i64::from(peer_id));
+        }
+
+        // a peer's last packets come before its disconnect
+        for (peer_id, received_us, packet) in packets_to_emit {
+            self.deliver_server_packet(peer_id, packet, received_us);
         }
 
         for peer_id in peer_disconnects_to_emit {
-            self.signals().on_peer_disconnect().emit(peer_id as i64);
-            self.available_peer_ids.push(peer_id);
+            self.signals().on_peer_disconnect().emit(i64::from(peer_id));
+            self.peer_slots.release(peer_id);
+            self.player_names.remove(&peer_id);
+            self.metrics.get_mut().forget_peer(peer_id);
         }
 
-        for (peer_id, packet) in packets_to_emit {
-            self.signals().on_server_packet().emit(peer_id, &packet);
+        self.handle_local_peer();
+    }
+
+    /// Accepts, admits or notes the disconnect of a connection as `event` says.
+    fn handle_server_event(
+        &mut self,
+        server: &mut dyn Transport,
+        event: TransportEvent,
+        peer_connects_to_emit: &mut Vec<PeerId>,
+        disconnected: &mut Vec<ConnectionId>,
+    ) {
+        match event {
+            TransportEvent::Connecting { connection, remote } => {
+                let allow_reserved = self.reserved_addresses.contains(&remote);
+                if !self.peer_slots.has_available(allow_reserved) {
+                    net_log!(Warning, Server, "Refused {}, no peer ids left", remote);
+                    server.close(connection, 1001, "Server is full");
+                } else {
+                    let result = server.accept(connection);
+                    net_log!(Verbose, Server, "Accepted {}: {:?}", remote, result);
+                    net_log!(Debug, Server, "{} clients connected", self.connected_clients.len());
+                }
+            }
+            TransportEvent::Connected { connection, remote } => {
+                let allow_reserved = self.reserved_addresses.contains(&remote);
+                match self.peer_slots.acquire(allow_reserved) {
+                    Some(peer_id) => {
+                        self.connected_clients.insert(connection, peer_id);
+                        net_log!(Info, Server, peer = peer_id, "Connected from {}", remote);
+                        peer_connects_to_emit.push(peer_id);
+                    }
+                    None => {
+                        net_log!(Error, Server, "No peer id left for {} after accepting it", remote);
+                        server.close(connection, 2000, "Server is full");
+                    }
+                }
+            }
+            TransportEvent::Disconnected { connection, .. } => disconnected.push(connection),
         }
     }
 
-    #[func]
-    fn set_fake_ping_lag_send(&mut self, value: i64) {
-        if !self.is_server {
+    /// Delivers the listen-server host's packets in both directions, emitting
+    /// the same signals a remote peer and a regular client would see.
+    fn handle_local_peer(&mut self) {
+        let Some(peer_id) = self.local_peer else {
             return;
+        };
+
+        if !self.local_peer_joined {
+            self.local_peer_joined = true;
+            if let Some(lobby) = self.lobby.as_mut() {
+                lobby.join(peer_id);
+            }
+            net_log!(Info, Server, peer = peer_id, "Local player joined");
+            self.signals().on_peer_connect().emit(i64::from(peer_id));
+            self.signals().on_connect_to_server().emit();
+        }
+
+        // the host's packets never cross the network, so they arrive now
+        let received_us = ticks_usec();
+        let inbox = std::mem::take(self.local_inbox.get_mut());
+        for packet in inbox {
+            if let (Packet::LobbyReady(ready), Some(lobby)) = (&packet, self.lobby.as_mut()) {
+                lobby.set_ready(peer_id, ready.ready);
+                continue;
+            }
+            self.deliver_server_packet(peer_id, packet, received_us);
+        }
+
+        let outbox = std::mem::take(self.local_outbox.get_mut());
+        for packet in outbox {
+            self.deliver_client_packet(packet, received_us);
         }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLag_Send,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
     }
-    
-    #[func]
-    fn set_fake_ping_lag_recv(&mut self, value: i64) {
-        if !self.is_server {
-            return;
+
+    fn deliver_server_packet(&mut self, peer_id: PeerId, packet: Packet, received_us: i64) {
+        if self.batch_packets {
+            self.queue_packet(i64::from(peer_id), received_us, packet);
+        } else {
+            let object = self.emit_server_packet(peer_id, &packet, received_us);
+            self.dispatch_to_handlers(i64::from(peer_id), packet.id(), &object);
         }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLag_Recv,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
     }
 
-    #[func]
-    fn set_fake_loss_send(&mut self, value: i64) {
-        if !self.is_server {
-            return;
+    fn deliver_client_packet(&mut self, packet: Packet, received_us: i64) {
+        if self.batch_packets {
+            self.queue_packet(-1, received_us, packet);
+        } else {
+            let object = self.emit_client_packet(&packet, received_us);
+            self.dispatch_to_handlers(-1, packet.id(), &object);
         }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLoss_Send,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
     }
 
-    #[func]
-    fn set_fake_loss_recv(&mut self, value: i64) {
-        if !self.is_server {
-            return;
+    fn queue_packet(&mut self, peer_id: i64, received_us: i64, packet: Packet) {
+        if self.pending_packets.len() == MAX_PENDING_PACKETS {
+            self.pending_packets.pop_front();
+            self.pending_packets_dropped += 1;
         }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLoss_Recv,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
+        self.pending_packets.push_back((peer_id, received_us, packet));
     }
 
-    #[func]
-    fn set_fake_jitter_send(&mut self, value: i64) {
-        if !self.is_server {
-            return;
+    /// Calls the handlers registered for `id` until one returns true.
+    fn dispatch_to_handlers(&mut self, peer_id: i64, id: PacketId, packet: &Gd<Object>) {
+        let mut handlers = std::mem::take(&mut self.handler_scratch);
+        if self.packet_handlers.handlers_for(id, &mut handlers) {
+            let args = [peer_id.to_variant(), packet.to_variant()];
+            // handlers may call back into the driver, e.g. to broadcast
+            let guard = self.base_mut();
+            for handler in &handlers {
+                if handler.call(&args).try_to::<bool>().unwrap_or(false) {
+                    break;
+                }
+            }
+            drop(guard);
         }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketJitter_Send_Avg,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
+        handlers.clear();
+        self.handler_scratch = handlers;
     }
 
-    #[func]
-    fn set_fake_jitter_recv(&mut self, value: i64) {
+    /// Sets a GNS fake link value. Like the GNS settings themselves this
+    /// only does anything on the server.
+    fn set_gns_config(&mut self, key: ESteamNetworkingConfigValue, value: i64) -> i64 {
+        let result = self._set_gns_config(key, value);
+        self.report(result)
+    }
+
+    fn _set_gns_config(&self, key: ESteamNetworkingConfigValue, value: i64) -> Result<(), NetworkError> {
         if !self.is_server {
-            return;
+            return Ok(());
+        }
+        match (self.gns_global.as_ref(), u32::try_from(value)) {
+            (None, _) => Err(NetworkError::NotInitialized("GameNetworkingSockets")),
+            (_, Err(_)) => Err(NetworkError::Invalid(format!("invalid {key:?} value {value}"))),
+            (Some(gns_global), Ok(value)) => gns_global
+                .utils()
+                .set_global_config_value(key, GnsConfig::Int32(value))
+                .map_err(|()| NetworkError::Invalid(format!("GNS rejected {key:?} value {value}"))),
         }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketJitter_Recv_Avg,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
     }
 
     #[func]
-    fn set_fake_dup_send(&mut self, value: i64) {
-        if !self.is_server {
-            return;
-        }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_Send,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
+    fn set_fake_ping_lag_send(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLag_Send, value)
     }
-    
+
     #[func]
-    fn set_fake_dup_recv(&mut self, value: i64) {
-        if !self.is_server {
-            return;
-        }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_Recv,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
+    fn set_fake_ping_lag_recv(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLag_Recv, value)
     }
 
     #[func]
-    fn set_fake_dup_ms_max(&mut self, value: i64) {
-        if !self.is_server {
-            return;
-        }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_TimeMax,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
+    fn set_fake_loss_send(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLoss_Send, value)
     }
 
     #[func]
-    fn set_fake_reorder_send(&mut self, value: i64) {
-        if !self.is_server {
-            return;
-        }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Send,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
+    fn set_fake_loss_recv(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketLoss_Recv, value)
     }
 
     #[func]
-    fn set_fake_reorder_recv(&mut self, value: i64) {
-        if !self.is_server {
-            return;
-        }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Recv,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
+    fn set_fake_jitter_send(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketJitter_Send_Avg, value)
     }
 
     #[func]
-    fn set_fake_reorder_ms(&mut self, value: i64) {
-        if !self.is_server {
-            return;
-        }
-        self.gns_global.utils().set_global_config_value(
-            ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Time,
-            GnsConfig::Int32(i64_to_u32(value)),
-        ).unwrap_or_else(|e| {
-            godot_print!("ERROR: Failed to set global config value: {:#?}", e);
-        });
+    fn set_fake_jitter_recv(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketJitter_Recv_Avg, value)
+    }
+
+    #[func]
+    fn set_fake_dup_send(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_Send, value)
+    }
+
+    #[func]
+    fn set_fake_dup_recv(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_Recv, value)
+    }
+
+    #[func]
+    fn set_fake_dup_ms_max(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketDup_TimeMax, value)
+    }
+
+    #[func]
+    fn set_fake_reorder_send(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Send, value)
+    }
+
+    #[func]
+    fn set_fake_reorder_recv(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Recv, value)
+    }
+
+    #[func]
+    fn set_fake_reorder_ms(&mut self, value: i64) -> i64 {
+        self.set_gns_config(ESteamNetworkingConfigValue::k_ESteamNetworkingConfig_FakePacketReorder_Time, value)
     }
 }
\ No newline at end of file
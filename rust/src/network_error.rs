@@ -0,0 +1,90 @@
+use br_core::transport::TransportError;
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Why a `NetworkDriver` call failed. GDScript sees `code()`, exposed as the
+/// driver's `ERR_*` constants, and the message through `on_network_error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum NetworkError {
+    /// An address, port or other argument that doesn't parse.
+    Parse(String),
+    Bind {
+        addr: SocketAddr,
+        reason: String,
+    },
+    Connect {
+        addr: SocketAddr,
+        reason: String,
+    },
+    /// The call needs something that isn't running, e.g. a server or GNS.
+    NotInitialized(&'static str),
+    Send(String),
+    /// Arguments that parse but don't make sense, like a capacity of 0.
+    Invalid(String),
+    /// Capture, demo and log files.
+    Io {
+        path: String,
+        reason: String,
+    },
+}
+
+impl NetworkError {
+    pub(crate) const OK: i64 = 0;
+    pub(crate) const PARSE: i64 = 1;
+    pub(crate) const BIND: i64 = 2;
+    pub(crate) const CONNECT: i64 = 3;
+    pub(crate) const NOT_INITIALIZED: i64 = 4;
+    pub(crate) const SEND: i64 = 5;
+    pub(crate) const INVALID: i64 = 6;
+    pub(crate) const IO: i64 = 7;
+
+    pub(crate) fn code(&self) -> i64 {
+        match self {
+            NetworkError::Parse(_) => Self::PARSE,
+            NetworkError::Bind { .. } => Self::BIND,
+            NetworkError::Connect { .. } => Self::CONNECT,
+            NetworkError::NotInitialized(_) => Self::NOT_INITIALIZED,
+            NetworkError::Send(_) => Self::SEND,
+            NetworkError::Invalid(_) => Self::INVALID,
+            NetworkError::Io { .. } => Self::IO,
+        }
+    }
+
+    pub(crate) fn bind(addr: SocketAddr, reason: impl fmt::Display) -> Self {
+        NetworkError::Bind { addr, reason: reason.to_string() }
+    }
+
+    pub(crate) fn connect(addr: SocketAddr, reason: TransportError) -> Self {
+        NetworkError::Connect { addr, reason: reason.to_string() }
+    }
+
+    pub(crate) fn io(path: &str, reason: impl fmt::Display) -> Self {
+        NetworkError::Io { path: path.to_string(), reason: reason.to_string() }
+    }
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Parse(what) => write!(f, "failed to parse {what}"),
+            NetworkError::Bind { addr, reason } => write!(f, "failed to listen on {addr}: {reason}"),
+            NetworkError::Connect { addr, reason } => write!(f, "failed to connect to {addr}: {reason}"),
+            NetworkError::NotInitialized(what) => write!(f, "{what} is not running"),
+            NetworkError::Send(reason) => write!(f, "failed to send: {reason}"),
+            NetworkError::Invalid(reason) => write!(f, "{reason}"),
+            NetworkError::Io { path, reason } => write!(f, "{path}: {reason}"),
+        }
+    }
+}
+
+/// Parses an IP address argument.
+pub(crate) fn parse_ip(ip_address: &str) -> Result<IpAddr, NetworkError> {
+    ip_address.parse().map_err(|e| NetworkError::Parse(format!("IP address '{ip_address}': {e}")))
+}
+
+/// Parses a port argument; `what` names it in the error, e.g. "query port".
+pub(crate) fn parse_port(port: i64, what: &str) -> Result<u16, NetworkError> {
+    u16::try_from(port).map_err(|_| NetworkError::Parse(format!("{what} {port}")))
+}
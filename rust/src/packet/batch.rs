@@ -0,0 +1,115 @@
+use super::chat::ChatPacketBatch;
+use super::id_assignment::IdAssignmentPacketBatch;
+use super::lobby_ready::LobbyReadyPacketBatch;
+use super::lobby_state::LobbyStatePacketBatch;
+use super::null::NullPacketBatch;
+use super::player_disconnected::PlayerDisconnectedPacketBatch;
+use super::player_input::PlayerInputPacketBatch;
+use super::player_state::PlayerStatePacketBatch;
+use crate::packet::prelude::*;
+
+/// Maps a packet field's Godot type to the container a batch keeps that
+/// field in, one entry per packet.
+pub(crate) trait Columnar: Sized {
+    type Column: Default;
+
+    /// Overwrites `column` with `len` values. Sizing in place keeps the
+    /// column's storage when the batch is refilled.
+    fn fill(column: &mut Self::Column, len: usize, values: impl Iterator<Item = Self>);
+}
+
+macro_rules! impl_packed_columnar {
+    ($godot_ty:ty, $column:ty) => {
+        impl Columnar for $godot_ty {
+            type Column = $column;
+
+            fn fill(column: &mut $column, len: usize, values: impl Iterator<Item = Self>) {
+                column.resize(len);
+                for (slot, value) in column.as_mut_slice().iter_mut().zip(values) {
+                    *slot = value;
+                }
+            }
+        }
+    };
+}
+
+impl_packed_columnar!(i64, PackedInt64Array);
+impl_packed_columnar!(f64, PackedFloat64Array);
+impl_packed_columnar!(Vector2, PackedVector2Array);
+impl_packed_columnar!(Vector3, PackedVector3Array);
+impl_packed_columnar!(GString, PackedStringArray);
+
+/// 0 or 1 per packet.
+impl Columnar for bool {
+    type Column = PackedByteArray;
+
+    fn fill(column: &mut PackedByteArray, len: usize, values: impl Iterator<Item = Self>) {
+        column.resize(len);
+        for (slot, value) in column.as_mut_slice().iter_mut().zip(values) {
+            *slot = u8::from(value);
+        }
+    }
+}
+
+/// Godot has no arrays of typed arrays, so each packet's list is a `Variant`.
+impl Columnar for Array<i64> {
+    type Column = Array<Variant>;
+
+    fn fill(column: &mut Array<Variant>, _len: usize, values: impl Iterator<Item = Self>) {
+        column.clear();
+        for value in values {
+            column.push(&value.to_variant());
+        }
+    }
+}
+
+// a batch, a filter and a getter per `Packet` variant, so each batch only
+// sees its own type, e.g. `chat: Gd<ChatPacketBatch>` and `fn chat()`
+macro_rules! packet_batch {
+    ({} $($variant:ident: $class:ident),+ $(,)?) => {
+        paste::paste! {
+            /// A tick's worth of received packets from `NetworkDriver.drain_packets`,
+            /// one batch per packet type with a column per field. The driver refills
+            /// the same batches on every drain, so copy out anything kept longer.
+            #[derive(GodotClass)]
+            #[class(no_init, base=RefCounted)]
+            pub(crate) struct PacketBatch {
+                base: Base<RefCounted>,
+                $( [<$variant:snake>]: Gd<[<$class Batch>]>, )+
+            }
+
+            impl PacketBatch {
+                pub(crate) fn empty() -> Gd<Self> {
+                    Gd::from_init_fn(|base| Self {
+                        base,
+                        $( [<$variant:snake>]: [<$class Batch>]::empty(), )+
+                    })
+                }
+
+                /// Replaces every batch's contents with `packets`, as
+                /// `(peer_id, received_us, packet)`.
+                pub(crate) fn fill(&mut self, packets: &[(i64, i64, Packet)]) {
+                    $(
+                        let of_type = packets.iter().filter_map(|(peer_id, received_us, packet)| match packet {
+                            Packet::$variant(packet) => Some((*peer_id, *received_us, packet)),
+                            _ => None,
+                        });
+                        self.[<$variant:snake>].bind_mut().fill(of_type);
+                    )+
+                }
+            }
+
+            #[godot_api]
+            impl PacketBatch {
+                $(
+                    #[func]
+                    fn [<$variant:snake>](&self) -> Gd<[<$class Batch>]> {
+                        self.[<$variant:snake>].clone()
+                    }
+                )+
+            }
+        }
+    };
+}
+
+for_each_packet!(packet_batch! {});
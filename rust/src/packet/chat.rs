@@ -4,7 +4,6 @@ use godot::prelude::*;
 define_packet! {
     name: ChatPacket,
     variant: Chat,
-    reliable: true,
     fields: {
         username: {
             godot: GString,
@@ -19,5 +18,4 @@ define_packet! {
             to_gd: |value: &String| GString::from(value.as_str()),
         },
     },
-    codec: postcard
 }
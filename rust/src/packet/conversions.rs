@@ -1,4 +1,5 @@
 use crate::packet::macros::{ToGodot, ToWire};
+use br_core::net_log;
 use godot::prelude::*;
 use num_traits::FromPrimitive;
 
@@ -26,7 +27,7 @@ impl ToGodot<Vector2> for [f32; 2] {
 impl ToWire<u8> for i64 {
     fn to_wire(&self) -> u8 {
         FromPrimitive::from_i64(*self).unwrap_or_else(|| {
-            godot_print!("ERROR: Failed to convert i64 to u8: {}", self);
+            net_log!(Error, Packet, "Failed to convert i64 to u8: {}", self);
             0
         })
     }
@@ -41,7 +42,7 @@ impl ToGodot<i64> for u8 {
 impl ToWire<f32> for f64 {
     fn to_wire(&self) -> f32 {
         FromPrimitive::from_f64(*self).unwrap_or_else(|| {
-            godot_print!("ERROR: Failed to convert f64 to f32: {}", self);
+            net_log!(Error, Packet, "Failed to convert f64 to f32: {}", self);
             0.0
         })
     }
@@ -56,7 +57,7 @@ impl ToGodot<f64> for f32 {
 impl ToWire<u16> for i64 {
     fn to_wire(&self) -> u16 {
         FromPrimitive::from_i64(*self).unwrap_or_else(|| {
-            godot_print!("ERROR: Failed to convert i64 to u16: {}", self);
+            net_log!(Error, Packet, "Failed to convert i64 to u16: {}", self);
             0
         })
     }
@@ -71,7 +72,7 @@ impl ToGodot<i64> for u16 {
 impl ToWire<u32> for i64 {
     fn to_wire(&self) -> u32 {
         FromPrimitive::from_i64(*self).unwrap_or_else(|| {
-            godot_print!("ERROR: Failed to convert i64 to u32: {}", self);
+            net_log!(Error, Packet, "Failed to convert i64 to u32: {}", self);
             0
         })
     }
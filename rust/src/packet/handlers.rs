@@ -0,0 +1,108 @@
+use crate::packet::prelude::*;
+use br_core::net_log;
+
+macro_rules! impl_packet_class_names {
+    ({} $($variant:ident: $class:ident),+ $(,)?) => {
+        /// The packet type whose Godot class is `class_name`, e.g. "ChatPacket".
+        pub(crate) fn packet_id_of_class(class_name: &str) -> Option<PacketId> {
+            match class_name {
+                $( stringify!($class) => Some(PacketId::$variant), )+
+                _ => None,
+            }
+        }
+
+        fn class_name_of(id: PacketId) -> &'static str {
+            match id {
+                $( PacketId::$variant => stringify!($class), )+
+            }
+        }
+    };
+}
+
+for_each_packet!(impl_packet_class_names! {});
+
+struct Handler {
+    callable: Callable,
+    priority: i64,
+}
+
+#[derive(Default)]
+struct Route {
+    // highest priority first, in registration order among equals
+    handlers: Vec<Handler>,
+    unhandled: u64,
+}
+
+/// `NetworkDriver`'s dispatch table from packet type to the Callables
+/// registered for it.
+///
+/// While any handler is registered, packets of a type nobody handles are
+/// counted, and the first one of each type is logged. With none registered
+/// the table is idle, as when only signals are used.
+#[derive(Default)]
+pub(crate) struct PacketHandlers {
+    // indexed by `PacketId`
+    routes: Vec<Route>,
+}
+
+impl PacketHandlers {
+    fn route_mut(&mut self, id: PacketId) -> &mut Route {
+        let index = id as usize;
+        if self.routes.len() <= index {
+            self.routes.resize_with(index + 1, Route::default);
+        }
+        &mut self.routes[index]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.routes.iter().all(|route| route.handlers.is_empty())
+    }
+
+    /// Adds `callable` for `id`, or moves it to `priority` if it is already
+    /// registered for it.
+    pub(crate) fn register(&mut self, id: PacketId, callable: Callable, priority: i64) {
+        let route = self.route_mut(id);
+        route.handlers.retain(|handler| handler.callable != callable);
+        let at = route.handlers.partition_point(|handler| handler.priority >= priority);
+        route.handlers.insert(at, Handler { callable, priority });
+    }
+
+    /// Returns whether `callable` was registered for `id`.
+    pub(crate) fn unregister(&mut self, id: PacketId, callable: &Callable) -> bool {
+        let route = self.route_mut(id);
+        let before = route.handlers.len();
+        route.handlers.retain(|handler| handler.callable != *callable);
+        route.handlers.len() != before
+    }
+
+    /// Fills `callables` with the handlers for `id` in the order they run,
+    /// dropping those whose object was freed. Returns false, after counting
+    /// the packet, if it has none.
+    pub(crate) fn handlers_for(&mut self, id: PacketId, callables: &mut Vec<Callable>) -> bool {
+        callables.clear();
+        if self.is_empty() {
+            return false;
+        }
+
+        let route = self.route_mut(id);
+        route.handlers.retain(|handler| handler.callable.is_valid());
+        callables.extend(route.handlers.iter().map(|handler| handler.callable.clone()));
+        if callables.is_empty() {
+            route.unhandled += 1;
+            if route.unhandled == 1 {
+                net_log!(
+                    Warning,
+                    Packet,
+                    "No handler registered for {}; further ones are only counted",
+                    class_name_of(id)
+                );
+            }
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn unhandled_count(&self, id: PacketId) -> u64 {
+        self.routes.get(id as usize).map_or(0, |route| route.unhandled)
+    }
+}
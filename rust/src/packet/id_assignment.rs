@@ -4,30 +4,28 @@ use godot::prelude::*;
 define_packet! {
     name: IdAssignmentPacket,
     variant: IdAssignment,
-    reliable: true,
     fields: {
         id: {
             godot: i64,
-            wire: u8,
+            wire: PeerId,
             default: 0,
         },
         remote_ids: {
             godot: Array<i64>,
-            wire: Vec<u8>,
+            wire: Vec<PeerId>,
             default: array![],
             to_wire: |value: &Array<i64>| {
                 value
                     .iter_shared()
-                    .map(|id| id.try_into().unwrap())
-                    .collect::<Vec<u8>>()
+                    .map(|id| crate::packet::macros::convert_to_wire::<i64, PeerId>(&id))
+                    .collect::<Vec<PeerId>>()
             },
-            to_gd: |value: &Vec<u8>| {
+            to_gd: |value: &Vec<PeerId>| {
                 value
                     .iter()
-                    .map(|&id| id as i64)
+                    .map(|&id| i64::from(id))
                     .collect::<Array<i64>>()
             },
         },
     },
-    codec: postcard
 }
@@ -0,0 +1,12 @@
+use crate::packet::prelude::*;
+use godot::prelude::*;
+
+define_packet! {
+    name: LobbyReadyPacket,
+    variant: LobbyReady,
+    fields: {
+        ready: {
+            godot: bool,
+        },
+    },
+}
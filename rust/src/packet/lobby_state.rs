@@ -0,0 +1,49 @@
+use crate::packet::prelude::*;
+use godot::prelude::*;
+
+define_packet! {
+    name: LobbyStatePacket,
+    variant: LobbyState,
+    fields: {
+        phase: {
+            godot: i64,
+            wire: u8,
+        },
+        countdown_ms: {
+            godot: i64,
+            wire: u32,
+        },
+        min_players: {
+            godot: i64,
+            wire: u16,
+        },
+        max_players: {
+            godot: i64,
+            wire: u16,
+        },
+        players: {
+            godot: Array<i64>,
+            wire: Vec<PeerId>,
+            default: array![],
+            to_wire: |value: &Array<i64>| {
+                value
+                    .iter_shared()
+                    .map(|id| crate::packet::macros::convert_to_wire::<i64, PeerId>(&id))
+                    .collect::<Vec<PeerId>>()
+            },
+            to_gd: |value: &Vec<PeerId>| value.iter().map(|&id| i64::from(id)).collect::<Array<i64>>(),
+        },
+        ready: {
+            godot: Array<i64>,
+            wire: Vec<PeerId>,
+            default: array![],
+            to_wire: |value: &Array<i64>| {
+                value
+                    .iter_shared()
+                    .map(|id| crate::packet::macros::convert_to_wire::<i64, PeerId>(&id))
+                    .collect::<Vec<PeerId>>()
+            },
+            to_gd: |value: &Vec<PeerId>| value.iter().map(|&id| i64::from(id)).collect::<Array<i64>>(),
+        },
+    },
+}
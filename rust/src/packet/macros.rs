@@ -7,9 +7,8 @@ Future enhancements and notes for define_packet! macro
   payload or by switching the Godot API to propagate `Option`/`Result`.
 
 - Packet enum/ID registration (optional):
-  Provide a separate `register_packets!(...)` macro that generates
-  `PacketId`, `Packet`, and all match arms. This eliminates the manual
-  edits to `packet.rs` when adding new packets.
+  `for_each_packet!` already generates the Godot-side match arms; `PacketId`
+  and `Packet` are still written out by hand in `br_core`.
 
 - Tradeoffs and considerations:
   * Macro debuggability: compile errors can be less obvious; keep expansions
@@ -18,18 +17,17 @@ Future enhancements and notes for define_packet! macro
     data shows up, consider adjusting the API to borrow or rebuild from
     references.
   * Symmetry: Godot-facing type stays clean (`PacketName`), wire type is
-    auto-suffixed with `Wire` and kept engine-free.
+    auto-suffixed with `Wire` and lives in the engine-free `br_core` crate.
 */
 
 /*
 Macro schema: define_packet!
 
-Invocation shape (postcard/serde codec)
+Invocation shape
 
     define_packet! {
         name: PacketTypeName,
         variant: PacketEnumVariant,
-        reliable: <bool>,
         fields: {
             field_a: {
                 godot: <GodotFieldType>
@@ -40,27 +38,30 @@ Invocation shape (postcard/serde codec)
             },
             field_b: { ... },
         },
-        codec: postcard
     }
 
 What gets generated
 
 1) Godot-facing class: `PacketTypeName`
    - `#[derive(GodotClass)] #[class(base=RefCounted)]`
-   - Fields are declared with Godot types and `#[var]` for export.
+   - Fields are declared with Godot types and `#[var]` for export, plus
+     `received_us`, which the driver sets when it polls a received packet.
    - API:
      - `fn new(..godot_fields..) -> Gd<Self>`
      - `fn create(..godot_fields..) -> Gd<GdPacket>`: builds the wire struct and wraps into `Packet::<variant>`
      - `fn to_payload(&self) -> Gd<GdPacket>`: convenience wrapper calling `create(..self fields..)`, so GDScript can forward a received packet back out without re-specifying fields.
 
-2) Wire/data struct: `PacketTypeNameWire`
-   - `#[derive(serde::Serialize, serde::Deserialize)]`
-   - Pure Rust data for network encoding/decoding; never contains Godot types.
+2) AsGd impl for `br_core::packet::PacketTypeNameWire`
+   - The wire struct, its reliability and codec are defined with
+     `define_wire_packet!` in `br_core`; field names must match.
+   - `fn as_gd(&self) -> Gd<Object>` builds the Godot class from a decoded packet;
+     `PacketTypeName::from_wire` does the same without upcasting.
 
-3) PacketData impl for `PacketTypeNameWire`
-   - `const IS_RELIABLE: bool = reliable`
-   - `fn encode(&self) -> Vec<u8>`: uses `postcard::to_allocvec(self)`; logs errors on failure.
-   - `fn decode(data: &[u8]) -> std::io::Result<Self>`: uses `postcard::from_bytes(data)`.
+3) Batch class: `PacketTypeNameBatch`
+   - What `NetworkDriver.drain_packets` returns for this type: `peer_id` and
+     `received_us` columns plus one column per field, each a packed array where Godot has
+     one (see `batch::Columnar`).
+   - `fn fill(..)` refills the columns in place from decoded wire structs.
 
 4) Conversions
    - Per-field conversion is governed by `to_wire` and `to_gd` closures.
@@ -76,8 +77,12 @@ What gets generated
    - If the Godot type does not implement `Default` and no `default:` is provided, compilation fails in `init`, forcing an explicit default.
 
 Notes
-   - The macro does not modify `PacketId` or the `Packet` enum; add new variants in `packet.rs` (or introduce a separate registration macro later).
+   - The macro does not modify `PacketId` or the `Packet` enum; add new variants in
+     `br_core`'s `packet.rs` and the `AsGd` match in this crate's `packet.rs`.
 */
+use godot::prelude::{Gd, Object};
+
+pub(crate) trait AsGd { fn as_gd(&self) -> Gd<Object>; }
 pub(crate) trait ToWire<W> { fn to_wire(&self) -> W; }
 pub(crate) trait ToGodot<G> { fn to_godot(&self) -> G; }
 
@@ -89,13 +94,6 @@ pub(crate) fn convert_to_wire<G, W>(v: &G) -> W where G: ToWire<W> { <G as ToWir
 #[inline]
 pub(crate) fn convert_to_godot<W, G>(v: &W) -> G where W: ToGodot<G> { <W as ToGodot<G>>::to_godot(v) }
 
-macro_rules! define_packet_field_wire_ty {
-    ($godot_ty:ty, $wire_ty:ty) => { $wire_ty };
-    (Vector3) => { [f32; 3] };
-    (Vector2) => { [f32; 2] };
-    ($godot_ty:ty) => { $godot_ty };
-}
-
 macro_rules! define_packet_field_default {
     ($godot_ty:ty, $default:expr) => {
         $default
@@ -151,7 +149,6 @@ macro_rules! define_packet {
     (
         name: $name:ident,
         variant: $variant:ident,
-        reliable: $reliable:expr,
         fields: {
             $( $field:ident : {
                 godot: $godot_ty:ty
@@ -161,27 +158,42 @@ macro_rules! define_packet {
                 $(, to_gd: $to_gd:expr)?
                 $(,)?
             } ),+ $(,)?
-        },
-        codec: postcard
+        } $(,)?
     ) => {
         paste::paste! {
             #[derive(GodotClass)]
             #[class(base=RefCounted)]
             pub(crate) struct $name {
                 base: Base<RefCounted>,
+                /// When the driver polled this packet, in `Time.get_ticks_usec()`
+                /// microseconds; -1 for packets built locally.
+                #[var]
+                pub(crate) received_us: i64,
                 $( #[var] $field: $godot_ty ),+
             }
 
             #[godot_api]
             impl $name {
                 pub(crate) fn with_fields($( $field: $godot_ty ),+) -> Gd<Self> {
-                    Gd::from_init_fn(|base| Self { base, $( $field ),+ })
+                    Gd::from_init_fn(|base| Self { base, received_us: -1, $( $field ),+ })
+                }
+
+                pub(crate) fn from_wire(packet: &[<$name Wire>]) -> Gd<Self> {
+                    Self::with_fields(
+                        $( define_packet_field_to_gd!(
+                            packet.$field,
+                            $godot_ty
+                            $(, $wire_ty)?
+                            $(, $to_gd)?
+                        ) ),+
+                    )
                 }
 
                 #[func]
                 pub(crate) fn new() -> Gd<Self> {
                     Gd::from_init_fn(|base| Self {
                         base,
+                        received_us: -1,
                         $( $field: define_packet_field_default!(
                             $godot_ty
                             $(, $default)?
@@ -214,6 +226,7 @@ macro_rules! define_packet {
                 fn init(base: Base<RefCounted>) -> Self {
                     Self {
                         base,
+                        received_us: -1,
                         $( $field: define_packet_field_default!(
                             $godot_ty
                             $(, $default)?
@@ -222,51 +235,64 @@ macro_rules! define_packet {
                 }
             }
 
-            #[derive(serde::Serialize, serde::Deserialize)]
-            pub(crate) struct [<$name Wire>] {
-                $( pub(crate) $field: define_packet_field_wire_ty!(
-                    $godot_ty
-                    $(, $wire_ty)?
-                ) ),+
+            impl AsGd for [<$name Wire>] {
+                fn as_gd(&self) -> Gd<Object> {
+                    $name::from_wire(self).upcast::<Object>()
+                }
             }
 
-            impl [<$name Wire>] {
-                pub(crate) fn as_gd(&self) -> Gd<Object> {
-                    $name::with_fields(
-                        $( define_packet_field_to_gd!(
-                            self.$field,
-                            $godot_ty
-                            $(, $wire_ty)?
-                            $(, $to_gd)?
-                        ) ),+
-                    ).upcast::<Object>()
-                }
+            #[derive(GodotClass)]
+            #[class(no_init, base=RefCounted)]
+            pub(crate) struct [<$name Batch>] {
+                base: Base<RefCounted>,
+                /// Who sent each packet; -1 for packets from the server.
+                #[var]
+                peer_id: PackedInt64Array,
+                /// When the driver polled each packet, as the packet's `received_us`.
+                #[var]
+                received_us: PackedInt64Array,
+                $( #[var] $field: <$godot_ty as crate::packet::batch::Columnar>::Column ),+
             }
 
-            impl PacketData for [<$name Wire>] {
-                const IS_RELIABLE: bool = $reliable;
-
-                fn encode(&self) -> Vec<u8> {
-                    match postcard::to_allocvec(self) {
-                        Ok(bytes) => bytes,
-                        Err(err) => {
-                            godot_print!(
-                                "ERROR: Failed to encode {}: {}",
-                                concat!(stringify!($name), "Wire"),
-                                err
-                            );
-                            Vec::new()
-                        }
-                    }
+            impl [<$name Batch>] {
+                pub(crate) fn empty() -> Gd<Self> {
+                    Gd::from_init_fn(|base| Self {
+                        base,
+                        peer_id: PackedInt64Array::new(),
+                        received_us: PackedInt64Array::new(),
+                        $( $field: Default::default() ),+
+                    })
                 }
 
-                fn decode(data: &[u8]) -> std::io::Result<Self> {
-                    postcard::from_bytes(data).map_err(|err| {
-                        std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            format!("postcard decode failed: {}", err)
-                        )
-                    })
+                /// Refills every column from `(peer_id, received_us, packet)`.
+                pub(crate) fn fill<'a>(
+                    &mut self,
+                    packets: impl Iterator<Item = (i64, i64, &'a [<$name Wire>])> + Clone,
+                ) {
+                    use crate::packet::batch::Columnar;
+                    let len = packets.clone().count();
+                    i64::fill(&mut self.peer_id, len, packets.clone().map(|(peer_id, _, _)| peer_id));
+                    i64::fill(&mut self.received_us, len, packets.clone().map(|(_, received_us, _)| received_us));
+                    $(
+                        <$godot_ty as Columnar>::fill(
+                            &mut self.$field,
+                            len,
+                            packets.clone().map(|(_, _, packet)| define_packet_field_to_gd!(
+                                packet.$field,
+                                $godot_ty
+                                $(, $wire_ty)?
+                                $(, $to_gd)?
+                            )),
+                        );
+                    )+
+                }
+            }
+
+            #[godot_api]
+            impl [<$name Batch>] {
+                #[func]
+                pub(crate) fn size(&self) -> i64 {
+                    self.peer_id.len() as i64
                 }
             }
         }
@@ -274,27 +300,30 @@ macro_rules! define_packet {
 }
 
 // A specialized macro for packets with no fields ("null"/"empty" payload)
-// Generates a Godot-facing class `<Name>` and a unit wire struct `<Name>Wire`.
+// Generates a Godot-facing class `<Name>` for the unit wire struct `<Name>Wire`.
 // Example:
-// define_null_packet! { name: NullPacket, variant: Null, reliable: false }
+// define_null_packet! { name: NullPacket, variant: Null }
 macro_rules! define_null_packet {
     (
         name: $name:ident,
-        variant: $variant:ident,
-        reliable: $reliable:expr $(,)?
+        variant: $variant:ident $(,)?
     ) => {
         paste::paste! {
             #[derive(GodotClass)]
             #[class(base=RefCounted)]
             pub(crate) struct $name {
                 base: Base<RefCounted>,
+                /// When the driver polled this packet, in `Time.get_ticks_usec()`
+                /// microseconds; -1 for packets built locally.
+                #[var]
+                pub(crate) received_us: i64,
             }
 
             #[godot_api]
             impl $name {
                 #[func]
                 pub(crate) fn new() -> Gd<Self> {
-                    Gd::from_init_fn(|base| Self { base })
+                    Gd::from_init_fn(|base| Self { base, received_us: -1 })
                 }
 
                 #[func]
@@ -311,37 +340,92 @@ macro_rules! define_null_packet {
             #[godot_api]
             impl IRefCounted for $name {
                 fn init(base: Base<RefCounted>) -> Self {
-                    Self { base }
+                    Self { base, received_us: -1 }
                 }
             }
 
-            // Unit-like wire struct for an empty payload
-            pub(crate) struct [<$name Wire>];
+            impl $name {
+                pub(crate) fn from_wire(_packet: &[<$name Wire>]) -> Gd<Self> {
+                    Self::new()
+                }
+            }
 
-            impl [<$name Wire>] {
-                pub(crate) fn as_gd(&self) -> Gd<Object> {
-                    $name::new().upcast::<Object>()
+            impl AsGd for [<$name Wire>] {
+                fn as_gd(&self) -> Gd<Object> {
+                    $name::from_wire(self).upcast::<Object>()
                 }
             }
 
-            impl PacketData for [<$name Wire>] {
-                const IS_RELIABLE: bool = $reliable;
+            #[derive(GodotClass)]
+            #[class(no_init, base=RefCounted)]
+            pub(crate) struct [<$name Batch>] {
+                base: Base<RefCounted>,
+                /// Who sent each packet; -1 for packets from the server.
+                #[var]
+                peer_id: PackedInt64Array,
+                /// When the driver polled each packet, as the packet's `received_us`.
+                #[var]
+                received_us: PackedInt64Array,
+            }
 
-                fn encode(&self) -> Vec<u8> {
-                    Vec::new()
+            impl [<$name Batch>] {
+                pub(crate) fn empty() -> Gd<Self> {
+                    Gd::from_init_fn(|base| Self {
+                        base,
+                        peer_id: PackedInt64Array::new(),
+                        received_us: PackedInt64Array::new(),
+                    })
                 }
 
-                fn decode(data: &[u8]) -> std::io::Result<Self> {
-                    if data.is_empty() {
-                        Ok(Self)
-                    } else {
-                        Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            "Null packet should have empty payload",
-                        ))
-                    }
+                /// Refills every column from `(peer_id, received_us, packet)`.
+                pub(crate) fn fill<'a>(
+                    &mut self,
+                    packets: impl Iterator<Item = (i64, i64, &'a [<$name Wire>])> + Clone,
+                ) {
+                    use crate::packet::batch::Columnar;
+                    let len = packets.clone().count();
+                    i64::fill(&mut self.peer_id, len, packets.clone().map(|(peer_id, _, _)| peer_id));
+                    i64::fill(&mut self.received_us, len, packets.map(|(_, received_us, _)| received_us));
                 }
             }
+
+            #[godot_api]
+            impl [<$name Batch>] {
+                #[func]
+                pub(crate) fn size(&self) -> i64 {
+                    self.peer_id.len() as i64
+                }
+            }
+        }
+    };
+}
+
+/*
+Packet registry: for_each_packet!
+
+    for_each_packet!(callback! { ..args.. });
+
+expands to
+
+    callback! { { ..args.. } IdAssignment: IdAssignmentPacket, PlayerInput: PlayerInputPacket, ... }
+
+with one `Variant: GodotClass` entry per `Packet` variant. Anything that has to
+cover every packet type (the `AsGd` match, `NetworkDriver`'s typed signals) is
+written as a callback, so registering a new packet is one line here.
+*/
+macro_rules! for_each_packet {
+    ($callback:ident! { $($args:tt)* }) => {
+        $callback! {
+            { $($args)* }
+            IdAssignment: IdAssignmentPacket,
+            PlayerInput: PlayerInputPacket,
+            PlayerState: PlayerStatePacket,
+            Chat: ChatPacket,
+            PlayerDisconnected: PlayerDisconnectedPacket,
+            Null: NullPacket,
+            LobbyState: LobbyStatePacket,
+            LobbyReady: LobbyReadyPacket,
         }
     };
 }
+pub(crate) use for_each_packet;
@@ -2,12 +2,15 @@
 mod macros;
 mod conversions;
 mod packet;
-mod packet_data;
 mod gd_packet;
+pub(crate) mod batch;
+mod handlers;
 mod null;
 mod chat;
 mod id_assignment;
 mod player_disconnected;
 mod player_input;
 mod player_state;
+mod lobby_state;
+mod lobby_ready;
 pub(crate) mod prelude;
@@ -4,5 +4,4 @@ use godot::prelude::*;
 define_null_packet! {
     name: NullPacket,
     variant: Null,
-    reliable: false,
 }
@@ -4,12 +4,10 @@ use godot::prelude::*;
 define_packet! {
     name: PlayerDisconnectedPacket,
     variant: PlayerDisconnected,
-    reliable: true,
     fields: {
         player_id: {
             godot: i64,
-            wire: u8,
+            wire: PeerId,
         },
     },
-    codec: postcard
 }
@@ -5,7 +5,6 @@ use crate::packet::prelude::*;
 define_packet! {
     name: PlayerInputPacket,
     variant: PlayerInput,
-    reliable: false,
     fields: {
         sequence_id: {
             godot: i64,
@@ -43,5 +42,38 @@ define_packet! {
             wire: i8,
         }
     },
-    codec: postcard
-}
\ No newline at end of file
+}
+impl PlayerInputPacket {
+    /// A copy with movement scaled by `weight`, for inputs made up to cover
+    /// a gap.
+    pub(crate) fn decayed(&self, weight: f64) -> Gd<Self> {
+        Self::with_fields(
+            self.sequence_id,
+            self.timestamp_us,
+            self.move_forward_backward * weight,
+            self.move_left_right * weight,
+            self.look_abs,
+            self.jump,
+            self.crouch,
+            self.sprint,
+            self.prone,
+            self.peek_left_right,
+        )
+    }
+
+    /// A copy that keeps movement and look but releases every action.
+    pub(crate) fn movement_only(&self) -> Gd<Self> {
+        Self::with_fields(
+            self.sequence_id,
+            self.timestamp_us,
+            self.move_forward_backward,
+            self.move_left_right,
+            self.look_abs,
+            false,
+            false,
+            false,
+            false,
+            0.0,
+        )
+    }
+}
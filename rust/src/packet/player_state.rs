@@ -4,11 +4,10 @@ use crate::packet::prelude::*;
 define_packet! {
     name: PlayerStatePacket,
     variant: PlayerState,
-    reliable: false,
     fields: {
         player_id: {
             godot: i64,
-            wire: u8,
+            wire: PeerId,
             default: -1,
         },
         last_input_sequence_id: {
@@ -49,5 +48,4 @@ define_packet! {
             wire: f32,
         },
     },
-    codec: postcard
 }
\ No newline at end of file
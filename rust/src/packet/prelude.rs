@@ -1,10 +1,22 @@
 pub(super) use godot::prelude::*;
-pub(crate) use super::packet::Packet;
+pub(crate) use br_core::packet::{Packet, PacketId, PeerId};
 pub(crate) use super::gd_packet::GdPacket;
-pub(super) use super::packet_data::PacketData;
-pub(crate) use super::null::NullPacketWire;
-pub(crate) use super::chat::ChatPacketWire;
-pub(crate) use super::id_assignment::IdAssignmentPacketWire;
-pub(crate) use super::player_disconnected::PlayerDisconnectedPacketWire;
-pub(crate) use super::player_input::PlayerInputPacketWire;
-pub(crate) use super::player_state::PlayerStatePacketWire;
\ No newline at end of file
+pub(crate) use super::batch::PacketBatch;
+pub(crate) use super::handlers::{PacketHandlers, packet_id_of_class};
+pub(crate) use super::macros::{AsGd, for_each_packet};
+pub(crate) use super::null::NullPacket;
+pub(crate) use super::chat::ChatPacket;
+pub(crate) use super::id_assignment::IdAssignmentPacket;
+pub(crate) use super::player_disconnected::PlayerDisconnectedPacket;
+pub(crate) use super::player_input::PlayerInputPacket;
+pub(crate) use super::player_state::PlayerStatePacket;
+pub(crate) use super::lobby_state::LobbyStatePacket;
+pub(crate) use super::lobby_ready::LobbyReadyPacket;
+pub(crate) use br_core::packet::NullPacketWire;
+pub(crate) use br_core::packet::ChatPacketWire;
+pub(crate) use br_core::packet::IdAssignmentPacketWire;
+pub(crate) use br_core::packet::PlayerDisconnectedPacketWire;
+pub(crate) use br_core::packet::PlayerInputPacketWire;
+pub(crate) use br_core::packet::PlayerStatePacketWire;
+pub(crate) use br_core::packet::LobbyStatePacketWire;
+pub(crate) use br_core::packet::LobbyReadyPacketWire;
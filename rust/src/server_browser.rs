@@ -0,0 +1,105 @@
+use crate::server_info::ServerInfo;
+use br_core::net_log;
+use br_core::packet::PROTOCOL_VERSION;
+use br_core::registry::{DEFAULT_MASTER_PORT, QueryOutcome, ServerFilter, ServerListClient};
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    time::Instant,
+};
+
+/// Resolves `host:port`, defaulting the port to the master server's.
+pub(crate) fn resolve_master(address: &str) -> Option<SocketAddr> {
+    let address = if address.contains(':') { address.to_string() } else { format!("{address}:{DEFAULT_MASTER_PORT}") };
+    address.to_socket_addrs().ok()?.next()
+}
+
+/// Client side of the internet server list: asks the master server at
+/// `master_address` for servers and hands them back as `ServerInfo`s.
+#[derive(GodotClass)]
+#[class(base=Node)]
+struct ServerBrowser {
+    base: Base<Node>,
+    client: Option<(SocketAddr, ServerListClient)>,
+    /// `host:port` of the master server.
+    #[var]
+    master_address: GString,
+}
+
+#[godot_api]
+impl INode for ServerBrowser {
+    fn init(base: Base<Node>) -> Self {
+        Self { base, client: None, master_address: format!("127.0.0.1:{DEFAULT_MASTER_PORT}").as_str().into() }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        let Some((_, client)) = self.client.as_mut() else {
+            return;
+        };
+        match client.poll(Instant::now()) {
+            Some(QueryOutcome::Complete(servers)) => {
+                let servers = servers
+                    .iter()
+                    .map(|server| ServerInfo::from_beacon(server.addr, &server.beacon))
+                    .collect::<Array<_>>();
+                self.signals().on_server_list().emit(&servers);
+            }
+            Some(QueryOutcome::TimedOut) => self.signals().on_refresh_failed().emit("master server did not answer"),
+            None => {}
+        }
+    }
+}
+
+#[godot_api]
+impl ServerBrowser {
+    #[signal]
+    fn on_server_list(servers: Array<Gd<ServerInfo>>);
+    #[signal]
+    fn on_refresh_failed(reason: GString);
+
+    /// Asks the master server for its list, dropping any refresh still in
+    /// flight. Empty `map` and `name` match anything; `name` matches a
+    /// substring. The answer arrives as `on_server_list` or
+    /// `on_refresh_failed`.
+    #[func]
+    fn refresh(&mut self, map: String, name: String, not_full: bool, not_empty: bool, compatible_only: bool) -> bool {
+        let address = self.master_address.to_string();
+        let Some(master) = resolve_master(&address) else {
+            net_log!(Error, Client, "Failed to resolve master server '{}'", address);
+            return false;
+        };
+
+        if self.client.as_ref().is_none_or(|(current, _)| *current != master) {
+            match ServerListClient::new(master) {
+                Ok(client) => self.client = Some((master, client)),
+                Err(e) => {
+                    net_log!(Error, Client, "Failed to open server list socket: {}", e);
+                    return false;
+                }
+            }
+        }
+        let Some((_, client)) = self.client.as_mut() else {
+            return false;
+        };
+
+        let filter = ServerFilter {
+            protocol_version: compatible_only.then_some(PROTOCOL_VERSION),
+            map: (!map.is_empty()).then_some(map),
+            name_contains: (!name.is_empty()).then_some(name),
+            not_full,
+            not_empty,
+        };
+
+        if let Err(e) = client.query(filter, Instant::now()) {
+            net_log!(Error, Client, "Failed to query master server {}: {}", master, e);
+            return false;
+        }
+        true
+    }
+
+    #[func]
+    fn is_refreshing(&self) -> bool {
+        self.client.as_ref().is_some_and(|(_, client)| client.is_querying())
+    }
+}
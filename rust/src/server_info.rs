@@ -0,0 +1,98 @@
+use br_core::discovery::Beacon;
+use br_core::packet::PROTOCOL_VERSION;
+use br_core::server_query::ServerStatus;
+use godot::prelude::*;
+use std::net::SocketAddr;
+
+/// A joinable server found on the LAN or through the master server list.
+#[derive(GodotClass)]
+#[class(no_init, base=RefCounted)]
+pub(crate) struct ServerInfo {
+    base: Base<RefCounted>,
+    #[var]
+    address: GString,
+    #[var]
+    port: i64,
+    #[var]
+    name: GString,
+    #[var]
+    map: GString,
+    #[var]
+    player_count: i64,
+    #[var]
+    capacity: i64,
+    #[var]
+    protocol_version: i64,
+    /// Whether this build can join it.
+    #[var]
+    compatible: bool,
+}
+
+impl ServerInfo {
+    pub(crate) fn from_beacon(addr: SocketAddr, beacon: &Beacon) -> Gd<Self> {
+        Gd::from_init_fn(|base| Self {
+            base,
+            address: addr.ip().to_string().as_str().into(),
+            port: i64::from(addr.port()),
+            name: beacon.name.as_str().into(),
+            map: beacon.map.as_str().into(),
+            player_count: i64::from(beacon.player_count),
+            capacity: i64::from(beacon.capacity),
+            protocol_version: i64::from(beacon.protocol_version),
+            compatible: beacon.protocol_version == PROTOCOL_VERSION,
+        })
+    }
+}
+
+/// A server's answer to a `ServerQuery`.
+#[derive(GodotClass)]
+#[class(no_init, base=RefCounted)]
+pub(crate) struct ServerStatusInfo {
+    base: Base<RefCounted>,
+    #[var]
+    address: GString,
+    /// The query port that answered, not the game port.
+    #[var]
+    query_port: i64,
+    #[var]
+    name: GString,
+    #[var]
+    map: GString,
+    #[var]
+    phase: GString,
+    #[var]
+    player_count: i64,
+    #[var]
+    capacity: i64,
+    /// May be shorter than `player_count` on very full servers.
+    #[var]
+    player_ids: PackedInt64Array,
+    #[var]
+    player_names: PackedStringArray,
+    #[var]
+    protocol_version: i64,
+    #[var]
+    compatible: bool,
+    #[var]
+    ping_ms: i64,
+}
+
+impl ServerStatusInfo {
+    pub(crate) fn from_status(addr: SocketAddr, status: &ServerStatus, ping_ms: u32) -> Gd<Self> {
+        Gd::from_init_fn(|base| Self {
+            base,
+            address: addr.ip().to_string().as_str().into(),
+            query_port: i64::from(addr.port()),
+            name: status.name.as_str().into(),
+            map: status.map.as_str().into(),
+            phase: status.phase.as_str().into(),
+            player_count: i64::from(status.player_count),
+            capacity: i64::from(status.capacity),
+            player_ids: status.players.iter().map(|player| i64::from(player.id)).collect(),
+            player_names: status.players.iter().map(|player| GString::from(player.name.as_str())).collect(),
+            protocol_version: i64::from(status.protocol_version),
+            compatible: status.protocol_version == PROTOCOL_VERSION,
+            ping_ms: i64::from(ping_ms),
+        })
+    }
+}
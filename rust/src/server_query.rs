@@ -0,0 +1,86 @@
+use crate::server_info::ServerStatusInfo;
+use br_core::net_log;
+use br_core::server_query::{DEFAULT_QUERY_PORT, QueryOutcome, ServerQueryClient};
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use std::{net::ToSocketAddrs, time::Instant};
+
+/// Asks servers for their status without joining them, e.g. to show player
+/// lists and pings in a server browser. Any number of queries may be in
+/// flight at once.
+#[derive(GodotClass)]
+#[class(base=Node)]
+struct ServerQuery {
+    base: Base<Node>,
+    client: Option<ServerQueryClient>,
+}
+
+#[godot_api]
+impl INode for ServerQuery {
+    fn init(base: Base<Node>) -> Self {
+        Self { base, client: None }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        for (addr, outcome) in client.poll(Instant::now()) {
+            match outcome {
+                QueryOutcome::Status { status, ping_ms } => {
+                    let info = ServerStatusInfo::from_status(addr, &status, ping_ms);
+                    self.signals().on_server_status().emit(&info);
+                }
+                QueryOutcome::TimedOut => {
+                    let address = addr.ip().to_string();
+                    self.signals().on_query_failed().emit(address.as_str(), i64::from(addr.port()));
+                }
+            }
+        }
+    }
+}
+
+#[godot_api]
+impl ServerQuery {
+    #[signal]
+    fn on_server_status(info: Gd<ServerStatusInfo>);
+    /// No answer from `address`:`query_port` in time.
+    #[signal]
+    fn on_query_failed(address: GString, query_port: i64);
+
+    /// Queries the server at `address`; `query_port` 0 uses the default.
+    /// The answer arrives as `on_server_status` or `on_query_failed`.
+    #[func]
+    fn query(&mut self, address: String, query_port: i64) -> bool {
+        let port = if query_port == 0 { Ok(DEFAULT_QUERY_PORT) } else { u16::try_from(query_port) };
+        let Some(addr) = port.ok().and_then(|port| (address.as_str(), port).to_socket_addrs().ok()?.next()) else {
+            net_log!(Error, Client, "Failed to resolve query address '{}:{}'", address, query_port);
+            return false;
+        };
+
+        if self.client.is_none() {
+            match ServerQueryClient::new() {
+                Ok(client) => self.client = Some(client),
+                Err(e) => {
+                    net_log!(Error, Client, "Failed to open query socket: {}", e);
+                    return false;
+                }
+            }
+        }
+        let Some(client) = self.client.as_mut() else {
+            return false;
+        };
+
+        if let Err(e) = client.query(addr, Instant::now()) {
+            net_log!(Error, Client, "Failed to query {}: {}", addr, e);
+            return false;
+        }
+        true
+    }
+
+    /// Queries still waiting for an answer.
+    #[func]
+    fn pending_queries(&self) -> i64 {
+        self.client.as_ref().map_or(0, |client| client.pending() as i64)
+    }
+}
@@ -0,0 +1,75 @@
+use crate::gns_transport::GnsTransport;
+use br_core::transport::{
+    LoopbackTransport, MultiTransport, Transport, TransportError, UdpTransport, WebSocketTransport,
+};
+use std::{net::SocketAddr, str::FromStr};
+
+/// Backends `NetworkDriver` can run on, picked with `set_transport`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TransportKind {
+    Gns,
+    /// Plain UDP with our own reliability, for when the GNS library isn't available.
+    Udp,
+    /// Binary WebSocket messages over TCP, so web exports and browser tools can join.
+    WebSocket,
+    /// In-process channels, for single player and tests.
+    Loopback,
+}
+
+impl TransportKind {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            TransportKind::Gns => "gns",
+            TransportKind::Udp => "udp",
+            TransportKind::WebSocket => "websocket",
+            TransportKind::Loopback => "loopback",
+        }
+    }
+
+    pub(crate) fn listen(self, addr: SocketAddr) -> Result<Box<dyn Transport>, TransportError> {
+        Ok(match self {
+            TransportKind::Gns => Box::new(GnsTransport::listen(addr)?),
+            TransportKind::Udp => Box::new(UdpTransport::listen(addr)?),
+            TransportKind::WebSocket => Box::new(WebSocketTransport::listen(addr)?),
+            TransportKind::Loopback => Box::new(LoopbackTransport::listen(addr)?),
+        })
+    }
+
+    /// Like `listen`, but also takes WebSocket clients on `websocket_addr`
+    /// unless its port is 0 or this already is the WebSocket backend.
+    pub(crate) fn listen_with_websocket(
+        self,
+        addr: SocketAddr,
+        websocket_addr: SocketAddr,
+    ) -> Result<Box<dyn Transport>, TransportError> {
+        let primary = self.listen(addr)?;
+        if websocket_addr.port() == 0 || self == TransportKind::WebSocket {
+            return Ok(primary);
+        }
+        let websocket = WebSocketTransport::listen(websocket_addr)?;
+        Ok(Box::new(MultiTransport::new(vec![primary, Box::new(websocket)])))
+    }
+
+    pub(crate) fn connect(self, addr: SocketAddr) -> Result<Box<dyn Transport>, TransportError> {
+        Ok(match self {
+            TransportKind::Gns => Box::new(GnsTransport::connect(addr)?),
+            TransportKind::Udp => Box::new(UdpTransport::connect(addr)?),
+            TransportKind::WebSocket => Box::new(WebSocketTransport::connect(addr)?),
+            TransportKind::Loopback => Box::new(LoopbackTransport::connect(addr)?),
+        })
+    }
+}
+
+impl FromStr for TransportKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "gns" => Ok(TransportKind::Gns),
+            "udp" => Ok(TransportKind::Udp),
+            "websocket" | "ws" => Ok(TransportKind::WebSocket),
+            "loopback" => Ok(TransportKind::Loopback),
+            other => Err(format!("unknown transport '{other}', expected gns, udp, websocket or loopback")),
+        }
+    }
+}